@@ -0,0 +1,297 @@
+// Console commands of the app. See common::console for the registry.
+
+use crate::parameters::Parameters;
+use crate::MainState;
+use arrayvec::{ArrayString, ArrayVec};
+use common::candump::parse_cansend;
+use common::command_accumulator::Completer;
+use common::console::{self, CommandResult, ConsoleCommand, InvalidArguments};
+use common::{HardwareInterface, NameLookupError, Parameter, ParameterQuality};
+use core::fmt::Write;
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+pub type ConsoleCommands = console::ConsoleCommands<MainState, 16>;
+
+// Create this next to MainState and pass the received lines to dispatch()
+pub fn new_console_commands() -> ConsoleCommands {
+    let mut commands = ConsoleCommands::new();
+    commands.register_all(&console::hardware_commands());
+    commands.register_all(&APP_COMMANDS);
+    commands
+}
+
+// Adds parameter names for <name> arguments. Create this when passing a
+// character to CommandAccumulator::put_with_completion().
+pub struct ConsoleCompleter<'a> {
+    pub commands: &'a ConsoleCommands,
+    pub params: &'a Parameters,
+}
+
+impl Completer for ConsoleCompleter<'_> {
+    fn complete(&self, words: &[&str], add: &mut dyn FnMut(&str)) {
+        self.commands.complete(words, add);
+        if let [name, args @ ..] = words {
+            let spec = self.commands.get(name).and_then(|command| command.arg_spec(args.len()));
+            if spec.map(console::arg_spec_name) == Some("name") {
+                for param in self.params.iter() {
+                    add(param.name);
+                }
+            }
+        }
+    }
+}
+
+const APP_COMMANDS: [ConsoleCommand<MainState>; 5] = [
+    ConsoleCommand {
+        name: "params",
+        args: "",
+        help: "List parameters\n\
+                The list is printed a few lines at a time; press any key to stop.",
+        handler: params_command,
+    },
+    ConsoleCommand {
+        name: "get",
+        args: "<name>",
+        help: "Show a parameter\n\
+                Names are matched ignoring case and spaces. Quote names that\n\
+                contain spaces, e.g. get \"Aux battery\".",
+        handler: get_command,
+    },
+    ConsoleCommand {
+        name: "set",
+        args: "<name> <value>",
+        help: "Set a writable parameter\n\
+                Bool values can also be given as true/false or on/off, and enum\n\
+                values by their label.",
+        handler: set_command,
+    },
+    ConsoleCommand {
+        name: "watch",
+        args: "<name...> [interval_ms]",
+        help: "Show parameters until a key is pressed\n\
+                The default interval is 1000ms.",
+        handler: watch_command,
+    },
+    ConsoleCommand {
+        name: "can",
+        args: "send|periodic|stop [frame] [interval_ms]",
+        help: "Send CAN frames\n\
+                can send <frame> sends once, can periodic <frame> <interval_ms>\n\
+                repeats until can stop. Frames are in cansend syntax: 123#DEADBEEF,\n\
+                12345678#11.22 (extended ID), 123#R or 123#R8 (remote frame).",
+        handler: can_command,
+    },
+];
+
+// Repeated by "can periodic"
+pub(crate) struct PeriodicCanFrame {
+    frame: bxcan::Frame,
+    interval_ms: u64,
+    last_send_millis: u64,
+}
+
+// A few lines per update, so that the list fits in the log buffers of the
+// firmware
+pub(crate) const PARAMS_LINES_PER_UPDATE: usize = 2;
+
+fn params_command(state: &mut MainState, _: &[&str], _: &mut dyn HardwareInterface)
+        -> CommandResult {
+    info!("  {: <28} {: >16} {: <4} {: >8}", "Name", "Value", "Unit", "Age");
+    state.console_params_next = Some(0);
+    Ok(())
+}
+
+// Tells the user why the name didn't match
+fn find_parameter<'p>(params: &'p Parameters, name: &str) -> Option<&'p Parameter<'static>> {
+    match params.get_parameter_by_name(name) {
+        Ok(param) => Some(param),
+        Err(NameLookupError::NotFound) => {
+            info!("-> No parameter {:?}", name);
+            None
+        }
+        Err(NameLookupError::Ambiguous) => {
+            info!("-> Several parameters are called {:?}; use the identifier", name);
+            None
+        }
+    }
+}
+
+fn get_command(state: &mut MainState, args: &[&str], hw: &mut dyn HardwareInterface)
+        -> CommandResult {
+    if let Some(param) = find_parameter(&state.params, args[0]) {
+        log_parameter(param, hw.millis());
+    }
+    Ok(())
+}
+
+fn set_command(state: &mut MainState, args: &[&str], hw: &mut dyn HardwareInterface)
+        -> CommandResult {
+    let (name, value) = (args[0], args[1]);
+    let Some(param) = find_parameter(&state.params, name) else {
+        return Ok(());
+    };
+    if !param.writable {
+        info!("-> {} is not writable", param.name);
+        return Ok(());
+    }
+    let Some(value) = param.value_type.parse(value) else {
+        info!("-> Invalid value {:?} for {}", value, param.name);
+        return Ok(());
+    };
+    let id = param.id;
+    state.params[id].set_value(value, hw.millis());
+    log_parameter(&state.params[id], hw.millis());
+    Ok(())
+}
+
+fn watch_command(state: &mut MainState, args: &[&str], hw: &mut dyn HardwareInterface)
+        -> CommandResult {
+    let mut names = args;
+    let mut interval_ms = 1000;
+    if let Some((last, rest)) = args.split_last() {
+        if let Ok(interval) = last.parse::<u64>() {
+            interval_ms = interval.max(20);
+            names = rest;
+        }
+    }
+    if names.is_empty() {
+        return Err(InvalidArguments);
+    }
+    let mut watch = ArrayVec::new();
+    for &name in names {
+        let Some(param) = find_parameter(&state.params, name) else {
+            return Ok(());
+        };
+        if watch.try_push(param.id).is_err() {
+            info!("-> At most {} parameters can be watched", watch.capacity());
+            return Ok(());
+        }
+    }
+    info!("Watching; press any key to stop");
+    state.console_watch = watch;
+    state.console_watch_interval_ms = interval_ms;
+    // Show the values right away
+    state.last_console_watch_millis = hw.millis().saturating_sub(interval_ms);
+    Ok(())
+}
+
+fn can_command(state: &mut MainState, args: &[&str], hw: &mut dyn HardwareInterface)
+        -> CommandResult {
+    match *args {
+        ["send", frame] => {
+            match parse_cansend(frame) {
+                Ok(frame) => hw.send_can(frame),
+                Err(e) => info!("-> {:?}", e),
+            }
+        }
+        ["periodic", frame, interval_ms] => {
+            let interval_ms: u64 = interval_ms.parse().map_err(|_| InvalidArguments)?;
+            let frame = match parse_cansend(frame) {
+                Ok(frame) => frame,
+                Err(e) => {
+                    info!("-> {:?}", e);
+                    return Ok(());
+                }
+            };
+            let periodic = PeriodicCanFrame {
+                frame,
+                interval_ms: interval_ms.max(1),
+                // Send the first one right away
+                last_send_millis: hw.millis().saturating_sub(interval_ms),
+            };
+            if state.can_periodic.try_push(periodic).is_err() {
+                info!("-> At most {} frames can be sent periodically",
+                        state.can_periodic.capacity());
+                return Ok(());
+            }
+            info!("Sending {} frame(s) periodically", state.can_periodic.len());
+        }
+        ["stop"] => {
+            info!("Stopped {} periodic frame(s)", state.can_periodic.len());
+            state.can_periodic.clear();
+        }
+        _ => return Err(InvalidArguments),
+    }
+    Ok(())
+}
+
+impl MainState {
+    // Call this for every byte received on the console before passing it on
+    // to the command accumulator. Returns true if the byte stopped a watch or
+    // a parameter listing and should be dropped.
+    pub fn on_console_key(&mut self) -> bool {
+        if self.console_params_next.take().is_some() {
+            info!("Listing stopped");
+            return true;
+        }
+        if self.console_watch.is_empty() {
+            return false;
+        }
+        self.console_watch.clear();
+        info!("Watch stopped");
+        true
+    }
+
+    pub(crate) fn update_console_params(&mut self, millis: u64) {
+        let Some(next) = self.console_params_next else {
+            return;
+        };
+        let end = (next + PARAMS_LINES_PER_UPDATE).min(self.params.len());
+        for param in &self.params.as_slice()[next..end] {
+            log_parameter(param, millis);
+        }
+        self.console_params_next = (end < self.params.len()).then_some(end);
+    }
+
+    pub(crate) fn update_console_watch(&mut self, millis: u64) {
+        if self.console_watch.is_empty() ||
+                millis.saturating_sub(self.last_console_watch_millis) < self.console_watch_interval_ms {
+            return;
+        }
+        self.last_console_watch_millis = millis;
+        let mut line: ArrayString<200> = ArrayString::new();
+        for &i in &self.console_watch {
+            let param = &self.params[i];
+            let _ = write!(line, "{}={}{} ", param.name, format_parameter_value(param),
+                    param.unit);
+        }
+        info!("{}", line.trim_end());
+    }
+
+    pub(crate) fn update_can_periodic(&mut self, hw: &mut dyn HardwareInterface) {
+        let millis = hw.millis();
+        for periodic in &mut self.can_periodic {
+            if millis.saturating_sub(periodic.last_send_millis) >= periodic.interval_ms {
+                periodic.last_send_millis = millis;
+                hw.send_can(periodic.frame.clone());
+            }
+        }
+    }
+}
+
+// "-" if nothing has been received
+fn format_parameter_value(param: &Parameter) -> ArrayString<32> {
+    let mut text = ArrayString::new();
+    if param.value.is_nan() || param.quality == ParameterQuality::NeverReceived {
+        text.push('-');
+        return text;
+    }
+    let _ = write!(text, "{:.*}", param.decimals as usize, param.value);
+    if let Some(label) = param.value_label() {
+        let _ = write!(text, " ({})", label);
+    }
+    text
+}
+
+// One row of the "params" table
+fn log_parameter(param: &Parameter, millis: u64) {
+    let mut age: ArrayString<16> = ArrayString::new();
+    if param.quality == ParameterQuality::NeverReceived {
+        age.push('-');
+    } else {
+        let _ = write!(age, "{:.1}s", millis.saturating_sub(param.update_timestamp) as f32 / 1000.0);
+    }
+    info!("  {: <28} {: >16} {: <4} {: >8} {:?}", param.name, format_parameter_value(param),
+            param.unit, age, param.quality);
+}
@@ -9,6 +9,7 @@ const CHARGE_COMPLETE_VOLTAGE_SETTING_MV: u16 = 4160; // Should be divisible by
 use common::*;
 
 pub mod can_simulator;
+pub mod console;
 pub mod parameters;
 use parameters::*;
 
@@ -17,7 +18,7 @@ pub extern crate embedded_graphics;
 pub extern crate log;
 pub extern crate profont;
 
-use arrayvec::ArrayString;
+use arrayvec::{ArrayString, ArrayVec};
 use bxcan::StandardId;
 use embedded_graphics as eg;
 use embedded_graphics::{mono_font, pixelcolor::*, prelude::*};
@@ -27,7 +28,6 @@ use int_enum::IntEnum;
 use log::{debug, error, info, trace, warn};
 use ringbuffer::RingBuffer;
 use core::fmt::Write;
-use bitvec::prelude::*;
 
 // View definitions
 
@@ -91,6 +91,36 @@ const TEXT_STYLE_PARAMETER_VALUE: mono_font::MonoTextStyle<Rgb565> =
         .background_color(Rgb565::BLACK)
         .build();
 
+const TEXT_STYLE_PARAMETER_VALUE_STALE: mono_font::MonoTextStyle<Rgb565> =
+    mono_font::MonoTextStyleBuilder::new()
+        .font(&profont::PROFONT_24_POINT)
+        .text_color(Rgb565::CSS_GRAY)
+        .background_color(Rgb565::BLACK)
+        .build();
+
+const TEXT_STYLE_PARAMETER_VALUE_DECODE_ERROR: mono_font::MonoTextStyle<Rgb565> =
+    mono_font::MonoTextStyleBuilder::new()
+        .font(&profont::PROFONT_24_POINT)
+        .text_color(Rgb565::CSS_ORANGE)
+        .background_color(Rgb565::BLACK)
+        .build();
+
+const TEXT_STYLE_PARAMETER_VALUE_OUT_OF_RANGE: mono_font::MonoTextStyle<Rgb565> =
+    mono_font::MonoTextStyleBuilder::new()
+        .font(&profont::PROFONT_24_POINT)
+        .text_color(Rgb565::RED)
+        .background_color(Rgb565::BLACK)
+        .build();
+
+fn parameter_value_style(quality: ParameterQuality) -> mono_font::MonoTextStyle<'static, Rgb565> {
+    match quality {
+        ParameterQuality::NeverReceived | ParameterQuality::Valid => TEXT_STYLE_PARAMETER_VALUE,
+        ParameterQuality::Stale => TEXT_STYLE_PARAMETER_VALUE_STALE,
+        ParameterQuality::DecodeError => TEXT_STYLE_PARAMETER_VALUE_DECODE_ERROR,
+        ParameterQuality::OutOfRange => TEXT_STYLE_PARAMETER_VALUE_OUT_OF_RANGE,
+    }
+}
+
 const TEXT_STYLE_BUTTON_ACTION: mono_font::MonoTextStyle<Rgb565> =
     mono_font::MonoTextStyleBuilder::new()
         .font(&profont::PROFONT_18_POINT)
@@ -141,6 +171,7 @@ pub fn draw_view_number(view_i: usize, hw: &mut dyn HardwareInterface) {
 pub fn draw_parameter_text(
     display_name: &str,
     text: &str,
+    quality: ParameterQuality,
     unit: &str,
     y: i32,
     redraw: bool,
@@ -163,7 +194,7 @@ pub fn draw_parameter_text(
     hw.display_draw_text(
         &text,
         Point::new(255, y),
-        TEXT_STYLE_PARAMETER_VALUE,
+        parameter_value_style(quality),
         eg::text::Alignment::Right,
     );
 }
@@ -171,8 +202,10 @@ pub fn draw_parameter_text(
 pub fn draw_parameter_dual_text(
     display_name: &str,
     text1: &str,
+    quality1: ParameterQuality,
     unit1: &str,
     text2: &str,
+    quality2: ParameterQuality,
     unit2: &str,
     y: i32,
     redraw: bool,
@@ -201,72 +234,82 @@ pub fn draw_parameter_dual_text(
     hw.display_draw_text(
         &text1,
         Point::new(155, y),
-        TEXT_STYLE_PARAMETER_VALUE,
+        parameter_value_style(quality1),
         eg::text::Alignment::Right,
     );
     hw.display_draw_text(
         &text2,
         Point::new(255, y),
-        TEXT_STYLE_PARAMETER_VALUE,
+        parameter_value_style(quality2),
         eg::text::Alignment::Right,
     );
 }
 
 pub fn draw_parameter_raw(
     display_name: &str,
-    value: f32,
+    value: ParameterValue,
     decimals: usize,
+    quality: ParameterQuality,
     unit: &str,
     y: i32,
     redraw: bool,
     hw: &mut dyn HardwareInterface,
 ) {
     let mut text: ArrayString<10> = ArrayString::new();
-    if value.is_nan() {
+    if value.is_nan() || quality == ParameterQuality::NeverReceived {
         text.push_str("     -");
     } else {
         text.push_str(&str_format!(fixedstr::str16, "{: >6.*}", decimals, value));
     }
 
-    draw_parameter_text(display_name, &text, unit, y, redraw, hw);
+    draw_parameter_text(display_name, &text, quality, unit, y, redraw, hw);
 }
 
 pub fn draw_parameter_dual_raw(
     display_name: &str,
-    value1: f32,
+    value1: ParameterValue,
     decimals1: usize,
+    quality1: ParameterQuality,
     unit1: &str,
-    value2: f32,
+    value2: ParameterValue,
     decimals2: usize,
+    quality2: ParameterQuality,
     unit2: &str,
     y: i32,
     redraw: bool,
     hw: &mut dyn HardwareInterface,
 ) {
     let mut text1: ArrayString<10> = ArrayString::new();
-    if value1.is_nan() {
+    if value1.is_nan() || quality1 == ParameterQuality::NeverReceived {
         text1.push_str("   -");
     } else {
         text1.push_str(&str_format!(fixedstr::str16, "{: >4.*}", decimals1, value1));
     }
 
     let mut text2: ArrayString<10> = ArrayString::new();
-    if value2.is_nan() {
+    if value2.is_nan() || quality2 == ParameterQuality::NeverReceived {
         text2.push_str("   -");
     } else {
         text2.push_str(&str_format!(fixedstr::str16, "{: >4.*}", decimals2, value2));
     }
 
-    draw_parameter_dual_text(display_name, &text1, unit1, &text2, unit2, y, redraw, hw);
+    draw_parameter_dual_text(display_name, &text1, quality1, unit1, &text2, quality2, unit2,
+            y, redraw, hw);
 }
 
-pub fn draw_parameter(id: ParameterId, y: i32, redraw: bool, hw: &mut dyn HardwareInterface) {
-    let param = get_parameter(id);
+pub fn draw_parameter(params: &Parameters, id: ParameterId, y: i32, redraw: bool, hw: &mut dyn HardwareInterface) {
+    let param = &params[id];
+
+    if let Some(label) = param.value_label() {
+        draw_parameter_text(param.display_name, label, param.quality, param.unit, y, redraw, hw);
+        return;
+    }
 
     draw_parameter_raw(
         param.display_name,
         param.value,
         param.decimals as usize,
+        param.quality,
         param.unit,
         y,
         redraw,
@@ -275,6 +318,7 @@ pub fn draw_parameter(id: ParameterId, y: i32, redraw: bool, hw: &mut dyn Hardwa
 }
 
 pub fn draw_parameter_dual_custom_midstring(
+    params: &Parameters,
     display_name: &str,
     id1: ParameterId,
     midstring: &str,
@@ -283,16 +327,18 @@ pub fn draw_parameter_dual_custom_midstring(
     redraw: bool,
     hw: &mut dyn HardwareInterface,
 ) {
-    let param1 = get_parameter(id1);
-    let param2 = get_parameter(id2);
+    let param1 = &params[id1];
+    let param2 = &params[id2];
 
     draw_parameter_dual_raw(
         display_name,
         param1.value,
         param1.decimals as usize,
+        param1.quality,
         midstring,
         param2.value,
         param2.decimals as usize,
+        param2.quality,
         param2.unit,
         y,
         redraw,
@@ -301,6 +347,7 @@ pub fn draw_parameter_dual_custom_midstring(
 }
 
 pub fn draw_parameter_dual(
+    params: &Parameters,
     display_name: &str,
     id1: ParameterId,
     id2: ParameterId,
@@ -308,16 +355,18 @@ pub fn draw_parameter_dual(
     redraw: bool,
     hw: &mut dyn HardwareInterface,
 ) {
-    let param1 = get_parameter(id1);
-    let param2 = get_parameter(id2);
+    let param1 = &params[id1];
+    let param2 = &params[id2];
 
     draw_parameter_dual_raw(
         display_name,
         param1.value,
         param1.decimals as usize,
+        param1.quality,
         param1.unit,
         param2.value,
         param2.decimals as usize,
+        param2.quality,
         param2.unit,
         y,
         redraw,
@@ -367,60 +416,60 @@ impl Warning {
     }
 }
 
-fn generate_warning(hw: &mut dyn HardwareInterface) -> Warning {
-    if get_parameter(ParameterId::InverterT).value >= 60.0 {
+fn generate_warning(params: &Parameters, hw: &mut dyn HardwareInterface) -> Warning {
+    if params[ParameterId::InverterT].current_value() >= 60.0 {
         Warning::InverterHot
-    } else if get_parameter(ParameterId::MotorT).value >= 60.0 {
+    } else if params[ParameterId::MotorT].current_value() >= 60.0 {
         Warning::MotorHot
-    } else if get_parameter(ParameterId::BatteryTMax).value >= 50.0 {
+    } else if params[ParameterId::BatteryTMax].current_value() >= 50.0 {
         Warning::BatteryHot
-    } else if get_parameter(ParameterId::BatteryVMin).value <= 3.0 {
+    } else if params[ParameterId::BatteryVMin].current_value() <= 3.0 {
         Warning::BatteryCriticallyLow
-    } else if get_parameter(ParameterId::BatteryVMax).value >= 4.20 {
+    } else if params[ParameterId::BatteryVMax].current_value() >= 4.20 {
         Warning::BatteryCriticallyHigh
-    } else if get_parameter(ParameterId::AuxVoltage).value <= 11.5 {
+    } else if params[ParameterId::AuxVoltage].current_value() <= 11.5 {
         Warning::AuxVoltageLow
-    } else if get_parameter(ParameterId::HeaterT).value >= 100.0 {
+    } else if params[ParameterId::HeaterT].current_value() >= 100.0 {
         Warning::HeaterOverTemperature
-    } else if get_parameter(ParameterId::PrechargeFailed).value >= 0.5 {
+    } else if params[ParameterId::PrechargeFailed].current_bool() == Some(true) {
         Warning::PrechargeFailed
-    } else if get_parameter(ParameterId::MainContactor).value >= 0.5 &&
-            (get_parameter(ParameterId::ObcDcv).value < 150.0 ||
-                get_parameter(ParameterId::ObcDcv).value > 400.0) {
+    } else if params[ParameterId::MainContactor].current_bool() == Some(true) &&
+            (params[ParameterId::ObcDcv].current_value() < 150.0 ||
+                params[ParameterId::ObcDcv].current_value() > 400.0) {
         Warning::ObcDcvMismatch
-    } else if (get_parameter(ParameterId::FoccciCPPWM).value -
-            get_parameter(ParameterId::ObcEvsePwm).value).abs() > 2.0 {
+    } else if (params[ParameterId::FoccciCPPWM].current_value() -
+            params[ParameterId::ObcEvsePwm].current_value()).abs() > 2.0 {
         Warning::ObcCpMismatch
-    } else if get_parameter(ParameterId::MainContactor).value >= 0.5 &&
-            get_parameter(ParameterId::DcdcStatus).value != 0x22 as f32 {
+    } else if params[ParameterId::MainContactor].current_bool() == Some(true) &&
+            params[ParameterId::DcdcStatus].current() != Some(ParameterValue::Enum(0x22)) {
         Warning::DcdcDown
-    } else if (get_parameter(ParameterId::DcdcAuxVoltage).value -
-            get_parameter(ParameterId::AuxVoltage).value).abs() > 1.0 {
+    } else if (params[ParameterId::DcdcAuxVoltage].current_value() -
+            params[ParameterId::AuxVoltage].current_value()).abs() > 1.0 {
         Warning::DcdcAuxMismatch
-    } else if get_parameter(ParameterId::IpdmPcbT).value > 60.0 {
+    } else if params[ParameterId::IpdmPcbT].current_value() > 60.0 {
         Warning::IpdmHot
-    } else if get_parameter(ParameterId::IpdmReqMC).value > 0.5 &&
-            get_parameter(ParameterId::MainContactor).value < 0.5 &&
-            get_parameter(ParameterId::Precharging).value < 0.5 {
+    } else if params[ParameterId::IpdmReqMC].current_bool() == Some(true) &&
+            params[ParameterId::MainContactor].current_bool() == Some(false) &&
+            params[ParameterId::Precharging].current_bool() == Some(false) {
         Warning::IpdmMcReqFail
-    } else if get_parameter(ParameterId::IpdmGroup1OC).value > 0.5 {
+    } else if params[ParameterId::IpdmGroup1OC].current_bool() == Some(true) {
         Warning::IpdmGroup1OC
-    } else if get_parameter(ParameterId::IpdmGroup2OC).value > 0.5 {
+    } else if params[ParameterId::IpdmGroup2OC].current_bool() == Some(true) {
         Warning::IpdmGroup2OC
-    } else if get_parameter(ParameterId::IpdmGroup3OC).value > 0.5 {
+    } else if params[ParameterId::IpdmGroup3OC].current_bool() == Some(true) {
         Warning::IpdmGroup3OC
-    } else if get_parameter(ParameterId::IpdmGroup4OC).value > 0.5 {
+    } else if params[ParameterId::IpdmGroup4OC].current_bool() == Some(true) {
         Warning::IpdmGroup4OC
-    } else if get_parameter(ParameterId::IpdmCurrent1).value > 6.0 {
+    } else if params[ParameterId::IpdmCurrent1].current_value() > 6.0 {
         Warning::IpdmGroup1HighCurrent
-    } else if get_parameter(ParameterId::IpdmCurrent2).value > 6.0 {
+    } else if params[ParameterId::IpdmCurrent2].current_value() > 6.0 {
         Warning::IpdmGroup2HighCurrent
-    } else if get_parameter(ParameterId::IpdmCurrent3).value > 6.0 {
+    } else if params[ParameterId::IpdmCurrent3].current_value() > 6.0 {
         Warning::IpdmGroup3HighCurrent
-    } else if get_parameter(ParameterId::IpdmCurrent4).value > 6.0 {
+    } else if params[ParameterId::IpdmCurrent4].current_value() > 6.0 {
         Warning::IpdmGroup4HighCurrent
-    } else if get_parameter(ParameterId::MainContactor).value >= 0.5 &&
-            get_parameter(ParameterId::DcdcCurrent).value < 0.2 as f32 {
+    } else if params[ParameterId::MainContactor].current_bool() == Some(true) &&
+            params[ParameterId::DcdcCurrent].current_value() < 0.2 as f32 {
         Warning::DcdcZeroCurrent
     } else if hw.millis() < 2500 {
         Warning::Test
@@ -429,21 +478,18 @@ fn generate_warning(hw: &mut dyn HardwareInterface) -> Warning {
     }
 }
 
-static mut main_view_drawn_cruise_requested: f32 = f32::NAN;
-static mut main_view_drawn_cruise_active: f32 = f32::NAN;
 static mut main_view_drawn_warning: Warning = Warning::None;
 
 static main_view: View = View {
     on_update: |redraw0: bool, state: &mut MainState, hw: &mut dyn HardwareInterface| {
         let mut redraw = redraw0;
 
-        let cruise_changed = unsafe {
-            main_view_drawn_cruise_requested !=
-                get_parameter(ParameterId::CruiseRequested).value ||
-            main_view_drawn_cruise_active !=
-                get_parameter(ParameterId::CruiseActive).value };
+        let cruise_changed =
+            state.params[ParameterId::CruiseRequested].changed_since(state.main_view_sequence) ||
+            state.params[ParameterId::CruiseActive].changed_since(state.main_view_sequence);
+        state.main_view_sequence = state.params.sequence();
 
-        let warning = generate_warning(hw);
+        let warning = generate_warning(&state.params, hw);
 
         let warning_changed = unsafe { main_view_drawn_warning != warning };
 
@@ -458,16 +504,10 @@ static main_view: View = View {
         }
 
         if redraw || cruise_changed {
-            unsafe {
-                main_view_drawn_cruise_requested =
-                        get_parameter(ParameterId::CruiseRequested).value;
-                main_view_drawn_cruise_active =
-                        get_parameter(ParameterId::CruiseActive).value;
-            };
             draw_button_action(1,
-                if get_parameter(ParameterId::CruiseRequested).value > 0.5 {
-                    if get_parameter(ParameterId::CruiseActive).value ==
-                            get_parameter(ParameterId::CruiseRequested).value {
+                if state.params[ParameterId::CruiseRequested].value.as_bool() {
+                    if state.params[ParameterId::CruiseActive].value ==
+                            state.params[ParameterId::CruiseRequested].value {
                         "Cruis"
                     } else {
                         "Crui?"
@@ -475,8 +515,8 @@ static main_view: View = View {
                 } else {
                     "Cruis"
                 },
-                get_parameter(ParameterId::CruiseActive).value > 0.5 ||
-                        get_parameter(ParameterId::CruiseRequested).value > 0.5,
+                state.params[ParameterId::CruiseActive].value.as_bool() ||
+                        state.params[ParameterId::CruiseRequested].value.as_bool(),
                 hw);
         }
 
@@ -484,14 +524,15 @@ static main_view: View = View {
             //draw_button_action(1, "BHeat", true, hw);
             {
                 let mut text: ArrayString<10> = ArrayString::new();
-                text.push_str(&str_format!(fixedstr::str16, "{: >2.*}A", 0, get_parameter(ParameterId::AcChargeCurrentSetting).value));
-                draw_button_action(2, &text, get_parameter(ParameterId::AcChargeCurrentSetting).value >= 13.0, hw);
+                text.push_str(&str_format!(fixedstr::str16, "{: >2.*}A", 0, state.params[ParameterId::AcChargeCurrentSetting].value));
+                draw_button_action(2, &text, state.params[ParameterId::AcChargeCurrentSetting].value.as_f32() >= 13.0, hw);
             }
             draw_button_action(3, "<", false, hw);
             draw_button_action(4, ">", false, hw);
         }
 
         draw_parameter_dual(
+            &state.params,
             "Range",
             ParameterId::Soc,
             ParameterId::RangeKm,
@@ -500,6 +541,7 @@ static main_view: View = View {
             hw,
         );
         draw_parameter_dual_custom_midstring(
+            &state.params,
             "Battery",
             ParameterId::BatteryTMin,
             " ..",
@@ -509,6 +551,7 @@ static main_view: View = View {
             hw,
         );
         draw_parameter_dual_custom_midstring(
+            &state.params,
             "",
             ParameterId::BatteryVMin,
             "..",
@@ -518,6 +561,7 @@ static main_view: View = View {
             hw,
         );
         /*draw_parameter(
+            &state.params,
             ParameterId::AllowedChargePower,
             TEXT_TOP_ROW_Y + PARAM_ROW_HEIGHT * 2,
             redraw,
@@ -526,12 +570,14 @@ static main_view: View = View {
         /*draw_parameter_text(
             "Heat status",
             "BDE",
+            ParameterQuality::Valid,
             "",
             TEXT_TOP_ROW_Y + PARAM_ROW_HEIGHT * 3,
             redraw,
             hw,
         );*/
         draw_parameter_dual(
+            &state.params,
             "Heater",
             ParameterId::HeaterT,
             ParameterId::HeaterPowerPercent,
@@ -540,6 +586,7 @@ static main_view: View = View {
             hw,
         );
         /*draw_parameter_dual(
+            &state.params,
             "Trip",
             ParameterId::TripKm,
             ParameterId::TripConsumption,
@@ -548,12 +595,14 @@ static main_view: View = View {
             hw,
         );*/
         draw_parameter(
+            &state.params,
             ParameterId::CabinT,
             TEXT_TOP_ROW_Y + PARAM_ROW_HEIGHT * 4,
             redraw,
             hw,
         );
         /*draw_parameter_dual(
+            &state.params,
             "Recent",
             ParameterId::RecentKm,
             ParameterId::RecentConsumption,
@@ -562,18 +611,21 @@ static main_view: View = View {
             hw,
         );*/
         draw_parameter(
+            &state.params,
             ParameterId::ChargePower,
             TEXT_TOP_ROW_Y + PARAM_ROW_HEIGHT * 5,
             redraw,
             hw,
         );
         /*draw_parameter(
+            &state.params,
             ParameterId::TicksMs,
             TEXT_TOP_ROW_Y + PARAM_ROW_HEIGHT * 6,
             redraw,
             hw,
         );*/
         draw_parameter_dual(
+            &state.params,
             "OBC",
             ParameterId::ObcDcv,
             ParameterId::ObcDcc,
@@ -584,6 +636,7 @@ static main_view: View = View {
 
         if warning == Warning::None {
             draw_parameter(
+                &state.params,
                 ParameterId::AuxVoltage,
                 TEXT_TOP_ROW_Y + PARAM_ROW_HEIGHT * 7,
                 redraw,
@@ -614,14 +667,14 @@ static main_view: View = View {
                 return true;
             }
             ButtonEvent::ButtonPress(Button::Button2) => {
-                if get_parameter(ParameterId::CruiseRequested).value < 0.5 {
-                    get_parameter(ParameterId::CruiseRequested).value = 1.0;
+                if !state.params[ParameterId::CruiseRequested].value.as_bool() {
+                    state.params[ParameterId::CruiseRequested].set_value(true, hw.millis());
                     hw.send_can(bxcan::Frame::new_data(
                         bxcan::StandardId::new(0x320).unwrap(),
                         bxcan::Data::new(b"\x02\x00\x00\x00\x01\x00\x00\x00").unwrap()
                     ));
                 } else {
-                    get_parameter(ParameterId::CruiseRequested).value = 0.0;
+                    state.params[ParameterId::CruiseRequested].set_value(false, hw.millis());
                     hw.send_can(bxcan::Frame::new_data(
                         bxcan::StandardId::new(0x320).unwrap(),
                         bxcan::Data::new(b"\x02\x00\x00\x00\x00\x00\x00\x00").unwrap()
@@ -630,10 +683,10 @@ static main_view: View = View {
                 return true;
             }
             ButtonEvent::ButtonPress(Button::Button3) => {
-                if get_parameter(ParameterId::AcChargeCurrentSetting).value < 13.0 {
-                    get_parameter(ParameterId::AcChargeCurrentSetting).value = 16.0;
+                if state.params[ParameterId::AcChargeCurrentSetting].value.as_f32() < 13.0 {
+                    state.params[ParameterId::AcChargeCurrentSetting].set_value(16.0, hw.millis());
                 } else {
-                    get_parameter(ParameterId::AcChargeCurrentSetting).value = 10.0;
+                    state.params[ParameterId::AcChargeCurrentSetting].set_value(10.0, hw.millis());
                 }
                 return true;
             }
@@ -665,6 +718,7 @@ fn draw_all_params_view_fg(redraw: bool, state: &mut MainState, hw: &mut dyn Har
         let i1 = state.all_params_view_page * PARAMS_PER_PAGE + i;
         if let Some(id) = ParameterId::from_usize(i1) {
             draw_parameter(
+                &state.params,
                 ParameterId::from_usize(i1).unwrap(),
                 TEXT_TOP_ROW_Y + PARAM_ROW_HEIGHT * i as i32,
                 redraw,
@@ -696,7 +750,7 @@ static all_params_view: View = View {
                 return false;
             }
             ButtonEvent::ButtonPress(Button::Button3) => {
-                if state.all_params_view_page < get_parameters().len() / PARAMS_PER_PAGE {
+                if state.all_params_view_page < state.params.len() / PARAMS_PER_PAGE {
                     state.all_params_view_page += 1;
                     return true;
                 }
@@ -807,35 +861,50 @@ pub struct MainState {
     log_display: LogDisplay,
     mainboard_log_display: LogDisplay,
     current_view: usize,
-    log_can: bool,
     all_params_view_page: usize,
+    main_view_sequence: u32, // Parameter changes drawn up to this
     last_millis: u64,
     dt_ms: u64,
-    last_can_500ms: u64,
+    tx_can_scheduler: can_tx::TxCanScheduler<NUM_PARAMETERS>,
     http_process: http::HttpProcess,
     last_hvac_power_can_send_millis: u64,
     last_hvac_power_output_wanted_off_millis: u64,
     last_charge_config_millis: u64,
+    ignition_was_on: bool, // For resetting the Recent counters
+    pub params: Parameters,
+    pub history: ParameterHistories,
+    console_params_next: Option<usize>, // Next parameter listed by "params"
+    console_watch: ArrayVec<usize, 8>, // Parameters streamed by "watch"
+    console_watch_interval_ms: u64,
+    last_console_watch_millis: u64,
+    can_periodic: ArrayVec<console::PeriodicCanFrame, 4>, // Sent by "can periodic"
 }
 
 impl MainState {
     pub fn new() -> Self {
-        init_parameters();
-
+        let params = new_parameter_store();
         Self {
             update_counter: 0,
             log_display: LogDisplay::new(),
             mainboard_log_display: LogDisplay::new(),
             current_view: 0,
-            log_can: false,
             all_params_view_page: 0,
+            main_view_sequence: 0,
             last_millis: 0,
             dt_ms: 0,
-            last_can_500ms: 0,
+            tx_can_scheduler: can_tx::TxCanScheduler::new(params.as_slice()),
             http_process: http::HttpProcess::new(),
             last_hvac_power_can_send_millis: 0,
             last_hvac_power_output_wanted_off_millis: 0,
             last_charge_config_millis: 0,
+            ignition_was_on: false,
+            params,
+            history: new_parameter_history(),
+            console_params_next: None,
+            console_watch: ArrayVec::new(),
+            console_watch_interval_ms: 1000,
+            last_console_watch_millis: 0,
+            can_periodic: ArrayVec::new(),
         }
     }
 
@@ -851,54 +920,69 @@ impl MainState {
 
         self.update_parameters(hw);
 
+        self.params.commit_changes();
+        self.history.update(self.params.as_slice(), millis);
+
         self.update_view(hw);
 
         self.update_hvac_power(hw);
 
         self.update_charge_config(hw);
 
-        if hw.millis() - self.last_can_500ms >= 500 {
-            self.last_can_500ms = hw.millis();
-            self.send_can_500ms(hw);
-        }
+        self.tx_can_scheduler.update(self.params.as_slice(), hw);
 
         self.update_http(hw);
 
+        self.update_console_params(millis);
+
+        self.update_console_watch(millis);
+
+        self.update_can_periodic(hw);
+
         self.last_millis = millis;
         self.update_counter += 1;
     }
 
-    fn timeout_parameters(&mut self, hw: &mut dyn HardwareInterface) {
-        for (i, param) in get_parameters().iter_mut().enumerate() {
-            if param.can_map.is_some() && !param.value.is_nan() {
-                let age_ms = hw.millis() - param.update_timestamp;
-                if age_ms >= 5000 {
-                    param.value = f32::NAN;
-                }
-            }
-        }
-    }
-
     fn update_parameters(&mut self, hw: &mut dyn HardwareInterface) {
-        get_parameter(ParameterId::TicksMs).set_value(hw.millis() as f32, hw.millis());
-        get_parameter(ParameterId::AuxVoltage).set_value(hw.get_analog_input(AnalogInput::AuxVoltage), hw.millis());
-        get_parameter(ParameterId::CabinT).set_value(hw.get_analog_input(AnalogInput::PcbT) - 12.0, hw.millis());
-        get_parameter(ParameterId::Usb1Connected).set_value(if hw.get_digital_input(DigitalInput::Usb1Connected) { 1.0 } else { 0.0 }, hw.millis());
-
-        get_parameter(ParameterId::ChargePower).set_value(
-            if get_parameter(ParameterId::CcsCurrent).value > 1.0 {
-                get_parameter(ParameterId::CcsCurrent).value *
-                        get_parameter(ParameterId::ObcDcv).value * 0.001
-            } else if get_parameter(ParameterId::ChademoCurrent).value > 1.0 {
-                get_parameter(ParameterId::ChademoCurrent).value *
-                        get_parameter(ParameterId::ObcDcv).value * 0.001
-            } else {
-                get_parameter(ParameterId::ObcDcc).value *
-                        get_parameter(ParameterId::ObcDcv).value * 0.001
-            },
-            hw.millis());
+        self.params[ParameterId::TicksMs].set_value(hw.millis() as f32, hw.millis());
+        self.params[ParameterId::AuxVoltage].set_value(hw.get_analog_input(AnalogInput::AuxVoltage), hw.millis());
+        self.params[ParameterId::CabinT].set_value(hw.get_analog_input(AnalogInput::PcbT) - 12.0, hw.millis());
+        self.params[ParameterId::Usb1Connected].set_value(hw.get_digital_input(DigitalInput::Usb1Connected), hw.millis());
+
+        self.params.update_timeouts(hw.millis());
+        self.update_trip(hw.millis());
+        self.params.update_derived(hw.millis());
+    }
 
-        self.timeout_parameters(hw);
+    // Integrates Speed and BatteryPower over dt_ms into the Trip and Recent
+    // counters. Inputs that aren't fresh are left out.
+    fn update_trip(&mut self, millis: u64) {
+        let ignition_on = self.params[ParameterId::IpdmIgnition].current_bool() == Some(true);
+        if ignition_on && !self.ignition_was_on {
+            for id in [ParameterId::RecentKm, ParameterId::RecentEnergy] {
+                // Back to unknown until the input is integrated again
+                self.params[id].set_value(f32::NAN, millis);
+                self.params[id].set_quality(ParameterQuality::NeverReceived);
+            }
+        }
+        self.ignition_was_on = ignition_on;
+
+        let hours = self.dt_ms as f32 / 3_600_000.0;
+        let counters = [
+            (ParameterId::Speed, ParameterId::TripKm, ParameterId::RecentKm, 1.0),
+            (ParameterId::BatteryPower, ParameterId::TripEnergy, ParameterId::RecentEnergy, 1000.0),
+        ];
+        for (input, trip, recent, scale) in counters {
+            let increment = self.params[input].current_value() * scale * hours;
+            if increment.is_nan() {
+                continue;
+            }
+            for id in [trip, recent] {
+                let value = self.params[id].value.as_f32();
+                let value = if value.is_nan() { 0.0 } else { value };
+                self.params[id].set_value(value + increment, millis);
+            }
+        }
     }
 
     fn update_view(&mut self, hw: &mut dyn HardwareInterface) {
@@ -906,32 +990,6 @@ impl MainState {
         ((views[self.current_view]).on_update)(self.update_counter == 0, self, hw);
     }
 
-    fn send_can_500ms(&mut self, hw: &mut dyn HardwareInterface) {
-        {
-            // Publish some stuff
-
-            let mut data = [0u8; 8];
-            let mut bits = data.view_bits_mut::<Msb0>();
-            bits[0..8].store_be(0);
-            bits[8..16].store_be(get_parameter(ParameterId::CabinT).value as i8);
-
-            self.send_normal_frame(hw, 0x404, &data);
-        }
-    }
-
-    fn send_normal_frame(&mut self, hw: &mut dyn HardwareInterface,
-            frame_id: u16, data: &[u8]) {
-        if let Some(frame_data) = bxcan::Data::new(data) {
-            hw.send_can(bxcan::Frame::new_data(
-                bxcan::StandardId::new(frame_id).unwrap(),
-                frame_data
-            ));
-        } else {
-            warn!("-!- send_normal_frame(): Invalid data for frame {:?}: {:?}",
-                    frame_id, data);
-        }
-    }
-
     fn send_setting_frame(&mut self, hw: &mut dyn HardwareInterface,
             frame_id: u16, setting_id: u8, old_value: u16, new_value: u16) {
         let mut data: [u8; 8] = [0; 8];
@@ -946,12 +1004,13 @@ impl MainState {
 
     fn update_hvac_power(&mut self, hw: &mut dyn HardwareInterface) {
         let mut wanted_output_state = false;
-        if get_parameter(ParameterId::HvacCountdown).value >= 0.0 {
-            get_parameter(ParameterId::HvacCountdown).set_value(
-                get_parameter(ParameterId::HvacCountdown).value - self.dt_ms as f32 * 0.001,
+        if self.params[ParameterId::HvacCountdown].value.as_f32() >= 0.0 {
+            let hvac_countdown = self.params[ParameterId::HvacCountdown].value.as_f32();
+            self.params[ParameterId::HvacCountdown].set_value(
+                hvac_countdown - self.dt_ms as f32 * 0.001,
                 hw.millis());
 
-            if get_parameter(ParameterId::AuxVoltage).value >= 13.4 {
+            if self.params[ParameterId::AuxVoltage].value.as_f32() >= 13.4 {
                 wanted_output_state = true;
             }
         }
@@ -977,7 +1036,7 @@ impl MainState {
             // turns on the HVAC fan and the ignition signal
             hw.set_digital_output(DigitalOutput::Pwmout1, !power_output_state); // Active low
 
-            if get_parameter(ParameterId::HvacCountdown).value > 0.0 {
+            if self.params[ParameterId::HvacCountdown].value.as_f32() > 0.0 {
                 // Request ipdm to turn on the heater and pump
                 self.send_setting_frame(hw, 0x570, 2, 0, 1);
             } else {
@@ -993,16 +1052,16 @@ impl MainState {
         }
         self.last_charge_config_millis = hw.millis();
 
-        if get_parameter(ParameterId::IpdmChargeCompleteVoltageSetting).value as u16 != CHARGE_COMPLETE_VOLTAGE_SETTING_MV {
-            self.send_setting_frame(hw, 0x570, 1, get_parameter(
-                    ParameterId::IpdmChargeCompleteVoltageSetting).value as u16 / 20,
+        if self.params[ParameterId::IpdmChargeCompleteVoltageSetting].value.as_f32() as u16 != CHARGE_COMPLETE_VOLTAGE_SETTING_MV {
+            self.send_setting_frame(hw, 0x570, 1, self.params[
+                    ParameterId::IpdmChargeCompleteVoltageSetting].value.as_f32() as u16 / 20,
                 CHARGE_COMPLETE_VOLTAGE_SETTING_MV / 20);
         }
 
-        let current_ac_charge_current_Ax5 = (get_parameter(
-                ParameterId::IpdmAcChargeCurrentSetting).value * 5.0) as u16;
-        let wanted_ac_charge_current_Ax5 = (get_parameter(
-                ParameterId::AcChargeCurrentSetting).value * 5.0) as u16;
+        let current_ac_charge_current_Ax5 = (self.params[
+                ParameterId::IpdmAcChargeCurrentSetting].value.as_f32() * 5.0) as u16;
+        let wanted_ac_charge_current_Ax5 = (self.params[
+                ParameterId::AcChargeCurrentSetting].value.as_f32() * 5.0) as u16;
 
         if current_ac_charge_current_Ax5 != wanted_ac_charge_current_Ax5 {
             self.send_setting_frame(hw, 0x570, 0,
@@ -1014,22 +1073,65 @@ impl MainState {
     fn update_http(&mut self, hw: &mut dyn HardwareInterface) {
         self.http_process.url.clear();
         self.http_process.url.push_str(base_url);
-        for param in get_parameters() {
+        // Names of the reported values that are out of range
+        let mut out_of_range: ArrayString<64> = ArrayString::new();
+        for param in self.params.iter() {
             if let Some(map) = &param.report_map {
+                if param.quality == ParameterQuality::OutOfRange &&
+                        out_of_range.remaining_capacity() > map.name.len() {
+                    if !out_of_range.is_empty() {
+                        out_of_range.push(',');
+                    }
+                    out_of_range.push_str(map.name);
+                }
+                // Missing, stale and undecodable values are reported as "NaN",
+                // "old" and "err" instead of the last value
+                let token = match param.quality {
+                    ParameterQuality::NeverReceived => Some("NaN"),
+                    ParameterQuality::Stale => Some("old"),
+                    ParameterQuality::DecodeError => Some("err"),
+                    _ => None,
+                };
+                if let Some(token) = token {
+                    self.http_process.url.push_str(&str_format!(
+                        fixedstr::str16, "{}={}&", map.name, token));
+                    continue;
+                }
+                if let Some(label) = param.value_label() {
+                    self.http_process.url.push_str(map.name);
+                    self.http_process.url.push('=');
+                    common::http::push_url_encoded(&mut self.http_process.url, label);
+                    self.http_process.url.push('&');
+                    continue;
+                }
+                // Integers are reported exactly unless they are scaled
+                let value = if map.scale == 1.0 {
+                    param.value
+                } else {
+                    ParameterValue::F32(param.value.as_f32() * map.scale)
+                };
                 self.http_process.url.push_str(&str_format!(
                     fixedstr::str16,
                     "{}={:.*}&",
                     map.name,
                     map.decimals as usize,
-                    param.value * map.scale
+                    value
                 ));
             }
         }
+        // The URL is close to full with every value present. Leave the flag out
+        // rather than overflow.
+        if !out_of_range.is_empty() &&
+                self.http_process.url.remaining_capacity() >= out_of_range.len() + 5 {
+            self.http_process.url.push_str("oor=");
+            self.http_process.url.push_str(&out_of_range);
+            self.http_process.url.push('&');
+        }
 
         match self.http_process.update(hw) {
             HttpUpdateStatus::Finished(response) => {
                 if response.body.contains("request_hvac_on") {
-                    get_parameters()[ParameterId::HvacCountdown as usize].set_value(180.0,
+                    self.params[ParameterId::HvacCountdown].set_value(180.0,
                             hw.millis());
                 }
             }
@@ -1064,34 +1166,6 @@ impl MainState {
         }
     }
 
-    pub fn on_console_command(&mut self, command: &str, hw: &mut dyn HardwareInterface) -> bool {
-        if command == "reboot" {
-            hw.reboot();
-            true
-        } else if command == "dfu" {
-            hw.activate_dfu();
-            true
-        } else if command == "panic" {
-            panic!();
-            true
-        } else if command == "log can" {
-            self.log_can = !self.log_can;
-            info!(
-                "Can logging {}",
-                if self.log_can { "enabled" } else { "disabled" }
-            );
-            true
-        } else {
-            false
-        }
-    }
-
-    pub fn list_console_commands(&self) {
-        info!("  dfu  - Activate DFU mode");
-        info!("  panic  - Call panic!()");
-        info!("  log can  - Enable logging of CAN messages on console");
-    }
-
     pub fn store_log_for_display(&mut self, buf: &str) {
         self.log_display.append(buf);
     }
@@ -1101,15 +1175,7 @@ impl MainState {
     }
 
     pub fn on_can(&mut self, frame: bxcan::Frame) {
-        if self.log_can {
-            if let bxcan::Id::Standard(id) = frame.id() {
-                if let Some(data) = frame.data() {
-                    info!("on_can: {:?}: {:?}", id, data);
-                }
-            }
-        }
-
-        update_parameters_on_can(frame, self.last_millis);
+        self.params.update_on_can(frame, self.last_millis);
     }
 
     pub fn switch_to_log_view(&mut self) {
@@ -1121,3 +1187,80 @@ impl MainState {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(state: &mut MainState, millis: u64) {
+        state.dt_ms = 1000;
+        state.update_trip(millis);
+        state.params.update_derived(millis);
+    }
+
+    // Drives for an hour at 90 km/h, taking 50 A from a 360 V pack, and then
+    // starts a new drive
+    #[test]
+    fn trip_counters_integrate_into_consumption_and_range() {
+        let mut state = MainState::new();
+        let value = |state: &MainState, id: ParameterId| state.params[id].current_value();
+        state.params[ParameterId::Soc].set_value(50.0, 0);
+        state.params[ParameterId::IpdmIgnition].set_value(true, 0);
+        state.params[ParameterId::Speed].set_value(90.0, 0);
+        state.params.update_derived(0);
+
+        // Without the pack current only the distance is known
+        for second in 1..=60 {
+            step(&mut state, second * 1000);
+        }
+        assert!((value(&state, ParameterId::TripKm) - 1.5).abs() < 0.01);
+        assert!(value(&state, ParameterId::TripEnergy).is_nan());
+        assert!(value(&state, ParameterId::TripConsumption).is_nan());
+        assert!(value(&state, ParameterId::RangeKm).is_nan());
+
+        state.params[ParameterId::TripKm].set_value(0.0, 60_000);
+        state.params[ParameterId::BatteryVoltage].set_value(360.0, 60_000);
+        state.params[ParameterId::BatteryCurrent].set_value(50.0, 60_000);
+        for second in 61..=3660 {
+            step(&mut state, second * 1000);
+        }
+        assert!((value(&state, ParameterId::TripKm) - 90.0).abs() < 0.1);
+        assert!((value(&state, ParameterId::TripEnergy) - 18000.0).abs() < 20.0);
+        assert!((value(&state, ParameterId::TripConsumption) - 200.0).abs() < 0.5);
+        assert!((value(&state, ParameterId::RecentKm) - 91.5).abs() < 0.1);
+        // 18 kWh over 91.5 km
+        assert!((value(&state, ParameterId::RecentConsumption) - 196.7).abs() < 0.5);
+        // 50% of the default 40 kWh
+        assert!((value(&state, ParameterId::RangeKm) - 101.7).abs() < 0.5);
+
+        // Turning the ignition off and on clears only the Recent counters
+        state.params[ParameterId::IpdmIgnition].set_value(false, 3_661_000);
+        step(&mut state, 3_661_000);
+        state.params[ParameterId::IpdmIgnition].set_value(true, 3_662_000);
+        step(&mut state, 3_662_000);
+        assert!(value(&state, ParameterId::TripKm) > 90.0);
+        assert!((value(&state, ParameterId::RecentKm) - 0.025).abs() < 0.001);
+        assert!((value(&state, ParameterId::RecentEnergy) - 5.0).abs() < 0.01);
+        assert!(value(&state, ParameterId::RecentConsumption).is_nan());
+        assert!(value(&state, ParameterId::RangeKm).is_nan());
+    }
+
+    #[test]
+    fn params_listing_is_spread_over_updates() {
+        let mut state = MainState::new();
+        state.console_params_next = Some(0);
+        let mut updates = 0;
+        while state.console_params_next.is_some() {
+            state.update_console_params(0);
+            updates += 1;
+        }
+        assert_eq!(updates, NUM_PARAMETERS.div_ceil(console::PARAMS_LINES_PER_UPDATE));
+        assert!(!state.on_console_key());
+
+        // A key press stops it
+        state.console_params_next = Some(0);
+        state.update_console_params(0);
+        assert!(state.on_console_key());
+        assert_eq!(state.console_params_next, None);
+    }
+}
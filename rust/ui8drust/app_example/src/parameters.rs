@@ -1,30 +1,46 @@
 use common::*;
-use bxcan::{Id, StandardId};
+
+// NaN until some distance has been driven
+fn consumption_wh_per_km(energy_wh: f32, km: f32) -> f32 {
+    if km >= 0.1 {
+        energy_wh / km
+    } else {
+        f32::NAN
+    }
+}
 
 define_parameters! {
     AuxVoltage {
         display_name: "Aux battery",
         decimals: 2,
         unit: "V",
+        min: 10.0,
+        max: 15.5,
         report_map: ReportMap { name: "vaux", decimals: 1, scale: 1.0 },
+        history: History { samples: 60, interval_ms: 1000 },
     },
     Soc {
         display_name: "SoC",
         unit: "%",
+        min: 0.0,
+        max: 100.0,
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x102).unwrap()),
+            id: standard_id(0x102),
             bits: CanBitSelection::Uint8(6),
             scale: 100.0 / 255.0,
         },
         report_map: ReportMap { name: "er", decimals: 0, scale: 2.55 },
+        history: History { samples: 60, interval_ms: 1000 },
     },
     BatteryVMin {
         display_name: "Bat V min",
         decimals: 2,
         unit: "V",
+        min: 2.5,
+        max: 4.3,
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x101).unwrap()),
-            bits: CanBitSelection::Function(|data: &[u8]| -> f32 {
+            id: standard_id(0x101),
+            bits: CanBitSelection::Function(2, |data: &[u8]| -> f32 {
                 (((data[0] as u16) << 4) | ((data[1] as u16) >> 4)) as f32
             }),
             scale: 0.01,
@@ -35,9 +51,11 @@ define_parameters! {
         display_name: "Bat V max",
         decimals: 2,
         unit: "V",
+        min: 2.5,
+        max: 4.3,
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x101).unwrap()),
-            bits: CanBitSelection::Function(|data: &[u8]| -> f32 {
+            id: standard_id(0x101),
+            bits: CanBitSelection::Function(3, |data: &[u8]| -> f32 {
                 ((((data[1] & 0x0f) as u16) << 8) | data[2] as u16) as f32
             }),
             scale: 0.01,
@@ -47,8 +65,10 @@ define_parameters! {
     BatteryTMin {
         display_name: "Bat T min",
         unit: "degC",
+        min: -30.0,
+        max: 60.0,
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x101).unwrap()),
+            id: standard_id(0x101),
             bits: CanBitSelection::Int8(3),
             scale: 1.0,
         },
@@ -57,8 +77,10 @@ define_parameters! {
     BatteryTMax {
         display_name: "Bat T max",
         unit: "degC",
+        min: -30.0,
+        max: 60.0,
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x101).unwrap()),
+            id: standard_id(0x101),
             bits: CanBitSelection::Int8(4),
             scale: 1.0,
         },
@@ -69,8 +91,8 @@ define_parameters! {
         decimals: 1,
         unit: "A",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x102).unwrap()),
-            bits: CanBitSelection::Function(|data: &[u8]| -> f32 {
+            id: standard_id(0x102),
+            bits: CanBitSelection::Function(4, |data: &[u8]| -> f32 {
                 (((data[2] as u16) << 8) | data[3] as u16) as f32
             }),
             scale: 0.1,
@@ -81,18 +103,38 @@ define_parameters! {
         decimals: 1,
         unit: "A",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x102).unwrap()),
-            bits: CanBitSelection::Function(|data: &[u8]| -> f32 {
+            id: standard_id(0x102),
+            bits: CanBitSelection::Function(6, |data: &[u8]| -> f32 {
                 (((data[4] as u16) << 8) | data[5] as u16) as f32
             }),
             scale: 0.1,
         },
     },
+    // Not mapped, as the BMS of this example doesn't send the pack current.
+    // Map these to your BMS or current shunt to get the energy counters.
+    BatteryVoltage {
+        display_name: "Bat voltage",
+        decimals: 1,
+        unit: "V",
+    },
+    BatteryCurrent {
+        display_name: "Bat current",
+        decimals: 1,
+        unit: "A", // Positive when discharging
+    },
+    BatteryPower {
+        display_name: "Bat power",
+        decimals: 1,
+        unit: "kW",
+        compute: |v| v[0] * v[1] * 0.001,
+        inputs: [BatteryVoltage, BatteryCurrent],
+    },
     MainContactor {
         display_name: "Main contactor",
+        value_type: ParameterType::Bool,
         unit: "",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x100).unwrap()),
+            id: standard_id(0x100),
             bits: CanBitSelection::Bit(2),
             scale: 1.0,
         },
@@ -100,18 +142,20 @@ define_parameters! {
     },
     Precharging {
         display_name: "Precharging",
+        value_type: ParameterType::Bool,
         unit: "",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x100).unwrap()),
+            id: standard_id(0x100),
             bits: CanBitSelection::Bit(5),
             scale: 1.0,
         },
     },
     PrechargeFailed {
         display_name: "Precharge failed",
+        value_type: ParameterType::Bool,
         unit: "",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x100).unwrap()),
+            id: standard_id(0x100),
             bits: CanBitSelection::Bit(6),
             scale: 1.0,
         },
@@ -119,9 +163,10 @@ define_parameters! {
     },
     Balancing {
         display_name: "Balancing",
+        value_type: ParameterType::Bool,
         unit: "",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x101).unwrap()),
+            id: standard_id(0x101),
             bits: CanBitSelection::Bit(5 * 8 + 0),
             scale: 1.0,
         },
@@ -135,7 +180,7 @@ define_parameters! {
         display_name: "CCS",
         unit: "A",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x506).unwrap()),
+            id: standard_id(0x506),
            bits: CanBitSelection::Uint8(5),
             scale: 2.0,
         },
@@ -145,7 +190,7 @@ define_parameters! {
         display_name: "Chademo",
         unit: "A",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x500).unwrap()),
+            id: standard_id(0x500),
             bits: CanBitSelection::Uint8(5),
             scale: 1.0,
         },
@@ -155,14 +200,26 @@ define_parameters! {
         display_name: "Charge power",
         decimals: 1,
         unit: "kW",
+        compute: |v| {
+            let (ccs_current, chademo_current, obc_dcc, obc_dcv) = (v[0], v[1], v[2], v[3]);
+            if ccs_current > 1.0 {
+                ccs_current * obc_dcv * 0.001
+            } else if chademo_current > 1.0 {
+                chademo_current * obc_dcv * 0.001
+            } else {
+                obc_dcc * obc_dcv * 0.001
+            }
+        },
+        inputs: [CcsCurrent, ChademoCurrent, ObcDcc, ObcDcv],
         report_map: ReportMap { name: "chgp", decimals: 0, scale: 0.001 },
+        history: History { samples: 60, interval_ms: 1000 },
     },
     BmsChargeCompleteVoltageSetting {
         display_name: "BmsChgCompV",
         unit: "mV",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x104).unwrap()),
-            bits: CanBitSelection::Function(|data: &[u8]| -> f32 {
+            id: standard_id(0x104),
+            bits: CanBitSelection::Function(2, |data: &[u8]| -> f32 {
                 (((data[0] as u16) << 8) | data[1] as u16) as f32
             }),
             scale: 1.0,
@@ -179,6 +236,7 @@ define_parameters! {
         value: 10.0,
         unit: "A",
         report_map: ReportMap { name: "acc", decimals: 0, scale: 1.0 },
+        writable: true,
     },
     IpdmAcChargeCurrentSetting {
         display_name: "IpdmAcCurSet",
@@ -189,7 +247,7 @@ define_parameters! {
         display_name: "AcObcSt->Focci",
         unit: "",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x204).unwrap()),
+            id: standard_id(0x204),
             bits: CanBitSelection::Uint8(1),
             scale: 1.0,
         },
@@ -199,7 +257,7 @@ define_parameters! {
         display_name: "Foccci CP PWM",
         unit: "%",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x506).unwrap()),
+            id: standard_id(0x506),
             bits: CanBitSelection::Uint8(1),
             scale: 1.0,
         },
@@ -209,7 +267,7 @@ define_parameters! {
         display_name: "OBC CP PWM",
         unit: "%",
         can_map: CanMap {
-           id: Id::Standard(StandardId::new(0x38a).unwrap()),
+           id: standard_id(0x38a),
             bits: CanBitSelection::Uint8(3),
             scale: 1.0,
         },
@@ -219,7 +277,7 @@ define_parameters! {
         decimals: 1,
         unit: "A",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x286).unwrap()),
+            id: standard_id(0x286),
             bits: CanBitSelection::Uint8(2),
             scale: 0.1,
         },
@@ -229,8 +287,8 @@ define_parameters! {
         decimals: 1,
         unit: "V",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x286).unwrap()),
-            bits: CanBitSelection::Function(|data: &[u8]| -> f32 {
+            id: standard_id(0x286),
+            bits: CanBitSelection::Function(2, |data: &[u8]| -> f32 {
                 (((data[0] as u16) << 8) | data[1] as u16) as f32
             }),
             scale: 0.1,
@@ -238,9 +296,10 @@ define_parameters! {
     },
     DcdcStatus {
         display_name: "DCDC status",
+        value_type: ParameterType::Enum(&[(0x22, "Running")]),
         unit: "",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x377).unwrap()),
+            id: standard_id(0x377),
             bits: CanBitSelection::Uint8(7),
             scale: 1.0,
         },
@@ -250,8 +309,8 @@ define_parameters! {
         decimals: 2,
         unit: "V",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x377).unwrap()),
-            bits: CanBitSelection::Function(|data: &[u8]| -> f32 {
+            id: standard_id(0x377),
+            bits: CanBitSelection::Function(2, |data: &[u8]| -> f32 {
                 (((data[0] as u16) << 8) | data[1] as u16) as f32
             }),
             scale: 0.01,
@@ -262,8 +321,8 @@ define_parameters! {
         decimals: 1,
         unit: "A",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x377).unwrap()),
-            bits: CanBitSelection::Function(|data: &[u8]| -> f32 {
+            id: standard_id(0x377),
+            bits: CanBitSelection::Function(4, |data: &[u8]| -> f32 {
                 (((data[2] as u16) << 8) | data[3] as u16) as f32
             }),
             scale: 0.1,
@@ -272,9 +331,11 @@ define_parameters! {
     HeaterT {
         display_name: "Heater T",
         unit: "degC",
+        min: -40.0,
+        max: 120.0,
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x398).unwrap()),
-            bits: CanBitSelection::Function(|data: &[u8]| -> f32 {
+            id: standard_id(0x398),
+            bits: CanBitSelection::Function(5, |data: &[u8]| -> f32 {
                 let t1 = data[3] as i8 - 40;
                 let t2 = data[4] as i8 - 40;
                 (if t1 > t2 { t1 } else { t2 }) as f32
@@ -287,8 +348,8 @@ define_parameters! {
         display_name: "Heater heating",
         unit: "",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x398).unwrap()),
-            bits: CanBitSelection::Function(|data: &[u8]| -> f32 {
+            id: standard_id(0x398),
+            bits: CanBitSelection::Function(6, |data: &[u8]| -> f32 {
                 if data[5] > 0 { 1.0 } else { 0.0 }
             }),
             scale: 1.0,
@@ -299,8 +360,8 @@ define_parameters! {
         display_name: "Heater power",
         unit: "%",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x398).unwrap()),
-            bits: CanBitSelection::Function(|data: &[u8]| -> f32 {
+            id: standard_id(0x398),
+            bits: CanBitSelection::Function(6, |data: &[u8]| -> f32 {
                 if data[5] > 0 {
                     // [1..2] = 24, 17 (varies smoothly, separate values) (at 50%)
                     // [1..2] = 53, 36 (varies smoothly, separate values) (at 100%)
@@ -319,18 +380,26 @@ define_parameters! {
         display_name: "CabinT",
         decimals: 1,
         unit: "degC",
+        min: -40.0,
+        max: 85.0,
+        tx_can_map: TxCanMap {
+            id: standard_id(0x404),
+            bits: CanBitSelection::Int8(1),
+            period_ms: 500,
+        },
         report_map: ReportMap { name: "cabin_t", decimals: 1, scale: 1.0 },
     },
     HvacCountdown {
         display_name: "HvacCountdown",
         decimals: 1,
         unit: "s",
+        writable: true,
     },
     ObcDcv {
         display_name: "OBC DC V",
         unit: "V",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x389).unwrap()),
+            id: standard_id(0x389),
             bits: CanBitSelection::Uint8(0),
             scale: 2.0,
         },
@@ -341,7 +410,7 @@ define_parameters! {
         decimals: 1,
         unit: "Adc",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x389).unwrap()),
+            id: standard_id(0x389),
             bits: CanBitSelection::Uint8(2),
             scale: 0.1,
         },
@@ -351,7 +420,7 @@ define_parameters! {
         display_name: "OBC AC V",
         unit: "V",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x389).unwrap()),
+            id: standard_id(0x389),
             bits: CanBitSelection::Uint8(1),
             scale: 1.0,
         },
@@ -361,8 +430,8 @@ define_parameters! {
         display_name: "Speed",
         unit: "km/h",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x051).unwrap()),
-            bits: CanBitSelection::Function(|data: &[u8]| -> f32 {
+            id: standard_id(0x051),
+            bits: CanBitSelection::Function(4, |data: &[u8]| -> f32 {
                 (((data[2] as u16) << 8) | data[3] as u16) as f32
             }),
             scale: 80.0 / 5300.0,
@@ -371,14 +440,17 @@ define_parameters! {
     },
     CruiseRequested {
         display_name: "Cruise requested",
+        value_type: ParameterType::Bool,
         unit: "",
         report_map: ReportMap { name: "crur", decimals: 0, scale: 1.0 },
+        writable: true,
     },
     CruiseActive {
         display_name: "Cruise active",
+        value_type: ParameterType::Bool,
         unit: "",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x300).unwrap()),
+            id: standard_id(0x300),
             bits: CanBitSelection::Bit(2),
             scale: 1.0,
         },
@@ -387,39 +459,43 @@ define_parameters! {
     InverterT {
         display_name: "InverterT",
         unit: "degC",
+        min: -40.0,
+        max: 150.0,
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x55a).unwrap()),
-            bits: CanBitSelection::Function(|data: &[u8]| -> f32 {
-                let fahrenheit = data[2];
-                (fahrenheit as f32 - 32.0) * 5.0 / 9.0
-            }),
-            scale: 1.0,
+            id: standard_id(0x55a),
+            // Fahrenheit
+            bits: CanBitSelection::Uint8(2),
+            scale: 5.0 / 9.0,
+            offset: -32.0 * 5.0 / 9.0,
         },
         report_map: ReportMap { name: "it", decimals: 0, scale: 1.0 },
     },
     MotorT {
         display_name: "MotorT",
         unit: "degC",
+        min: -40.0,
+        max: 150.0,
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x55a).unwrap()),
-            bits: CanBitSelection::Function(|data: &[u8]| -> f32 {
-                let fahrenheit = data[1];
-                (fahrenheit as f32 - 32.0) * 5.0 / 9.0
-            }),
-            scale: 1.0,
+            id: standard_id(0x55a),
+            // Fahrenheit
+            bits: CanBitSelection::Uint8(1),
+            scale: 5.0 / 9.0,
+            offset: -32.0 * 5.0 / 9.0,
         },
         report_map: ReportMap { name: "mt", decimals: 0, scale: 1.0 },
     },
     Usb1Connected {
         display_name: "USB1 connected",
+        value_type: ParameterType::Bool,
         decimals: 0,
         unit: "",
     },
     IpdmIgnition {
         display_name: "IPDM Ignition",
+        value_type: ParameterType::Bool,
         unit: "",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x200).unwrap()),
+            id: standard_id(0x200),
             bits: CanBitSelection::Bit(6),
             scale: 1.0,
         },
@@ -427,9 +503,10 @@ define_parameters! {
     },
     IpdmReqMC {
         display_name: "IPDM req MC",
+        value_type: ParameterType::Bool,
         unit: "",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x200).unwrap()),
+            id: standard_id(0x200),
             bits: CanBitSelection::Bit(0),
             scale: 1.0,
         },
@@ -439,43 +516,47 @@ define_parameters! {
         display_name: "IPDM PCB T",
         unit: "degC",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x200).unwrap()),
+            id: standard_id(0x200),
             bits: CanBitSelection::Int8(5),
             scale: 1.0,
         },
     },
     IpdmGroup1OC {
         display_name: "IPDM OC 1",
+        value_type: ParameterType::Bool,
         unit: "",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x200).unwrap()),
+            id: standard_id(0x200),
             bits: CanBitSelection::Bit(56),
             scale: 1.0,
         },
     },
     IpdmGroup2OC {
         display_name: "IPDM OC 2",
+        value_type: ParameterType::Bool,
         unit: "",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x200).unwrap()),
+            id: standard_id(0x200),
             bits: CanBitSelection::Bit(57),
             scale: 1.0,
         },
     },
     IpdmGroup3OC {
         display_name: "IPDM OC 3",
+        value_type: ParameterType::Bool,
         unit: "",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x200).unwrap()),
+            id: standard_id(0x200),
             bits: CanBitSelection::Bit(58),
             scale: 1.0,
         },
     },
     IpdmGroup4OC {
         display_name: "IPDM OC 4",
+        value_type: ParameterType::Bool,
         unit: "",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x200).unwrap()),
+            id: standard_id(0x200),
             bits: CanBitSelection::Bit(59),
             scale: 1.0,
         },
@@ -485,7 +566,7 @@ define_parameters! {
         decimals: 2,
         unit: "A",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x206).unwrap()),
+            id: standard_id(0x206),
             bits: CanBitSelection::BeUnsigned(0, 12),
             scale: 1.0 / 256.0,
         },
@@ -496,7 +577,7 @@ define_parameters! {
         decimals: 2,
         unit: "A",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x206).unwrap()),
+            id: standard_id(0x206),
             bits: CanBitSelection::BeUnsigned(12, 12),
             scale: 1.0 / 256.0,
         },
@@ -507,7 +588,7 @@ define_parameters! {
         decimals: 2,
         unit: "A",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x206).unwrap()),
+            id: standard_id(0x206),
             bits: CanBitSelection::BeUnsigned(24, 12),
             scale: 1.0 / 256.0,
         },
@@ -518,7 +599,7 @@ define_parameters! {
         decimals: 2,
         unit: "A",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x206).unwrap()),
+            id: standard_id(0x206),
             bits: CanBitSelection::BeUnsigned(36, 12),
             scale: 1.0 / 256.0,
         },
@@ -529,7 +610,7 @@ define_parameters! {
         decimals: 2,
         unit: "A",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x206).unwrap()),
+            id: standard_id(0x206),
             bits: CanBitSelection::BeUnsigned(48, 12),
             scale: 1.0 / (256.0 / 3.0),
         },
@@ -540,7 +621,7 @@ define_parameters! {
         decimals: 2,
         unit: "V",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x204).unwrap()),
+            id: standard_id(0x204),
             bits: CanBitSelection::BeUnsigned(16, 12),
             scale: 1.0 / 128.0,
         },
@@ -550,7 +631,7 @@ define_parameters! {
         decimals: 2,
         unit: "V",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x204).unwrap()),
+            id: standard_id(0x204),
             bits: CanBitSelection::BeUnsigned(28, 12),
             scale: 1.0 / 128.0,
         },
@@ -560,7 +641,7 @@ define_parameters! {
         decimals: 2,
         unit: "V",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x204).unwrap()),
+            id: standard_id(0x204),
             bits: CanBitSelection::BeUnsigned(40, 12),
             scale: 1.0 / 128.0,
         },
@@ -570,7 +651,7 @@ define_parameters! {
         decimals: 2,
         unit: "V",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x204).unwrap()),
+            id: standard_id(0x204),
             bits: CanBitSelection::BeUnsigned(52, 12),
             scale: 1.0 / 128.0,
         },
@@ -580,7 +661,7 @@ define_parameters! {
         decimals: 2,
         unit: "V",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x205).unwrap()),
+            id: standard_id(0x205),
             bits: CanBitSelection::BeUnsigned(0, 12),
             scale: 1.0 / 128.0,
         },
@@ -590,70 +671,77 @@ define_parameters! {
         decimals: 2,
         unit: "V",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x205).unwrap()),
+            id: standard_id(0x205),
             bits: CanBitSelection::BeUnsigned(12, 12),
             scale: 1.0 / 128.0,
         },
     },
     IpdmM7 {
         display_name: "IPDM M7",
+        value_type: ParameterType::Bool,
         unit: "",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x204).unwrap()),
+            id: standard_id(0x204),
             bits: CanBitSelection::Bit(1),
             scale: 1.0,
         },
     },
     IpdmM8 {
         display_name: "IPDM M8",
+        value_type: ParameterType::Bool,
         unit: "",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x204).unwrap()),
+            id: standard_id(0x204),
             bits: CanBitSelection::Bit(2),
             scale: 1.0,
         },
     },
     IpdmM9 {
         display_name: "IPDM M9",
+        value_type: ParameterType::Bool,
         unit: "",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x204).unwrap()),
+            id: standard_id(0x204),
             bits: CanBitSelection::Bit(3),
             scale: 1.0,
         },
     },
     IpdmM10 {
         display_name: "IPDM M10",
+        value_type: ParameterType::Bool,
         unit: "",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x204).unwrap()),
+            id: standard_id(0x204),
             bits: CanBitSelection::Bit(4),
             scale: 1.0,
         },
     },
     IpdmM11 {
         display_name: "IPDM M11",
+        value_type: ParameterType::Bool,
         unit: "",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x204).unwrap()),
+            id: standard_id(0x204),
             bits: CanBitSelection::Bit(5),
             scale: 1.0,
         },
     },
     IpdmM12 {
         display_name: "IPDM M12",
+        value_type: ParameterType::Bool,
         unit: "",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x204).unwrap()),
+            id: standard_id(0x204),
             bits: CanBitSelection::Bit(6),
             scale: 1.0,
         },
     },
     IpdmM13 {
         display_name: "IPDM M13",
+        value_type: ParameterType::Bool,
         unit: "",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x204).unwrap()),
+            id: standard_id(0x204),
             bits: CanBitSelection::Bit(7),
             scale: 1.0,
         },
@@ -663,7 +751,7 @@ define_parameters! {
         decimals: 2,
         unit: "V",
         can_map: CanMap {
-            id: Id::Standard(StandardId::new(0x205).unwrap()),
+            id: standard_id(0x205),
             bits: CanBitSelection::BeUnsigned(52, 12),
             scale: 1.0 / 128.0,
         },
@@ -671,29 +759,34 @@ define_parameters! {
     },
     PmState {
         display_name: "PmState",
+        value_type: ParameterType::U32,
         unit: "",
         report_map: ReportMap { name: "pms", decimals: 0, scale: 1.0 },
     },
     PmCr {
         display_name: "PmCr",
+        value_type: ParameterType::U32,
         unit: "",
         report_map: ReportMap { name: "pmcr", decimals: 0, scale: 1.0 },
     },
-    RangeKm {
-        display_name: "Range",
-        unit: "km",
-    },
+    // Integrated by MainState from Speed and BatteryPower, starting from the
+    // first fresh value. Trip is cleared by setting TripKm and TripEnergy to
+    // 0, Recent whenever the ignition turns on.
     TripKm {
         display_name: "Trip",
         unit: "km",
+        writable: true,
     },
     TripEnergy {
         display_name: "Trip",
         unit: "Wh",
+        writable: true,
     },
     TripConsumption {
         display_name: "Trip",
         unit: "Wh/km",
+        compute: |v| consumption_wh_per_km(v[0], v[1]),
+        inputs: [TripEnergy, TripKm],
     },
     RecentKm {
         display_name: "Recent",
@@ -706,6 +799,31 @@ define_parameters! {
     RecentConsumption {
         display_name: "Recent",
         unit: "Wh/km",
+        compute: |v| consumption_wh_per_km(v[0], v[1]),
+        inputs: [RecentEnergy, RecentKm],
+    },
+    // Usable energy of a full battery. The default is not persisted; change it
+    // here to match your pack. "set" overrides it until the next boot.
+    BatteryCapacity {
+        display_name: "Bat capacity",
+        value: 40.0,
+        decimals: 1,
+        unit: "kWh",
+        min: 0.0,
+        writable: true,
+    },
+    RangeKm {
+        display_name: "Range",
+        unit: "km",
+        compute: |v| {
+            let (soc, capacity_kwh, recent_consumption) = (v[0], v[1], v[2]);
+            if recent_consumption > 0.0 {
+                soc * 0.01 * capacity_kwh * 1000.0 / recent_consumption
+            } else {
+                f32::NAN
+            }
+        },
+        inputs: [Soc, BatteryCapacity, RecentConsumption],
     },
     TicksMs {
         display_name: "Ticks",
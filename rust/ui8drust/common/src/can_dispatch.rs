@@ -0,0 +1,161 @@
+// CAN ID to parameter dispatch
+//
+// Instead of comparing every received frame against every CanMap, the
+// parameters are indexed by CAN ID. The index is a table of (ID, parameter
+// index) pairs sorted by ID, which is binary searched for each frame.
+// define_parameters! builds it at compile time into a static sized to the
+// number of CAN mapped parameters, so nothing is allocated or sorted at runtime.
+
+use crate::Parameter;
+use bxcan::{ExtendedId, StandardId};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CanDispatchEntry {
+    key: u32,
+    param_i: u16,
+}
+
+impl CanDispatchEntry {
+    pub const EMPTY: CanDispatchEntry = CanDispatchEntry { key: 0, param_i: 0 };
+}
+
+// Standard and extended IDs get different keys even if the raw values match.
+// This is const for building the table at compile time, but bxcan's as_raw()
+// isn't, so the raw value is read with transmute. That is sound: transmute
+// checks that the sizes match, so the only field of the ID fills it.
+pub const fn can_id_key(id: bxcan::Id) -> u32 {
+    match id {
+        bxcan::Id::Standard(id) => {
+            let raw = unsafe { core::mem::transmute::<StandardId, u16>(id) };
+            raw as u32
+        }
+        bxcan::Id::Extended(id) => {
+            let raw = unsafe { core::mem::transmute::<ExtendedId, u32>(id) };
+            raw | 0x8000_0000
+        }
+    }
+}
+
+// Sizes the table for define_parameters!
+pub const fn count_can_mapped(params: &[Parameter]) -> usize {
+    let mut count = 0;
+    let mut i = 0;
+    while i < params.len() {
+        if params[i].can_map.is_some() {
+            count += 1;
+        }
+        i += 1;
+    }
+    count
+}
+
+// N is the number of CAN mapped parameters
+pub struct CanDispatchTable<const N: usize> {
+    entries: [CanDispatchEntry; N],
+    num_entries: usize,
+}
+
+impl<const N: usize> CanDispatchTable<N> {
+    // define_parameters! calls this in a static initializer
+    pub const fn build(params: &[Parameter]) -> Self {
+        let mut entries = [CanDispatchEntry::EMPTY; N];
+        let mut num_entries = 0;
+        let mut i = 0;
+        while i < params.len() {
+            if let Some(can_map) = &params[i].can_map {
+                assert!(num_entries < N, "CanDispatchTable is too small; see count_can_mapped()");
+                entries[num_entries] = CanDispatchEntry {
+                    key: can_id_key(can_map.id),
+                    param_i: i as u16,
+                };
+                num_entries += 1;
+            }
+            i += 1;
+        }
+        // Insertion sort, as the slice sorts aren't const. Being stable, it
+        // keeps parameters sharing an ID in definition order.
+        let mut i = 1;
+        while i < num_entries {
+            let entry = entries[i];
+            let mut j = i;
+            while j > 0 && entries[j - 1].key > entry.key {
+                entries[j] = entries[j - 1];
+                j -= 1;
+            }
+            entries[j] = entry;
+            i += 1;
+        }
+        Self { entries, num_entries }
+    }
+
+    // Returns the indices of the parameters mapped to the CAN ID
+    pub fn parameters_for(&self, id: bxcan::Id) -> impl Iterator<Item = usize> + '_ {
+        let key = can_id_key(id);
+        let entries = &self.entries[..self.num_entries];
+        let first = entries.partition_point(|entry| entry.key < key);
+        entries[first..]
+            .iter()
+            .take_while(move |entry| entry.key == key)
+            .map(|entry| entry.param_i as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::candump::parse_cansend;
+    use crate::{
+        extended_id, standard_id, update_parameters_on_can, CanBitSelection, CanMap,
+        ParameterQuality, ParameterStore,
+    };
+
+    const fn rx(id: bxcan::Id, bits: CanBitSelection) -> Option<CanMap> {
+        Some(CanMap { id, bits, ..CanMap::DEFAULT })
+    }
+
+    const DEFINITIONS: [Parameter<'static>; 5] = [
+        Parameter::new(0, "A", "A", f32::NAN, 0, "",
+                rx(standard_id(0x123), CanBitSelection::Uint8(0))),
+        Parameter::new(1, "Plain", "Plain", 1.0, 0, "", None),
+        // Same raw value as 0x123, but extended
+        Parameter::new(2, "B", "B", f32::NAN, 0, "",
+                rx(extended_id(0x123), CanBitSelection::Uint8(1))),
+        Parameter::new(3, "C", "C", f32::NAN, 0, "",
+                rx(standard_id(0x010), CanBitSelection::Uint8(2))),
+        // Shares the ID with A
+        Parameter::new(4, "D", "D", f32::NAN, 0, "",
+                rx(standard_id(0x123), CanBitSelection::Uint8(3))),
+    ];
+
+    // Built at compile time like in define_parameters!
+    static TABLE: CanDispatchTable<4> = CanDispatchTable::build(&DEFINITIONS);
+
+    #[test]
+    fn dispatch_table_matches_linear_scan() {
+        assert!(TABLE.parameters_for(standard_id(0x123)).eq([0, 4]));
+        assert!(TABLE.parameters_for(extended_id(0x123)).eq([2]));
+        assert_eq!(TABLE.parameters_for(standard_id(0x124)).count(), 0);
+        assert_eq!(count_can_mapped(&DEFINITIONS), 4);
+        assert_eq!(can_id_key(standard_id(0x7ff)), 0x7ff);
+        assert_eq!(can_id_key(extended_id(0x1f334455)), 0x9f334455);
+
+        let mut store: ParameterStore<5, 4> = ParameterStore::new(DEFINITIONS, &TABLE);
+        let mut linear = DEFINITIONS;
+        let frames = ["123#01020304", "00000123#11121314", "010#212223", "7FF#31323334",
+                "00000010#41424344", "123#5152"];
+        for (millis, frame) in frames.iter().enumerate() {
+            let frame = parse_cansend(frame).unwrap();
+            store.update_on_can(frame.clone(), millis as u64);
+            update_parameters_on_can(&mut linear, frame, millis as u64);
+            for (a, b) in store.iter().zip(linear.iter()) {
+                assert!(a.value.same_as(&b.value), "{}: {} vs {}", a.name, a.value, b.value);
+                assert_eq!((a.quality, a.update_timestamp, a.decode_error_count),
+                        (b.quality, b.update_timestamp, b.decode_error_count), "{}", a.name);
+            }
+        }
+        let values = [0x51, 1, 0x12, 0x23, 0x04].map(|v| v as f32);
+        assert!(store.iter().map(|p| p.value.as_f32()).eq(values));
+        // The last frame was too short for D
+        assert_eq!(store[4usize].quality, ParameterQuality::DecodeError);
+    }
+}
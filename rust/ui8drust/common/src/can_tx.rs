@@ -0,0 +1,187 @@
+// CAN transmit scheduling for TxCanMap
+//
+// Each CAN ID used by a TxCanMap becomes one 8 byte frame containing every
+// parameter mapped to that ID. The first parameter with the ID (in definition
+// order) decides the period and holds the timestamp of the frame.
+
+use crate::can_dispatch::can_id_key;
+use crate::{HardwareInterface, Parameter, ParameterQuality};
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+// Encodes every parameter published with the CAN ID. Returns None unless all
+// of them have a valid value, so that a missing value isn't sent as a
+// plausible looking zero; the receiver sees the frame time out instead.
+pub fn encode_tx_frame(params: &[Parameter], id: bxcan::Id) -> Option<bxcan::Frame> {
+    let mut data = [0u8; 8];
+    for param in params {
+        let Some(tx_can_map) = param.tx_can_map.as_ref().filter(|m| m.id == id) else {
+            continue;
+        };
+        if param.quality != ParameterQuality::Valid || param.value.is_nan() {
+            return None;
+        }
+        if !tx_can_map.encode(&mut data, param.value.as_f32()) {
+            warn!("{}: tx_can_map doesn't fit in the frame", param.name);
+        }
+    }
+    Some(bxcan::Frame::new_data(id, bxcan::Data::new(&data).unwrap()))
+}
+
+// N is the number of parameters
+pub struct TxCanScheduler<const N: usize> {
+    last_send_millis: [u64; N],
+    // True for the first parameter of each CAN ID, which schedules the frame
+    schedules_frame: [bool; N],
+}
+
+impl<const N: usize> TxCanScheduler<N> {
+    // params are the parameter definitions, for finding the frames to send
+    pub const fn new(params: &[Parameter]) -> Self {
+        let mut schedules_frame = [false; N];
+        let mut i = 0;
+        while i < params.len() && i < N {
+            if let Some(tx_can_map) = &params[i].tx_can_map {
+                let key = can_id_key(tx_can_map.id);
+                let mut first = true;
+                let mut j = 0;
+                while j < i {
+                    if let Some(earlier) = &params[j].tx_can_map {
+                        first &= can_id_key(earlier.id) != key;
+                    }
+                    j += 1;
+                }
+                schedules_frame[i] = first;
+            }
+            i += 1;
+        }
+        Self {
+            last_send_millis: [0; N],
+            schedules_frame,
+        }
+    }
+
+    // Call this regularly. Sends the frames whose period has passed.
+    pub fn update(&mut self, params: &[Parameter], hw: &mut dyn HardwareInterface) {
+        let millis = hw.millis();
+        self.update_with(params, millis, |frame| hw.send_can(frame));
+    }
+
+    // Like update(), but passes the frames to send. A frame that can't be sent
+    // yet is tried again on the next update.
+    pub fn update_with(&mut self, params: &[Parameter], millis: u64,
+            mut send: impl FnMut(bxcan::Frame)) {
+        for (i, param) in params.iter().enumerate().take(N) {
+            let Some(tx_can_map) = &param.tx_can_map else {
+                continue;
+            };
+            if !self.schedules_frame[i] ||
+                    millis.wrapping_sub(self.last_send_millis[i]) < tx_can_map.period_ms {
+                continue;
+            }
+            if let Some(frame) = encode_tx_frame(params, tx_can_map.id) {
+                self.last_send_millis[i] = millis;
+                send(frame);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{standard_id, CanBitSelection, TxCanMap};
+
+    fn tx_parameters() -> [Parameter<'static>; 3] {
+        let mut params = [
+            Parameter::new(0, "CabinT", "Cabin temperature", f32::NAN, 1, "degC", None),
+            Parameter::new(1, "Plain", "Plain", 1.0, 0, "", None),
+            Parameter::new(2, "Heater", "Heater", f32::NAN, 0, "W", None),
+        ];
+        params[0].tx_can_map = Some(TxCanMap {
+            id: standard_id(0x404),
+            bits: CanBitSelection::Int8(1),
+            period_ms: 500,
+            ..TxCanMap::DEFAULT
+        });
+        params[2].tx_can_map = Some(TxCanMap {
+            id: standard_id(0x404),
+            bits: CanBitSelection::LeUnsigned(16, 16),
+            scale: 0.5,
+            offset: -100.0,
+            period_ms: 100, // Ignored; CabinT comes first
+        });
+        params
+    }
+
+    #[test]
+    fn frames_encode() {
+        let mut params = tx_parameters();
+        let data = |params: &[Parameter]| {
+            let frame = encode_tx_frame(params, standard_id(0x404)).unwrap();
+            assert_eq!(frame.id(), standard_id(0x404));
+            let mut data = [0u8; 8];
+            data.copy_from_slice(frame.data().unwrap());
+            data
+        };
+        // Nothing is sent until every parameter of the frame has a valid value
+        assert!(encode_tx_frame(&params, standard_id(0x404)).is_none());
+        params[0].set_value(21.4, 0);
+        assert!(encode_tx_frame(&params, standard_id(0x404)).is_none());
+        params[2].set_value(1000.0, 0);
+        // (1000 + 100) / 0.5 = 2200 = 0x0898
+        assert_eq!(data(&params), [0, 21, 0x98, 0x08, 0, 0, 0, 0]);
+        params[0].set_value(-3.6, 0);
+        assert_eq!(data(&params), [0, 0xfc, 0x98, 0x08, 0, 0, 0, 0]);
+        // Saturates
+        params[0].set_value(300.0, 0);
+        assert_eq!(data(&params)[1], 127);
+        for quality in [ParameterQuality::Stale, ParameterQuality::DecodeError,
+                ParameterQuality::OutOfRange] {
+            params[2].set_quality(quality);
+            assert!(encode_tx_frame(&params, standard_id(0x404)).is_none());
+        }
+
+        let mut raw = [0u8; 2];
+        let tx_can_map = TxCanMap { bits: CanBitSelection::BeSigned(4, 12), scale: 0.1,
+                ..TxCanMap::DEFAULT };
+        assert!(tx_can_map.encode(&mut raw, -0.2));
+        assert_eq!(raw, [0x0f, 0xfe]);
+        assert!(!tx_can_map.encode(&mut raw[..1], 1.0));
+        let tx_can_map = TxCanMap { bits: CanBitSelection::Function(1, |d| d[0] as f32),
+                ..TxCanMap::DEFAULT };
+        assert!(!tx_can_map.encode(&mut raw, 1.0));
+    }
+
+    #[test]
+    fn frames_follow_period() {
+        let mut params = tx_parameters();
+        let mut scheduler: TxCanScheduler<3> = TxCanScheduler::new(&params);
+        assert_eq!(scheduler.schedules_frame, [true, false, false]);
+        params[0].set_value(20.0, 0);
+        let mut sent_at = std::vec::Vec::new();
+        for millis in (0..=1600).step_by(50) {
+            // The frame waits for Heater
+            if millis == 600 {
+                params[2].set_value(0.0, millis);
+            }
+            if millis == 1000 {
+                params[0].set_value(22.0, millis);
+            }
+            scheduler.update_with(&params, millis, |frame| {
+                assert_eq!(frame.id(), standard_id(0x404));
+                sent_at.push((millis, frame.data().unwrap()[1]));
+            });
+        }
+        // A single frame for both parameters, at the period of the first one,
+        // held back at 500 ms while Heater had no value
+        assert_eq!(sent_at, [(600, 20), (1100, 22), (1600, 22)]);
+
+        // Late updates don't accumulate
+        let mut sent = 0;
+        scheduler.update_with(&params, 2500, |_| sent += 1);
+        scheduler.update_with(&params, 2600, |_| sent += 1);
+        scheduler.update_with(&params, 3000, |_| sent += 1);
+        assert_eq!(sent, 2);
+    }
+}
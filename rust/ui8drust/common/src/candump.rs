@@ -0,0 +1,313 @@
+// CAN frames as text, in the syntax of can-utils' cansend and candump
+//
+// "123#DEADBEEF" is a standard frame, "1F334455#11.22" an extended one (8 ID
+// digits) and "123#R" or "123#R4" a remote frame with the given DLC. Dots
+// between data bytes are allowed. CAN FD ("##") is not supported.
+//
+// CanLog logs frames in the candump -L log file format, e.g.
+// "(12.345000) can0 123#DEADBEEF R", with R or T at the end telling whether
+// the frame was received or transmitted, like candump -L -x. The lines go to
+// LOG_TARGET, which loggers write without a level prefix.
+
+use arrayvec::ArrayString;
+use bxcan::{ExtendedId, Frame, Id, StandardId};
+use core::fmt::{self, Write};
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+pub const LOG_TARGET: &str = "candump";
+pub const MAX_CAN_LOG_FILTERS: usize = 4;
+
+#[derive(Debug, PartialEq)]
+pub enum CanFrameParseError {
+    MissingSeparator,
+    InvalidId,
+    InvalidData,
+    TooLong,
+    CanFdNotSupported,
+}
+
+pub fn parse_cansend(text: &str) -> Result<Frame, CanFrameParseError> {
+    let (id_text, data_text) = text.split_once('#').ok_or(CanFrameParseError::MissingSeparator)?;
+    let id = parse_id(id_text)?;
+    if data_text.starts_with('#') {
+        return Err(CanFrameParseError::CanFdNotSupported);
+    }
+
+    if let Some(dlc_text) = data_text.strip_prefix(['R', 'r']) {
+        let dlc = match dlc_text {
+            "" => 0,
+            _ => dlc_text.parse::<u8>().map_err(|_| CanFrameParseError::InvalidData)?,
+        };
+        if dlc > 8 {
+            return Err(CanFrameParseError::TooLong);
+        }
+        return Ok(Frame::new_remote(id, dlc));
+    }
+
+    let mut data = [0u8; 8];
+    let mut len = 0;
+    let mut digits = data_text.bytes().filter(|&c| c != b'.');
+    while let Some(high) = digits.next() {
+        let low = digits.next().ok_or(CanFrameParseError::InvalidData)?;
+        if len == data.len() {
+            return Err(CanFrameParseError::TooLong);
+        }
+        data[len] = (hex_digit(high)? << 4) | hex_digit(low)?;
+        len += 1;
+    }
+    Ok(Frame::new_data(id, bxcan::Data::new(&data[..len]).unwrap()))
+}
+
+// 3 digits make a standard ID and 8 digits an extended one, like in cansend
+fn parse_id(text: &str) -> Result<Id, CanFrameParseError> {
+    if !text.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return Err(CanFrameParseError::InvalidId);
+    }
+    let raw = u32::from_str_radix(text, 16).map_err(|_| CanFrameParseError::InvalidId)?;
+    let id = match text.len() {
+        3 => StandardId::new(raw as u16).filter(|_| raw <= 0x7ff).map(Id::Standard),
+        8 => ExtendedId::new(raw).map(Id::Extended),
+        _ => None,
+    };
+    id.ok_or(CanFrameParseError::InvalidId)
+}
+
+fn hex_digit(c: u8) -> Result<u8, CanFrameParseError> {
+    (c as char).to_digit(16).map(|d| d as u8).ok_or(CanFrameParseError::InvalidData)
+}
+
+// Formats the frame in cansend syntax
+pub struct CansendFrame<'a>(pub &'a Frame);
+
+impl fmt::Display for CansendFrame<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0.id() {
+            Id::Standard(id) => write!(f, "{:03X}#", id.as_raw())?,
+            Id::Extended(id) => write!(f, "{:08X}#", id.as_raw())?,
+        }
+        match self.0.data() {
+            Some(data) => {
+                for b in data.iter() {
+                    write!(f, "{:02X}", b)?;
+                }
+                Ok(())
+            }
+            None => write!(f, "R{}", self.0.dlc()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CanDirection {
+    Rx,
+    Tx,
+}
+
+// A candump style filter: "<id>:<mask>" passes frames whose ID matches id in
+// the bits set in mask, "<id>~<mask>" passes the others. A plain "<id>" only
+// passes that ID. Standard and extended IDs are compared as numbers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CanFilter {
+    pub id: u32,
+    pub mask: u32,
+    pub inverted: bool,
+}
+
+impl CanFilter {
+    pub fn parse(text: &str) -> Option<CanFilter> {
+        let parse_hex = |text: &str| -> Option<u32> {
+            if text.is_empty() || !text.bytes().all(|c| c.is_ascii_hexdigit()) {
+                return None;
+            }
+            u32::from_str_radix(text, 16).ok()
+        };
+        if let Some((id, mask)) = text.split_once(':') {
+            Some(CanFilter { id: parse_hex(id)?, mask: parse_hex(mask)?, inverted: false })
+        } else if let Some((id, mask)) = text.split_once('~') {
+            Some(CanFilter { id: parse_hex(id)?, mask: parse_hex(mask)?, inverted: true })
+        } else {
+            Some(CanFilter { id: parse_hex(text)?, mask: 0x1fffffff, inverted: false })
+        }
+    }
+
+    pub fn matches(&self, id: Id) -> bool {
+        let raw = match id {
+            Id::Standard(id) => id.as_raw() as u32,
+            Id::Extended(id) => id.as_raw(),
+        };
+        ((raw & self.mask) == (self.id & self.mask)) != self.inverted
+    }
+}
+
+impl fmt::Display for CanFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let separator = if self.inverted { '~' } else { ':' };
+        write!(f, "{:X}{}{:X}", self.id, separator, self.mask)
+    }
+}
+
+// Which frames the platform logs. Frames pass if any of the filters matches,
+// or if there are no filters.
+#[derive(Debug, Clone, Copy)]
+pub struct CanLog {
+    enabled: bool,
+    filters: [CanFilter; MAX_CAN_LOG_FILTERS],
+    num_filters: usize,
+}
+
+impl CanLog {
+    pub const fn new() -> Self {
+        Self {
+            enabled: false,
+            filters: [CanFilter { id: 0, mask: 0, inverted: false }; MAX_CAN_LOG_FILTERS],
+            num_filters: 0,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn filters(&self) -> &[CanFilter] {
+        &self.filters[..self.num_filters]
+    }
+
+    pub fn disable(&mut self) {
+        self.enabled = false;
+    }
+
+    // Returns false if there are too many filters
+    pub fn enable(&mut self, filters: &[CanFilter]) -> bool {
+        if filters.len() > MAX_CAN_LOG_FILTERS {
+            return false;
+        }
+        self.filters[..filters.len()].copy_from_slice(filters);
+        self.num_filters = filters.len();
+        self.enabled = true;
+        true
+    }
+
+    pub fn passes(&self, id: Id) -> bool {
+        self.enabled && (self.num_filters == 0 || self.filters().iter().any(|f| f.matches(id)))
+    }
+
+    // Call this for every received and transmitted frame
+    pub fn log(&self, millis: u64, frame: &Frame, direction: CanDirection) {
+        if !self.passes(frame.id()) {
+            return;
+        }
+        let mut line: ArrayString<64> = ArrayString::new();
+        let _ = write_candump_line(&mut line, millis, frame, direction);
+        info!(target: LOG_TARGET, "{}", line);
+    }
+}
+
+impl Default for CanLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// E.g. "(12.345000) can0 123#DEADBEEF R"
+pub fn write_candump_line(out: &mut dyn Write, millis: u64, frame: &Frame,
+        direction: CanDirection) -> fmt::Result {
+    let direction = match direction {
+        CanDirection::Rx => 'R',
+        CanDirection::Tx => 'T',
+    };
+    write!(out, "({}.{:06}) can0 {} {}", millis / 1000, (millis % 1000) * 1000,
+            CansendFrame(frame), direction)
+}
+
+// A line of a candump -L log file
+#[derive(Debug)]
+pub struct CandumpLine<'a> {
+    pub micros: u64,
+    pub interface: &'a str,
+    pub frame: Frame,
+    // None if the line doesn't say
+    pub direction: Option<CanDirection>,
+}
+
+// Returns None for lines that aren't frames, e.g. other log output
+pub fn parse_candump_line(line: &str) -> Option<CandumpLine<'_>> {
+    let mut words = line.split_whitespace();
+    let timestamp = words.next()?.strip_prefix('(')?.strip_suffix(')')?;
+    let interface = words.next()?;
+    let frame = parse_cansend(words.next()?).ok()?;
+    let direction = match words.next() {
+        Some("R") => Some(CanDirection::Rx),
+        Some("T") => Some(CanDirection::Tx),
+        _ => None,
+    };
+
+    let (seconds, fraction) = timestamp.split_once('.').unwrap_or((timestamp, ""));
+    if fraction.len() > 6 || !fraction.bytes().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let mut micros = seconds.parse::<u64>().ok()?.checked_mul(1_000_000)?;
+    for (i, c) in fraction.bytes().enumerate() {
+        micros += (c - b'0') as u64 * 10u64.pow(5 - i as u32);
+    }
+    Some(CandumpLine { micros, interface, frame, direction })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{extended_id, standard_id};
+
+    #[test]
+    fn cansend_frames_parse() {
+        let frame = parse_cansend("123#DEAD.beef").unwrap();
+        assert_eq!(frame.id(), standard_id(0x123));
+        assert_eq!(frame.data().unwrap().as_ref(), &[0xde, 0xad, 0xbe, 0xef]);
+        let frame = parse_cansend("1F334455#").unwrap();
+        assert_eq!(frame.id(), extended_id(0x1f334455));
+        assert_eq!(frame.dlc(), 0);
+        let frame = parse_cansend("7FF#R4").unwrap();
+        assert!(frame.is_remote_frame());
+        assert_eq!(frame.dlc(), 4);
+        assert_eq!(parse_cansend("800#00"), Err(CanFrameParseError::InvalidId));
+        assert_eq!(parse_cansend("12#00"), Err(CanFrameParseError::InvalidId));
+        assert_eq!(parse_cansend("123#0"), Err(CanFrameParseError::InvalidData));
+        assert_eq!(parse_cansend("123#00112233445566778"), Err(CanFrameParseError::InvalidData));
+        assert_eq!(parse_cansend("123#001122334455667788"), Err(CanFrameParseError::TooLong));
+        assert_eq!(parse_cansend("123##100"), Err(CanFrameParseError::CanFdNotSupported));
+        assert_eq!(parse_cansend("123"), Err(CanFrameParseError::MissingSeparator));
+    }
+
+    #[test]
+    fn lines_format_and_parse() {
+        let mut line: ArrayString<64> = ArrayString::new();
+        let frame = parse_cansend("1F334455#0102").unwrap();
+        write_candump_line(&mut line, 12345, &frame, CanDirection::Tx).unwrap();
+        assert_eq!(line.as_str(), "(12.345000) can0 1F334455#0102 T");
+        line.clear();
+        let frame = parse_cansend("07F#R3").unwrap();
+        write_candump_line(&mut line, 5, &frame, CanDirection::Rx).unwrap();
+        assert_eq!(line.as_str(), "(0.005000) can0 07F#R3 R");
+
+        let filter = CanFilter::parse("100:700").unwrap();
+        assert!(filter.matches(standard_id(0x1ff)));
+        assert!(!filter.matches(standard_id(0x200)));
+        assert!(CanFilter::parse("100~700").unwrap().matches(standard_id(0x200)));
+        assert!(CanFilter::parse("1F334455").unwrap().matches(extended_id(0x1f334455)));
+        assert_eq!(CanFilter::parse("12x:7ff"), None);
+        let mut can_log = CanLog::new();
+        assert!(!can_log.passes(standard_id(0x123)));
+        can_log.enable(&[]);
+        assert!(can_log.passes(standard_id(0x123)));
+        can_log.enable(&[filter]);
+        assert!(!can_log.passes(standard_id(0x223)));
+
+        let line = parse_candump_line("(1700000000.5) vcan0 123#11 T").unwrap();
+        assert_eq!(line.micros, 1_700_000_000_500_000);
+        assert_eq!(line.interface, "vcan0");
+        assert_eq!(line.frame, parse_cansend("123#11").unwrap());
+        assert_eq!(line.direction, Some(CanDirection::Tx));
+        assert!(parse_candump_line("(12.000001) can0 123#").unwrap().direction.is_none());
+        assert!(parse_candump_line("[INFO] Hello").is_none());
+    }
+}
@@ -1,36 +1,374 @@
+use crate::console::tokenize;
 use arrayvec::ArrayString;
+use core::fmt::Write;
 
-pub struct CommandAccumulator<const BUF_LEN: usize> {
+// Enough for redrawing the line and listing a bunch of completions. Whatever
+// doesn't fit is dropped.
+pub const OUTPUT_LEN: usize = 512;
+
+// Supplies the words for tab completion
+pub trait Completer {
+    // Calls add() with every word that can follow the given words. words is
+    // empty when completing the first word.
+    fn complete(&self, words: &[&str], add: &mut dyn FnMut(&str));
+}
+
+// No completion
+impl Completer for () {
+    fn complete(&self, _: &[&str], _: &mut dyn FnMut(&str)) {}
+}
+
+// ANSI escape sequence parsing state
+#[derive(Clone, Copy, PartialEq)]
+enum Escape {
+    None,
+    Esc,
+    // ESC [, with the first numeric parameter so far. Further parameters
+    // (after ';') are ignored.
+    Csi { param: u8, more: bool },
+    // ESC O
+    Ss3,
+}
+
+// Collects characters into commands, with line editing for terminal users:
+// arrow keys, Home/End/Delete, Ctrl-A/E/C/U, history and tab completion. What
+// should be echoed back to the terminal is collected in an output buffer; see
+// take_output().
+pub struct CommandAccumulator<const BUF_LEN: usize, const HISTORY_LEN: usize = 8> {
     buf: ArrayString<BUF_LEN>,
+    cursor: usize, // Byte index into buf
+    escape: Escape,
+    last_was_cr: bool,
+    history: [ArrayString<BUF_LEN>; HISTORY_LEN],
+    history_count: usize,
+    history_next: usize, // Slot for the next command
+    // 0 when editing a new line, otherwise the line is from history and this
+    // is how far back
+    browse: usize,
+    unsaved: ArrayString<BUF_LEN>, // The new line while browsing history
+    output: ArrayString<OUTPUT_LEN>,
 }
 
-impl<const BUF_LEN: usize> CommandAccumulator<BUF_LEN> {
+impl<const BUF_LEN: usize, const HISTORY_LEN: usize> CommandAccumulator<BUF_LEN, HISTORY_LEN> {
     pub fn new() -> Self {
         CommandAccumulator {
             buf: ArrayString::new(),
+            cursor: 0,
+            escape: Escape::None,
+            last_was_cr: false,
+            history: [ArrayString::new(); HISTORY_LEN],
+            history_count: 0,
+            history_next: 0,
+            browse: 0,
+            unsaved: ArrayString::new(),
+            output: ArrayString::new(),
         }
     }
 
     pub fn put(&mut self, c: char) -> Option<ArrayString<BUF_LEN>> {
-        if c == '\n' || c == '\r' {
-            if self.buf.len() > 0 {
-                // NOTE: This is a bit wasteful, we could instead use a
-                // swappable buffer and swap in an empty one, returning the
-                // existing buffer as-is
-                let command = self.buf.clone();
+        self.put_with_completion(c, &())
+    }
+
+    // Returns the command when Enter is pressed on a non-empty line
+    pub fn put_with_completion(&mut self, c: char, completer: &dyn Completer)
+            -> Option<ArrayString<BUF_LEN>> {
+        match self.escape {
+            Escape::None => {}
+            Escape::Esc => {
+                self.escape = match c {
+                    '[' => Escape::Csi { param: 0, more: false },
+                    'O' => Escape::Ss3,
+                    _ => Escape::None,
+                };
+                return None;
+            }
+            Escape::Csi { param, more } => {
+                match c {
+                    '0'..='9' if !more => {
+                        let param = param.saturating_mul(10).saturating_add(c as u8 - b'0');
+                        self.escape = Escape::Csi { param, more };
+                    }
+                    '0'..='9' | ';' => self.escape = Escape::Csi { param, more: true },
+                    _ => {
+                        self.escape = Escape::None;
+                        self.on_escape_sequence(c, param);
+                    }
+                }
+                return None;
+            }
+            Escape::Ss3 => {
+                self.escape = Escape::None;
+                self.on_escape_sequence(c, 0);
+                return None;
+            }
+        }
+
+        let after_cr = core::mem::replace(&mut self.last_was_cr, c == '\r');
+        match c {
+            '\r' | '\n' => {
+                // "\r\n" is one Enter
+                if c == '\n' && after_cr {
+                    return None;
+                }
+                self.out("\r\n");
+                return self.finish_line();
+            }
+            '\x1b' => self.escape = Escape::Esc,
+            '\u{7f}' | '\x08' if self.cursor > 0 => {
+                // Backspace
+                self.cursor -= self.char_len_before_cursor();
+                self.buf.remove(self.cursor);
+                self.redraw();
+            }
+            '\u{7f}' | '\x08' => {}
+            '\x01' => self.move_cursor_to(0),
+            '\x05' => self.move_cursor_to(self.buf.len()),
+            '\x03' => {
+                // Ctrl-C: Throw away the line
+                self.out("^C\r\n");
                 self.buf.clear();
-                return Some(command);
+                self.cursor = 0;
+                self.browse = 0;
             }
-        } else if c == '\u{7f}' {
-            // Backspace: Edit the command
-            if self.buf.len() > 0 {
-                self.buf.truncate(self.buf.len() - 1)
+            '\x15' => {
+                // Ctrl-U: Delete up to the cursor
+                let rest: ArrayString<BUF_LEN> = ArrayString::from(&self.buf[self.cursor..]).unwrap();
+                self.buf = rest;
+                self.cursor = 0;
+                self.redraw();
             }
-        } else if self.buf.is_full() {
-            // Ignore character because buffer is full
+            '\t' => self.complete(completer),
+            _ => self.insert(c),
+        }
+        None
+    }
+
+    // What should be written to the terminal since the last call
+    pub fn take_output(&mut self) -> ArrayString<OUTPUT_LEN> {
+        core::mem::take(&mut self.output)
+    }
+
+    fn finish_line(&mut self) -> Option<ArrayString<BUF_LEN>> {
+        let line = core::mem::take(&mut self.buf);
+        self.cursor = 0;
+        self.browse = 0;
+        if line.is_empty() {
+            return None;
+        }
+        if HISTORY_LEN > 0 && (self.history_count == 0 || self.history_entry(1) != &line) {
+            self.history[self.history_next] = line;
+            self.history_next = (self.history_next + 1) % HISTORY_LEN;
+            self.history_count = (self.history_count + 1).min(HISTORY_LEN);
+        }
+        Some(line)
+    }
+
+    fn on_escape_sequence(&mut self, c: char, param: u8) {
+        match (c, param) {
+            ('A', _) => self.browse_history(true),
+            ('B', _) => self.browse_history(false),
+            ('C', _) if self.cursor < self.buf.len() => {
+                let len = self.buf[self.cursor..].chars().next().map_or(0, char::len_utf8);
+                self.move_cursor_to(self.cursor + len);
+            }
+            ('D', _) if self.cursor > 0 => {
+                self.move_cursor_to(self.cursor - self.char_len_before_cursor());
+            }
+            ('H', _) | ('~', 1) | ('~', 7) => self.move_cursor_to(0),
+            ('F', _) | ('~', 4) | ('~', 8) => self.move_cursor_to(self.buf.len()),
+            ('~', 3) if self.cursor < self.buf.len() => {
+                // Delete
+                self.buf.remove(self.cursor);
+                self.redraw();
+            }
+            _ => {}
+        }
+    }
+
+    // n = 1 is the latest command
+    fn history_entry(&self, n: usize) -> &ArrayString<BUF_LEN> {
+        &self.history[(self.history_next + HISTORY_LEN - n) % HISTORY_LEN]
+    }
+
+    fn browse_history(&mut self, back: bool) {
+        if back && self.browse < self.history_count {
+            if self.browse == 0 {
+                self.unsaved = self.buf;
+            }
+            self.browse += 1;
+        } else if !back && self.browse > 0 {
+            self.browse -= 1;
         } else {
+            return;
+        }
+        self.buf = if self.browse == 0 { self.unsaved } else { *self.history_entry(self.browse) };
+        self.cursor = self.buf.len();
+        self.redraw();
+    }
+
+    fn insert(&mut self, c: char) {
+        if self.buf.len() + c.len_utf8() > BUF_LEN {
+            // Ignore character because buffer is full
+            return;
+        }
+        if self.cursor == self.buf.len() {
             self.buf.push(c);
+            self.cursor += c.len_utf8();
+            let _ = self.output.try_push(c);
+            return;
         }
-        None
+        let mut buf: ArrayString<BUF_LEN> = ArrayString::new();
+        buf.push_str(&self.buf[..self.cursor]);
+        buf.push(c);
+        buf.push_str(&self.buf[self.cursor..]);
+        self.buf = buf;
+        self.cursor += c.len_utf8();
+        self.redraw();
+    }
+
+    fn complete(&mut self, completer: &dyn Completer) {
+        let line = self.buf;
+        let word_start = line[..self.cursor].rfind(' ').map_or(0, |i| i + 1);
+        let Ok(words) = tokenize(&line[..word_start]) else {
+            return;
+        };
+        let prefix = &line[word_start..self.cursor];
+        // The longest beginning that all matches share
+        let mut common: ArrayString<BUF_LEN> = ArrayString::new();
+        let mut count = 0;
+        completer.complete(&words, &mut |word| {
+            if !starts_with_ignore_case(word, prefix) {
+                return;
+            }
+            if count == 0 {
+                let _ = common.try_push_str(word);
+            } else {
+                let mut len = common.bytes().zip(word.bytes())
+                        .take_while(|(a, b)| a.eq_ignore_ascii_case(b)).count();
+                while !common.is_char_boundary(len) {
+                    len -= 1;
+                }
+                common.truncate(len);
+            }
+            count += 1;
+        });
+        if count == 0 {
+            return;
+        }
+        if count == 1 || common.len() > prefix.len() {
+            let mut buf: ArrayString<BUF_LEN> = ArrayString::new();
+            let ok = buf.try_push_str(&line[..word_start]).is_ok() &&
+                    buf.try_push_str(&common).is_ok() &&
+                    (count > 1 || buf.try_push(' ').is_ok());
+            let cursor = buf.len();
+            if ok && buf.try_push_str(&line[self.cursor..]).is_ok() {
+                self.buf = buf;
+                self.cursor = cursor;
+                self.redraw();
+            }
+            return;
+        }
+        // Nothing to add; show the choices
+        let _ = self.output.try_push_str("\r\n");
+        completer.complete(&words, &mut |word| {
+            if starts_with_ignore_case(word, prefix) {
+                let _ = self.output.try_push_str(word);
+                let _ = self.output.try_push_str("  ");
+            }
+        });
+        self.out("\r\n");
+        self.redraw();
+    }
+
+    fn char_len_before_cursor(&self) -> usize {
+        self.buf[..self.cursor].chars().next_back().map_or(0, char::len_utf8)
+    }
+
+    fn move_cursor_to(&mut self, cursor: usize) {
+        if cursor != self.cursor {
+            self.cursor = cursor;
+            self.redraw();
+        }
+    }
+
+    // Rewrites the whole line and puts the terminal's cursor in place
+    fn redraw(&mut self) {
+        let chars_after_cursor = self.buf[self.cursor..].chars().count();
+        let _ = write!(self.output, "\r\x1b[K{}", self.buf);
+        if chars_after_cursor > 0 {
+            let _ = write!(self.output, "\x1b[{}D", chars_after_cursor);
+        }
+    }
+
+    fn out(&mut self, s: &str) {
+        let _ = self.output.try_push_str(s);
+    }
+}
+
+impl<const BUF_LEN: usize, const HISTORY_LEN: usize> Default
+        for CommandAccumulator<BUF_LEN, HISTORY_LEN> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn starts_with_ignore_case(word: &str, prefix: &str) -> bool {
+    word.len() >= prefix.len() &&
+            word.as_bytes()[..prefix.len()].eq_ignore_ascii_case(prefix.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn type_keys<const N: usize>(acc: &mut CommandAccumulator<N>, keys: &str,
+            completer: &dyn Completer) -> Option<ArrayString<N>> {
+        let mut command = None;
+        for c in keys.chars() {
+            command = command.or(acc.put_with_completion(c, completer));
+        }
+        command
+    }
+
+    #[test]
+    fn lines_are_edited() {
+        let mut acc: CommandAccumulator<50> = CommandAccumulator::new();
+        // Left arrow, Delete, End, Backspace
+        assert_eq!(type_keys(&mut acc, "get Sxoc\x1b[D\x1b[D\x1b[D\x1b[3~\x1b[F\x7fc\r", &())
+                .unwrap().as_str(), "get Soc");
+        // Ctrl-U deletes up to the cursor, Ctrl-C throws away the line
+        assert_eq!(type_keys(&mut acc, "foo\x15bar\x03", &()), None);
+        assert_eq!(type_keys(&mut acc, "\x01par\x1b[Hx\x15\x05ams\r", &()).unwrap().as_str(),
+                "params");
+        // History, and the unfinished line is kept while browsing it
+        assert_eq!(type_keys(&mut acc, "new\x1b[A\x1b[A\x1b[B\x1b[Bx\r", &()).unwrap().as_str(),
+                "newx");
+        assert_eq!(type_keys(&mut acc, "\x1b[A\x1b[A\r", &()).unwrap().as_str(), "params");
+        // "\r\n" is a single Enter
+        acc.take_output();
+        assert_eq!(type_keys(&mut acc, "ab\r", &()).unwrap().as_str(), "ab");
+        assert_eq!(acc.put('\n'), None);
+        assert_eq!(acc.take_output().as_str(), "ab\r\n");
+    }
+
+    #[test]
+    fn words_are_completed() {
+        struct Words;
+        impl Completer for Words {
+            fn complete(&self, words: &[&str], add: &mut dyn FnMut(&str)) {
+                let choices: &[&str] = match words {
+                    [] => &["get", "set", "help"],
+                    ["get"] => &["AuxVoltage", "AuxCurrent", "Soc"],
+                    _ => &[],
+                };
+                choices.iter().for_each(|word| add(word));
+            }
+        }
+        let mut acc: CommandAccumulator<50> = CommandAccumulator::new();
+        assert_eq!(type_keys(&mut acc, "g\tso\t\r", &Words).unwrap().as_str(), "get Soc ");
+        // The common beginning first, then a list of the choices
+        type_keys(&mut acc, "get a\t", &Words);
+        acc.take_output();
+        assert_eq!(type_keys(&mut acc, "\tV\t\r", &Words).unwrap().as_str(), "get AuxVoltage ");
     }
 }
@@ -0,0 +1,408 @@
+// Console command registry
+//
+// Commands are registered with a name, an argument spec, help text and a
+// handler. C is whatever the handlers operate on, normally the app's
+// MainState. The registry splits the line into arguments, checks their count
+// against the spec and provides "help".
+
+use crate::candump::{CanFilter, MAX_CAN_LOG_FILTERS};
+use crate::command_accumulator::Completer;
+use crate::log_filter::{LogLevels, LogSink};
+use crate::HardwareInterface;
+use arrayvec::ArrayVec;
+use log::LevelFilter;
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+// Including the command name
+pub const MAX_ARGS: usize = 12;
+
+// Returned by a handler when the arguments don't make sense. The registry then
+// shows the usage. Handlers say about other failures themselves.
+#[derive(Debug, PartialEq)]
+pub struct InvalidArguments;
+
+pub type CommandResult = Result<(), InvalidArguments>;
+
+pub type CommandHandler<C> =
+        fn(ctx: &mut C, args: &[&str], hw: &mut dyn HardwareInterface) -> CommandResult;
+
+pub struct ConsoleCommand<C> {
+    pub name: &'static str,
+    // E.g. "<name> [interval_ms]". Words in <> are required, words in [] are
+    // optional and "..." at the end of a word allows any number of them. Other
+    // words are required as well, and are literal words, or a choice of them
+    // separated by |.
+    pub args: &'static str,
+    // The first line is shown in the command list, the rest only by
+    // "help <command>"
+    pub help: &'static str,
+    pub handler: CommandHandler<C>,
+}
+
+// derive() would require C: Clone
+impl<C> Clone for ConsoleCommand<C> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<C> Copy for ConsoleCommand<C> {}
+
+impl<C> ConsoleCommand<C> {
+    // (min, max), max is None if unlimited
+    pub fn arg_count_range(&self) -> (usize, Option<usize>) {
+        let mut min = 0;
+        let mut max = Some(0);
+        for word in self.args.split_whitespace() {
+            if !word.starts_with('[') {
+                min += 1;
+            }
+            max = max.map(|max| max + 1);
+            if is_variadic(word) {
+                max = None;
+            }
+        }
+        (min, max)
+    }
+
+    // The word of the spec that describes argument i, e.g. "<name...>"
+    pub fn arg_spec(&self, i: usize) -> Option<&'static str> {
+        for (word_i, word) in self.args.split_whitespace().enumerate() {
+            // "..." takes the rest of the arguments
+            if word_i == i || (word_i < i && is_variadic(word)) {
+                return Some(word);
+            }
+        }
+        None
+    }
+
+    fn accepts_arg_count(&self, count: usize) -> bool {
+        let (min, max) = self.arg_count_range();
+        count >= min && max.is_none_or(|max| count <= max)
+    }
+
+    fn log_usage(&self, prefix: &str) {
+        let summary = self.help.lines().next().unwrap_or("");
+        if self.args.is_empty() {
+            info!("{}{}  - {}", prefix, self.name, summary);
+        } else {
+            info!("{}{} {}  - {}", prefix, self.name, self.args, summary);
+        }
+    }
+}
+
+fn is_variadic(spec_word: &str) -> bool {
+    spec_word.trim_end_matches(['>', ']']).ends_with("...")
+}
+
+// "<name...>" -> "name"
+pub fn arg_spec_name(spec_word: &str) -> &str {
+    spec_word.trim_matches(['<', '>', '[', ']']).trim_end_matches("...")
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TokenizeError {
+    UnterminatedQuote,
+    TooManyArguments,
+}
+
+// Splits the line at whitespace. "..." and '...' group words into one argument,
+// e.g. get "Aux battery". There are no escapes.
+pub fn tokenize(line: &str) -> Result<ArrayVec<&str, MAX_ARGS>, TokenizeError> {
+    let mut tokens = ArrayVec::new();
+    let mut rest = line.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let (token, remaining) = if c == '"' || c == '\'' {
+            let quoted = &rest[1..];
+            let end = quoted.find(c).ok_or(TokenizeError::UnterminatedQuote)?;
+            (&quoted[..end], &quoted[end + 1..])
+        } else {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            (&rest[..end], &rest[end..])
+        };
+        tokens.try_push(token).map_err(|_| TokenizeError::TooManyArguments)?;
+        rest = remaining.trim_start();
+    }
+    Ok(tokens)
+}
+
+// N is the maximum number of commands
+pub struct ConsoleCommands<C, const N: usize> {
+    commands: ArrayVec<ConsoleCommand<C>, N>,
+}
+
+impl<C, const N: usize> ConsoleCommands<C, N> {
+    pub fn new() -> Self {
+        Self {
+            commands: ArrayVec::new(),
+        }
+    }
+
+    // Panics if there is no room or the name is taken. Both are mistakes in the
+    // code registering the commands.
+    pub fn register(&mut self, command: ConsoleCommand<C>) {
+        if command.name == "help" || self.get(command.name).is_some() {
+            panic!("Console command {:?} registered twice", command.name);
+        }
+        if self.commands.try_push(command).is_err() {
+            panic!("No room for console command {:?}", command.name);
+        }
+    }
+
+    pub fn register_all(&mut self, commands: &[ConsoleCommand<C>]) {
+        for command in commands {
+            self.register(*command);
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ConsoleCommand<C>> {
+        self.commands.iter().find(|command| command.name == name)
+    }
+
+    pub fn iter(&self) -> core::slice::Iter<'_, ConsoleCommand<C>> {
+        self.commands.iter()
+    }
+
+    // Runs the command on the line. Returns false if there is no such command,
+    // in which case the available commands have been listed.
+    pub fn dispatch(&self, ctx: &mut C, line: &str, hw: &mut dyn HardwareInterface) -> bool {
+        let tokens = match tokenize(line) {
+            Ok(tokens) => tokens,
+            Err(e) => {
+                info!("-> {:?}", e);
+                return true;
+            }
+        };
+        let Some((&name, args)) = tokens.split_first() else {
+            return true;
+        };
+        if name == "help" {
+            match args {
+                [] => self.list(),
+                [name] => self.help(name),
+                _ => info!("-> Usage: help [command]"),
+            }
+            return true;
+        }
+        let Some(command) = self.get(name) else {
+            info!("-> {:?} is an unknown command. Available commands:", name);
+            self.list();
+            return false;
+        };
+        if !command.accepts_arg_count(args.len()) || (command.handler)(ctx, args, hw).is_err() {
+            command.log_usage("-> Usage: ");
+        }
+        true
+    }
+
+    pub fn list(&self) {
+        info!("  help [command]  - Show help");
+        for command in &self.commands {
+            command.log_usage("  ");
+        }
+    }
+
+    pub fn help(&self, name: &str) {
+        let Some(command) = self.get(name) else {
+            info!("-> {:?} is an unknown command", name);
+            return;
+        };
+        command.log_usage("  ");
+        for line in command.help.lines().skip(1) {
+            info!("    {}", line);
+        }
+    }
+}
+
+// Completes command names, and arguments that have to be a literal word.
+// Completing other arguments is up to the app.
+impl<C, const N: usize> Completer for ConsoleCommands<C, N> {
+    fn complete(&self, words: &[&str], add: &mut dyn FnMut(&str)) {
+        match words {
+            [] | ["help"] => {
+                if words.is_empty() {
+                    add("help");
+                }
+                for command in &self.commands {
+                    add(command.name);
+                }
+            }
+            [name, args @ ..] => {
+                let spec = self.get(name).and_then(|command| command.arg_spec(args.len()));
+                if let Some(spec) = spec.filter(|spec| !spec.starts_with(['<', '['])) {
+                    for word in spec.split('|') {
+                        add(word);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<C, const N: usize> Default for ConsoleCommands<C, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Commands that only need the hardware interface, for any app to register
+pub fn hardware_commands<C>() -> [ConsoleCommand<C>; 6] {
+    [
+        ConsoleCommand {
+            name: "reboot",
+            args: "",
+            help: "Reboot",
+            handler: |_, _, hw| {
+                hw.reboot();
+                Ok(())
+            },
+        },
+        ConsoleCommand {
+            name: "dfu",
+            args: "",
+            help: "Activate DFU mode",
+            handler: |_, _, hw| {
+                hw.activate_dfu();
+                Ok(())
+            },
+        },
+        ConsoleCommand {
+            name: "panic",
+            args: "",
+            help: "Call panic!()",
+            handler: |_, _, _| panic!(),
+        },
+        ConsoleCommand {
+            name: "loglevel",
+            args: "[level] [target] [sink]",
+            help: "Show or set log levels\n\
+                    Without a target the default level is set. Targets are\n\
+                    module paths like common::sim7600 and include their\n\
+                    submodules. The sink is uart, usb or display; without one\n\
+                    all are set. Levels: off, error, warn, info, debug, trace",
+            handler: loglevel_command,
+        },
+        ConsoleCommand {
+            name: "log",
+            args: "can [filter...]",
+            help: "Toggle logging of CAN messages on console\n\
+                    Frames are logged in candump -L format with R or T at the\n\
+                    end for received and transmitted frames. Filters are in\n\
+                    candump syntax: 123:7FF passes ID 123, 100~700 passes IDs\n\
+                    not in 100...1FF. Giving filters enables logging.",
+            handler: log_command,
+        },
+        ConsoleCommand {
+            name: "slcan",
+            args: "on|off",
+            help: "Turn slcan gateway mode on or off\n\
+                    In slcan mode the USB serial port works as an slcan (Lawicel)\n\
+                    USB-CAN adapter, e.g. for slcand or SavvyCAN, instead of as a\n\
+                    console. On desktop a pty is opened instead.",
+            handler: |_, args, hw| {
+                let enabled = match args[0] {
+                    "on" => true,
+                    "off" => false,
+                    _ => return Err(InvalidArguments),
+                };
+                hw.set_slcan_enabled(enabled);
+                info!("slcan {}", if hw.slcan_enabled() { "enabled" } else { "disabled" });
+                Ok(())
+            },
+        },
+    ]
+}
+
+fn log_command<C>(_: &mut C, args: &[&str], hw: &mut dyn HardwareInterface) -> CommandResult {
+    let Some((&"can", filter_args)) = args.split_first() else {
+        return Err(InvalidArguments);
+    };
+    let mut filters: ArrayVec<CanFilter, MAX_CAN_LOG_FILTERS> = ArrayVec::new();
+    for arg in filter_args {
+        let filter = CanFilter::parse(arg).ok_or(InvalidArguments)?;
+        if filters.try_push(filter).is_err() {
+            info!("-> At most {} filters", MAX_CAN_LOG_FILTERS);
+            return Ok(());
+        }
+    }
+    let mut can_log = hw.can_log();
+    if can_log.is_enabled() && filters.is_empty() {
+        can_log.disable();
+        info!("Can logging disabled");
+    } else {
+        can_log.enable(&filters);
+        info!("Can logging enabled");
+        for filter in can_log.filters() {
+            info!("  Filter: {}", filter);
+        }
+    }
+    hw.set_can_log(can_log);
+    Ok(())
+}
+
+fn loglevel_command<C>(_: &mut C, args: &[&str], hw: &mut dyn HardwareInterface)
+        -> CommandResult {
+    let mut filter = hw.log_filter();
+    let Some((level, args)) = args.split_first() else {
+        log_levels("(default)", filter.default_levels());
+        for (target, levels) in filter.rules() {
+            log_levels(target, levels);
+        }
+        return Ok(());
+    };
+    let level: LevelFilter = level.parse().map_err(|_| InvalidArguments)?;
+    // The sink can be given without a target
+    let (target, sink) = match *args {
+        [] => (None, None),
+        [word] => match LogSink::from_name(word) {
+            Some(sink) => (None, Some(sink)),
+            None => (Some(word), None),
+        },
+        [target, sink] => (Some(target), Some(LogSink::from_name(sink).ok_or(InvalidArguments)?)),
+        _ => return Err(InvalidArguments),
+    };
+    if let Err(e) = filter.set(target, sink, level) {
+        info!("-> {:?}", e);
+        return Ok(());
+    }
+    hw.set_log_filter(filter);
+    match target {
+        Some(target) => log_levels(target, &filter.levels(target)),
+        None => log_levels("(default)", filter.default_levels()),
+    }
+    Ok(())
+}
+
+fn log_levels(target: &str, levels: &LogLevels) {
+    info!("  {: <24} uart={} usb={} display={}", target, levels[LogSink::Uart as usize],
+            levels[LogSink::Usb as usize], levels[LogSink::Display as usize]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_are_tokenized() {
+        assert!(tokenize("  set  Soc 50 ").unwrap().iter().eq(&["set", "Soc", "50"]));
+        assert!(tokenize("get \"Aux battery\" 'a b'").unwrap().iter()
+                .eq(&["get", "Aux battery", "a b"]));
+        assert!(tokenize("").unwrap().is_empty());
+        assert_eq!(tokenize("get \"Aux"), Err(TokenizeError::UnterminatedQuote));
+        assert_eq!(tokenize("a b c d e f g h i j k l m"), Err(TokenizeError::TooManyArguments));
+    }
+
+    #[test]
+    fn arg_specs_give_counts() {
+        let command = |args| ConsoleCommand::<()> {
+            name: "test",
+            args,
+            help: "",
+            handler: |_, _, _| Ok(()),
+        };
+        assert_eq!(command("").arg_count_range(), (0, Some(0)));
+        assert_eq!(command("<name> <value>").arg_count_range(), (2, Some(2)));
+        assert_eq!(command("can [level]").arg_count_range(), (1, Some(2)));
+        assert_eq!(command("<name...> [interval_ms]").arg_count_range(), (1, None));
+    }
+}
@@ -0,0 +1,470 @@
+// DBC exporter
+//
+// Writes the CAN mapped parameters as a DBC document so that the same signals
+// can be looked at in SavvyCAN, cantools and such. Both the signals ui8d
+// decodes (can_map) and the ones it publishes (tx_can_map) are included.
+// Allocation-free; writes into anything implementing core::fmt::Write.
+
+use crate::{round_f64_to_i64, CanBitSelection, CanMux, Parameter, ParameterType};
+use core::fmt::{Result, Write};
+
+// A can_map or a tx_can_map of a parameter
+#[derive(Clone, Copy)]
+struct Signal<'p, 'a> {
+    param: &'p Parameter<'a>,
+    id: bxcan::Id,
+    bits: &'p CanBitSelection,
+    scale: f32,
+    offset: f32,
+    mux: &'static [CanMux],
+    value_table: &'static [(i64, &'static str)],
+    // Some for tx_can_map
+    period_ms: Option<u64>,
+}
+
+impl Signal<'_, '_> {
+    fn is_tx(&self) -> bool {
+        self.period_ms.is_some()
+    }
+}
+
+// Every signal in definition order. A parameter's can_map comes before its
+// tx_can_map.
+fn signals<'p, 'a>(params: &'p [Parameter<'a>]) -> impl Iterator<Item = Signal<'p, 'a>> + Clone {
+    params.iter().flat_map(|param| {
+        let rx = param.can_map.as_ref().map(|can_map| Signal {
+            param,
+            id: can_map.id,
+            bits: &can_map.bits,
+            scale: can_map.scale,
+            offset: can_map.offset,
+            mux: can_map.mux,
+            value_table: can_map.value_table,
+            period_ms: None,
+        });
+        let tx = param.tx_can_map.as_ref().map(|tx_can_map| Signal {
+            param,
+            id: tx_can_map.id,
+            bits: &tx_can_map.bits,
+            scale: tx_can_map.scale,
+            offset: tx_can_map.offset,
+            mux: &[],
+            value_table: &[],
+            period_ms: Some(tx_can_map.period_ms),
+        });
+        [rx, tx].into_iter().flatten()
+    })
+}
+
+// The signals with the CAN ID, starting from the one at index i
+fn signals_with_id<'p, 'a>(
+    params: &'p [Parameter<'a>],
+    i: usize,
+    id: bxcan::Id,
+) -> impl Iterator<Item = Signal<'p, 'a>> + Clone {
+    signals(params).skip(i).filter(move |s| s.id == id)
+}
+
+fn raw_id(id: bxcan::Id) -> u32 {
+    match id {
+        bxcan::Id::Standard(id) => id.as_raw() as u32,
+        // Bit 31 marks an extended ID in DBC
+        bxcan::Id::Extended(id) => id.as_raw() | 0x8000_0000,
+    }
+}
+
+// Signal names have to be C identifiers. Parameter names already are, but make
+// sure anyway.
+fn write_identifier<W: Write>(w: &mut W, name: &str) -> Result {
+    for c in name.chars() {
+        w.write_char(if c.is_ascii_alphanumeric() { c } else { '_' })?;
+    }
+    Ok(())
+}
+
+fn msb0_to_dbc(i0: u8) -> u8 {
+    (i0 / 8) * 8 + (7 - i0 % 8)
+}
+
+// Returns start bit, length, little endian and signed in DBC terms
+fn dbc_bits(bits: &CanBitSelection) -> Option<(u8, u8, bool, bool)> {
+    match *bits {
+        CanBitSelection::Bit(bit_i) => Some((bit_i, 1, true, false)),
+        CanBitSelection::LeUnsigned(i0, len) => Some((i0, len, true, false)),
+        CanBitSelection::LeSigned(i0, len) => Some((i0, len, true, true)),
+        // Be* uses the Msb0 index of the MSB, while DBC Motorola uses the Lsb0
+        // index of the MSB within its byte
+        CanBitSelection::BeUnsigned(i0, len) => Some((msb0_to_dbc(i0), len, false, false)),
+        CanBitSelection::BeSigned(i0, len) => Some((msb0_to_dbc(i0), len, false, true)),
+        CanBitSelection::Uint8(byte_i) => Some((byte_i * 8, 8, true, false)),
+        CanBitSelection::Int8(byte_i) => Some((byte_i * 8, 8, true, true)),
+        // Marked as float with SIG_VALTYPE_
+        CanBitSelection::LeFloat32(i0) => Some((i0, 32, true, true)),
+        CanBitSelection::BeFloat32(i0) => Some((msb0_to_dbc(i0), 32, false, true)),
+        CanBitSelection::Function(..) => None,
+    }
+}
+
+fn write_signal_name<W: Write>(w: &mut W, signal: &Signal) -> Result {
+    write_identifier(w, signal.param.name)?;
+    if let CanBitSelection::Function(..) = signal.bits {
+        w.write_str("_PLACEHOLDER")?;
+    }
+    Ok(())
+}
+
+// Multiplexer selections don't have names in ui8d, so they are named after
+// their bit position
+fn write_mux_name<W: Write>(w: &mut W, bits: &CanBitSelection) -> Result {
+    let (start, len, _, _) = dbc_bits(bits).unwrap_or((0, 0, true, false));
+    write!(w, "Mux_{}_{}", start, len)
+}
+
+fn same_bits(a: &CanBitSelection, b: &CanBitSelection) -> bool {
+    dbc_bits(a).is_some() && dbc_bits(a) == dbc_bits(b)
+}
+
+fn write_bits<W: Write>(
+    w: &mut W,
+    bits: &CanBitSelection,
+    scale: f32,
+    offset: f32,
+    unit: &str,
+) -> Result {
+    let (start, len, little_endian, signed) =
+        // The layout is hidden inside a function. Export a clearly named 1-bit
+        // placeholder so that the signal at least shows up.
+        dbc_bits(bits).unwrap_or((0, 1, true, false));
+    writeln!(
+        w,
+        " : {}|{}@{}{} ({},{}) [0|0] \"{}\" Vector__XXX",
+        start,
+        len,
+        if little_endian { 1 } else { 0 },
+        if signed { '-' } else { '+' },
+        scale,
+        offset,
+        unit
+    )
+}
+
+fn write_signal<W: Write>(w: &mut W, signal: &Signal) -> Result {
+    w.write_str(" SG_ ")?;
+    write_signal_name(w, signal)?;
+    if let Some(mux) = signal.mux.last() {
+        write!(w, " m{}", mux.value)?;
+    }
+    write_bits(w, signal.bits, signal.scale, signal.offset, signal.param.unit)
+}
+
+// Calls f for every multiplexer of the CAN ID the first time its bits are seen,
+// with the multiplexer that selects it, if any
+fn for_each_mux<F>(params: &[Parameter], id: bxcan::Id, mut f: F) -> Result
+where
+    F: FnMut(&CanMux, Option<&CanMux>) -> Result,
+{
+    let maps = signals_with_id(params, 0, id);
+    for (i, signal) in maps.clone().enumerate() {
+        for (depth, mux) in signal.mux.iter().enumerate() {
+            let seen_before = maps.clone().take(i + 1).enumerate().any(|(i2, s)| {
+                s.mux
+                    .iter()
+                    .take(if i2 == i { depth } else { s.mux.len() })
+                    .any(|mux2| same_bits(&mux2.bits, &mux.bits))
+            });
+            if !seen_before {
+                f(mux, depth.checked_sub(1).map(|d| &signal.mux[d]))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Plain DBC multiplexing allows a single multiplexer per message. Anything
+// else needs SG_MUL_VAL_ to tell which multiplexer selects which signal.
+fn needs_extended_mux(params: &[Parameter], id: bxcan::Id) -> bool {
+    let mut top_level: Option<&CanBitSelection> = None;
+    for signal in signals_with_id(params, 0, id) {
+        if signal.mux.is_empty() {
+            continue;
+        }
+        if signal.mux.len() > 1 {
+            return true;
+        }
+        match top_level {
+            Some(bits) if !same_bits(bits, &signal.mux[0].bits) => return true,
+            _ => top_level = Some(&signal.mux[0].bits),
+        }
+    }
+    false
+}
+
+fn write_comment<W: Write>(w: &mut W, signal: &Signal) -> Result {
+    write!(w, "CM_ SG_ {} ", raw_id(signal.id))?;
+    write_signal_name(w, signal)?;
+    if let CanBitSelection::Function(..) = signal.bits {
+        writeln!(
+            w,
+            " \"{}: PLACEHOLDER, decoded by a custom function in ui8d. \
+                Bit layout unknown.\";",
+            signal.param.display_name
+        )
+    } else if signal.is_tx() {
+        writeln!(w, " \"{}, sent by ui8d\";", signal.param.display_name)
+    } else {
+        writeln!(w, " \"{}\";", signal.param.display_name)
+    }
+}
+
+fn write_value_type<W: Write>(w: &mut W, signal: &Signal) -> Result {
+    if let CanBitSelection::LeFloat32(_) | CanBitSelection::BeFloat32(_) = signal.bits {
+        write!(w, "SIG_VALTYPE_ {} ", raw_id(signal.id))?;
+        write_signal_name(w, signal)?;
+        w.write_str(" : 1;\n")?;
+    }
+    Ok(())
+}
+
+// Enum parameters export their labels, others the CanMap value table
+fn write_value_table<W: Write>(w: &mut W, signal: &Signal) -> Result {
+    let enum_labels = match signal.param.value_type {
+        ParameterType::Enum(labels) => labels,
+        _ => &[],
+    };
+    if signal.value_table.is_empty() && enum_labels.is_empty() {
+        return Ok(());
+    }
+    write!(w, "VAL_ {} ", raw_id(signal.id))?;
+    write_signal_name(w, signal)?;
+    if enum_labels.is_empty() {
+        for (raw, label) in signal.value_table {
+            write!(w, " {} \"{}\"", raw, label)?;
+        }
+    } else {
+        for (value, label) in enum_labels {
+            // A label that no raw value decodes to can't be in the table
+            if let Some(raw) = enum_raw_value(signal, *value) {
+                write!(w, " {} \"{}\"", raw, label)?;
+            }
+        }
+    }
+    w.write_str(" ;\n")
+}
+
+// The raw value that decodes to the enum value, rounding like
+// CanMap::decode_value()
+fn enum_raw_value(signal: &Signal, value: u32) -> Option<i64> {
+    let (scale, offset) = (signal.scale as f64, signal.offset as f64);
+    let raw = round_f64_to_i64((value as f64 - offset) / scale);
+    (round_f64_to_i64(raw as f64 * scale + offset) == value as i64).then_some(raw)
+}
+
+// Returns true if an earlier signal already uses the same CAN ID
+fn id_seen_before(params: &[Parameter], i: usize, id: bxcan::Id) -> bool {
+    signals(params).take(i).any(|s| s.id == id)
+}
+
+pub fn write_dbc<W: Write>(w: &mut W, params: &[Parameter]) -> Result {
+    w.write_str("VERSION \"\"\n\nNS_ :\n\nBS_:\n\nBU_: ui8d\n\n")?;
+
+    // One BO_ per CAN ID, with all parameters mapped to that ID as signals.
+    // Frames that ui8d publishes have ui8d as the transmitter.
+    for (i, signal) in signals(params).enumerate() {
+        if id_seen_before(params, i, signal.id) {
+            continue;
+        }
+        let raw = raw_id(signal.id);
+        let transmitter = if signals_with_id(params, i, signal.id).any(|s| s.is_tx()) {
+            "ui8d"
+        } else {
+            "Vector__XXX"
+        };
+        writeln!(w, "BO_ {} ID_{:X}: 8 {}", raw, raw & 0x1fff_ffff, transmitter)?;
+        for_each_mux(params, signal.id, |mux, outer| {
+            w.write_str(" SG_ ")?;
+            write_mux_name(w, &mux.bits)?;
+            match outer {
+                Some(outer) => write!(w, " m{}M", outer.value)?,
+                None => w.write_str(" M")?,
+            }
+            write_bits(w, &mux.bits, 1.0, 0.0, "")
+        })?;
+        for signal2 in signals_with_id(params, i, signal.id) {
+            write_signal(w, &signal2)?;
+        }
+        w.write_str("\n")?;
+    }
+
+    for signal in signals(params) {
+        write_comment(w, &signal)?;
+    }
+
+    // The period of a published frame is the period of its first parameter.
+    // See can_tx::TxCanScheduler.
+    if signals(params).any(|s| s.is_tx()) {
+        w.write_str("BA_DEF_ BO_ \"GenMsgCycleTime\" INT 0 65535;\n")?;
+        w.write_str("BA_DEF_DEF_ \"GenMsgCycleTime\" 0;\n")?;
+    }
+    for (i, signal) in signals(params).enumerate() {
+        let Some(period_ms) = signal.period_ms else {
+            continue;
+        };
+        let first_tx = signals(params).take(i).all(|s| !(s.is_tx() && s.id == signal.id));
+        if first_tx {
+            writeln!(w, "BA_ \"GenMsgCycleTime\" BO_ {} {};", raw_id(signal.id), period_ms)?;
+        }
+    }
+
+    for signal in signals(params) {
+        write_value_type(w, &signal)?;
+    }
+
+    for signal in signals(params) {
+        write_value_table(w, &signal)?;
+    }
+
+    for (i, signal) in signals(params).enumerate() {
+        if id_seen_before(params, i, signal.id) || !needs_extended_mux(params, signal.id) {
+            continue;
+        }
+        let raw = raw_id(signal.id);
+        for_each_mux(params, signal.id, |mux, outer| {
+            if let Some(outer) = outer {
+                write!(w, "SG_MUL_VAL_ {} ", raw)?;
+                write_mux_name(w, &mux.bits)?;
+                w.write_char(' ')?;
+                write_mux_name(w, &outer.bits)?;
+                writeln!(w, " {}-{};", outer.value, outer.value)?;
+            }
+            Ok(())
+        })?;
+        for signal2 in signals_with_id(params, i, signal.id) {
+            if let Some(mux) = signal2.mux.last() {
+                write!(w, "SG_MUL_VAL_ {} ", raw)?;
+                write_signal_name(w, &signal2)?;
+                w.write_char(' ')?;
+                write_mux_name(w, &mux.bits)?;
+                writeln!(w, " {}-{};", mux.value, mux.value)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dbc_import::{Dbc, DbcImport};
+    use crate::{extended_id, standard_id, CanMap, TxCanMap};
+    use std::string::String;
+
+    #[test]
+    fn signals_round_trip() {
+        static CELL_MUX: [CanMux; 1] = [CanMux { bits: CanBitSelection::Uint8(0), value: 3 }];
+        static NESTED_MUX: [CanMux; 2] = [
+            CanMux { bits: CanBitSelection::Uint8(0), value: 4 },
+            CanMux { bits: CanBitSelection::Uint8(1), value: 2 },
+        ];
+        let rx = |id, bits, scale, offset| Some(CanMap { id, bits, scale, offset, ..CanMap::DEFAULT });
+        let tx = |id, bits, period_ms| Some(TxCanMap { id, bits, period_ms, ..TxCanMap::DEFAULT });
+        let mut params = [
+            Parameter::new(0, "Soc", "SoC", f32::NAN, 0, "%",
+                    rx(standard_id(0x100), CanBitSelection::LeUnsigned(0, 8), 0.5, 0.0)),
+            Parameter::new(1, "Current", "Current", f32::NAN, 0, "A",
+                    rx(standard_id(0x100), CanBitSelection::BeSigned(12, 12), 0.1, -10.0)),
+            Parameter::new(2, "Status", "Status", f32::NAN, 0, "",
+                    rx(standard_id(0x100), CanBitSelection::Uint8(7), 1.0, 0.0)),
+            Parameter::new(3, "Power", "Power", f32::NAN, 0, "W",
+                    rx(extended_id(0x800), CanBitSelection::BeFloat32(0), 1.0, 0.0)),
+            Parameter::new(4, "CellV", "Cell voltage", f32::NAN, 0, "V",
+                    rx(standard_id(0x200), CanBitSelection::LeUnsigned(8, 16), 0.001, 0.0)),
+            Parameter::new(5, "CellT", "Cell temperature", f32::NAN, 0, "C",
+                    rx(standard_id(0x200), CanBitSelection::Int8(2), 1.0, 0.0)),
+            Parameter::new(6, "Custom", "Custom", f32::NAN, 0, "",
+                    rx(standard_id(0x300), CanBitSelection::Function(2, |d| d[1] as f32),
+                    1.0, 0.0)),
+            Parameter::new(7, "CabinT", "Cabin temperature", f32::NAN, 0, "C", None),
+            Parameter::new(8, "Heater", "Heater", f32::NAN, 0, "", None),
+        ];
+        params[7].tx_can_map = tx(standard_id(0x404), CanBitSelection::Int8(1), 500);
+        params[8].tx_can_map = tx(standard_id(0x404), CanBitSelection::LeFloat32(16), 100);
+        params[2].can_map.as_mut().unwrap().value_table = &[(0, "Off"), (34, "Running")];
+        params[4].can_map.as_mut().unwrap().mux = &CELL_MUX;
+        params[5].can_map.as_mut().unwrap().mux = &NESTED_MUX;
+
+        let mut text = String::new();
+        write_dbc(&mut text, &params).unwrap();
+        assert!(text.contains("BO_ 256 ID_100: 8 Vector__XXX\n"), "{}", text);
+        assert!(text.contains("BO_ 1028 ID_404: 8 ui8d\n"), "{}", text);
+        assert!(text.contains("CM_ SG_ 1028 CabinT \"Cabin temperature, sent by ui8d\";"), "{}", text);
+        // The period of the first parameter of the frame
+        assert!(text.contains("BA_ \"GenMsgCycleTime\" BO_ 1028 500;\n"), "{}", text);
+        assert!(!text.contains("BO_ 1028 100;\n"), "{}", text);
+
+        let dbc = Dbc::parse(&text).unwrap();
+        assert!(dbc.messages.iter().map(|m| (m.id, m.extended))
+                .eq([(0x100, false), (0x800, true), (0x200, false), (0x300, false), (0x404, false)]));
+        let (_, status) = dbc.find_signal("Status").unwrap();
+        assert_eq!(status.value_table, [(0, "Off".into()), (34, "Running".into())]);
+        let (_, custom) = dbc.find_signal("Custom_PLACEHOLDER").unwrap();
+        assert_eq!((custom.start_bit, custom.length), (0, 1));
+        let (message, cell_t) = dbc.find_signal("CellT").unwrap();
+        let mux: std::vec::Vec<_> = message.mux_conditions(cell_t).unwrap().iter()
+                .map(|(signal, value)| (signal.start_bit, *value)).collect();
+        assert_eq!(mux, [(0, 4), (8, 2)]);
+
+        // Importing gives back the same bit selections
+        let mut import = DbcImport::new(&text).unwrap();
+        for name in ["Soc", "Current", "Status", "Power", "CellV", "CellT", "CabinT", "Heater"] {
+            import.signal(name);
+        }
+        let entries = import.generate_entries().unwrap();
+        for expected in [
+            "id: standard_id(0x100),\n            bits: CanBitSelection::LeUnsigned(0, 8),\n            scale: 0.5,",
+            "bits: CanBitSelection::BeSigned(12, 12),\n            scale: 0.1,\n            offset: -10.0,",
+            "bits: CanBitSelection::LeUnsigned(56, 8),",
+            "value_table: &[(0, \"Off\"), (34, \"Running\")],",
+            "id: extended_id(0x800),\n            bits: CanBitSelection::BeFloat32(0),",
+            "bits: CanBitSelection::LeUnsigned(8, 16),\n            scale: 0.001,\n            \
+                mux: &[CanMux { bits: CanBitSelection::LeUnsigned(0, 8), value: 3 }],",
+            "bits: CanBitSelection::LeSigned(16, 8),\n            scale: 1.0,\n            \
+                mux: &[CanMux { bits: CanBitSelection::LeUnsigned(0, 8), value: 4 }, \
+                CanMux { bits: CanBitSelection::LeUnsigned(8, 8), value: 2 }],",
+            "id: standard_id(0x404),\n            bits: CanBitSelection::LeSigned(8, 8),",
+            "bits: CanBitSelection::LeFloat32(16),",
+        ] {
+            assert!(entries.contains(expected), "{}\n{}", expected, entries);
+        }
+    }
+
+    #[test]
+    fn enum_labels_round_trip() {
+        let mut params = [
+            Parameter::new(0, "Gear", "Gear", f32::NAN, 0, "", Some(CanMap {
+                id: standard_id(0x100),
+                bits: CanBitSelection::Int8(0),
+                scale: 2.0,
+                offset: -2.0,
+                ..CanMap::DEFAULT
+            })),
+        ];
+        params[0].value_type = ParameterType::Enum(&[(0, "Reverse"), (2, "Neutral"),
+                (3, "Odd"), (4, "Drive")]);
+
+        let mut text = String::new();
+        write_dbc(&mut text, &params).unwrap();
+        // Odd would need raw 2.5
+        assert!(text.contains("VAL_ 256 Gear 1 \"Reverse\" 2 \"Neutral\" 3 \"Drive\" ;\n"),
+                "{}", text);
+
+        // Each raw value in the table decodes back to its label
+        let dbc = Dbc::parse(&text).unwrap();
+        let (_, gear) = dbc.find_signal("Gear").unwrap();
+        for (raw, label) in &gear.value_table {
+            let can_map = params[0].can_map.as_ref().unwrap();
+            params[0].value = can_map.decode_value(&[*raw as u8], params[0].value_type).unwrap();
+            assert_eq!(params[0].value_label(), Some(label.as_str()));
+        }
+        assert_eq!(gear.value_table.len(), 3);
+    }
+}
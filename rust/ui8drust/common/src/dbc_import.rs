@@ -0,0 +1,781 @@
+// DBC importer for build scripts
+//
+// Reads a DBC file and generates entries for define_parameters!. This needs
+// std, so enable the "std" feature of common in [build-dependencies] and call
+// this from the application's build.rs. See README.txt for an example.
+
+use std::fmt::Write;
+use std::format;
+use std::string::{String, ToString};
+use std::vec::Vec;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DbcError {
+    pub line: usize, // 0 if the error isn't tied to a line
+    pub message: String,
+}
+
+impl std::fmt::Display for DbcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.line > 0 {
+            write!(f, "DBC line {}: {}", self.line, self.message)
+        } else {
+            write!(f, "DBC: {}", self.message)
+        }
+    }
+}
+
+impl std::error::Error for DbcError {}
+
+fn error(line: usize, message: &str) -> DbcError {
+    DbcError {
+        line,
+        message: message.to_string(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DbcSignal {
+    pub name: String,
+    pub start_bit: u8,
+    pub length: u8,
+    pub little_endian: bool, // @1 = Intel, @0 = Motorola
+    pub signed: bool,
+    pub scale: f64,
+    pub offset: f64,
+    pub min: f64,
+    pub max: f64,
+    pub unit: String,
+    pub multiplexer: bool,      // M: Selects which other signals are present
+    pub mux_value: Option<u32>, // mN: Present when the multiplexer equals N
+    pub float: bool,            // SIG_VALTYPE_ 1: IEEE float32
+    pub value_table: Vec<(i64, String)>, // VAL_: Labels for raw values
+}
+
+// SG_MUL_VAL_: Names the multiplexer of a signal explicitly. Needed for nested
+// multiplexers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DbcExtendedMux {
+    pub signal: String,
+    pub multiplexer: String,
+    pub min: u32,
+    pub max: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DbcMessage {
+    pub id: u32,
+    pub extended: bool,
+    pub name: String,
+    pub dlc: u8,
+    pub signals: Vec<DbcSignal>,
+    pub extended_mux: Vec<DbcExtendedMux>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dbc {
+    pub messages: Vec<DbcMessage>,
+}
+
+// Splits "a|b" style pairs
+fn split_pair(s: &str, separator: char, line: usize) -> Result<(&str, &str), DbcError> {
+    s.split_once(separator)
+        .ok_or_else(|| error(line, &format!("Expected '{}' in {:?}", separator, s)))
+}
+
+fn parse_number<T: std::str::FromStr>(s: &str, line: usize) -> Result<T, DbcError> {
+    s.trim()
+        .parse::<T>()
+        .map_err(|_| error(line, &format!("Invalid number {:?}", s)))
+}
+
+// Returns the contents between open and close, and the rest after close
+fn take_delimited(
+    s: &str,
+    open: char,
+    close: char,
+    line: usize,
+) -> Result<(&str, &str), DbcError> {
+    let s = s.trim_start();
+    let s = s
+        .strip_prefix(open)
+        .ok_or_else(|| error(line, &format!("Expected '{}' in {:?}", open, s)))?;
+    let end = s
+        .find(close)
+        .ok_or_else(|| error(line, &format!("Expected '{}' in {:?}", close, s)))?;
+    Ok((&s[..end], &s[end + 1..]))
+}
+
+fn parse_message(rest: &str, line: usize) -> Result<DbcMessage, DbcError> {
+    // BO_ 256 BMS_Status: 8 BMS
+    let (id_s, rest) = rest
+        .trim()
+        .split_once(char::is_whitespace)
+        .ok_or_else(|| error(line, "Invalid BO_"))?;
+    let (name, rest) = split_pair(rest, ':', line)?;
+    let raw_id: u32 = parse_number(id_s, line)?;
+    let dlc_s = rest.split_whitespace().next().unwrap_or("8");
+    Ok(DbcMessage {
+        // Bit 31 marks an extended ID
+        id: raw_id & 0x1fff_ffff,
+        extended: raw_id & 0x8000_0000 != 0,
+        name: name.trim().to_string(),
+        dlc: parse_number(dlc_s, line)?,
+        signals: Vec::new(),
+        extended_mux: Vec::new(),
+    })
+}
+
+fn parse_signal(rest: &str, line: usize) -> Result<DbcSignal, DbcError> {
+    // SG_ Soc : 55|8@0+ (0.4,0) [0|100] "%" Vector__XXX
+    // SG_ CellVoltage m3 : 8|16@1+ (0.001,0) [0|5] "V" Vector__XXX
+    let (left, right) = split_pair(rest, ':', line)?;
+    let mut left_parts = left.split_whitespace();
+    let name = left_parts
+        .next()
+        .ok_or_else(|| error(line, "Signal without a name"))?;
+    let mut multiplexer = false;
+    let mut mux_value = None;
+    if let Some(indicator) = left_parts.next() {
+        if indicator == "M" {
+            multiplexer = true;
+        } else if let Some(value_s) = indicator.strip_prefix('m') {
+            // "m3M" is a multiplexer that is itself multiplexed
+            let value_s = match value_s.strip_suffix('M') {
+                Some(value_s) => {
+                    multiplexer = true;
+                    value_s
+                }
+                None => value_s,
+            };
+            mux_value = Some(parse_number(value_s, line)?);
+        } else {
+            return Err(error(line, &format!("Invalid multiplexer indicator {:?}", indicator)));
+        }
+    }
+
+    let right = right.trim_start();
+    let (bits_s, right) = right
+        .split_once(char::is_whitespace)
+        .ok_or_else(|| error(line, "Invalid SG_"))?;
+    let (start_s, bits_s) = split_pair(bits_s, '|', line)?;
+    let (length_s, format_s) = split_pair(bits_s, '@', line)?;
+    let little_endian = match format_s.chars().next() {
+        Some('1') => true,
+        Some('0') => false,
+        _ => return Err(error(line, "Invalid byte order")),
+    };
+    let signed = match format_s.chars().nth(1) {
+        Some('-') => true,
+        Some('+') => false,
+        _ => return Err(error(line, "Invalid signedness")),
+    };
+
+    let (factor_s, right) = take_delimited(right, '(', ')', line)?;
+    let (scale_s, offset_s) = split_pair(factor_s, ',', line)?;
+    let (range_s, right) = take_delimited(right, '[', ']', line)?;
+    let (min_s, max_s) = split_pair(range_s, '|', line)?;
+    let (unit, _receivers) = take_delimited(right, '"', '"', line)?;
+
+    Ok(DbcSignal {
+        name: name.to_string(),
+        start_bit: parse_number(start_s, line)?,
+        length: parse_number(length_s, line)?,
+        little_endian,
+        signed,
+        scale: parse_number(scale_s, line)?,
+        offset: parse_number(offset_s, line)?,
+        min: parse_number(min_s, line)?,
+        max: parse_number(max_s, line)?,
+        unit: unit.to_string(),
+        multiplexer,
+        mux_value,
+        float: false,
+        value_table: Vec::new(),
+    })
+}
+
+// Returns the message ID, the signal name and the rest of VAL_ and
+// SIG_VALTYPE_ lines
+fn parse_signal_reference(rest: &str, line: usize) -> Result<(u32, &str, &str), DbcError> {
+    let rest = rest.trim_start();
+    let (id_s, rest) = rest
+        .split_once(char::is_whitespace)
+        .ok_or_else(|| error(line, "Expected message ID and signal name"))?;
+    let rest = rest.trim_start();
+    let end = rest
+        .find(|c: char| c.is_whitespace() || c == ':' || c == ';')
+        .unwrap_or(rest.len());
+    Ok((parse_number(id_s, line)?, &rest[..end], &rest[end..]))
+}
+
+fn parse_value_table(rest: &str, line: usize) -> Result<Vec<(i64, String)>, DbcError> {
+    // 0 "Off" 34 "Running" ;
+    let mut value_table = Vec::new();
+    let mut rest = rest.trim();
+    while !rest.is_empty() && rest != ";" {
+        let (raw_s, after) = rest
+            .split_once(char::is_whitespace)
+            .ok_or_else(|| error(line, "Invalid VAL_"))?;
+        let (label, after) = take_delimited(after, '"', '"', line)?;
+        value_table.push((parse_number(raw_s, line)?, label.to_string()));
+        rest = after.trim();
+    }
+    Ok(value_table)
+}
+
+fn parse_extended_mux(rest: &str, line: usize) -> Result<(u32, DbcExtendedMux), DbcError> {
+    // SG_MUL_VAL_ 2024 CellVoltage CellGroup 3-3;
+    let rest = rest.trim().trim_end_matches(';');
+    let mut parts = rest.split_whitespace();
+    let (Some(id_s), Some(signal), Some(multiplexer), Some(range_s)) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(error(line, "Invalid SG_MUL_VAL_"));
+    };
+    if parts.next().is_some() {
+        return Err(error(line, "Multiple multiplexer value ranges are not supported"));
+    }
+    let (min_s, max_s) = split_pair(range_s, '-', line)?;
+    Ok((
+        parse_number(id_s, line)?,
+        DbcExtendedMux {
+            signal: signal.to_string(),
+            multiplexer: multiplexer.to_string(),
+            min: parse_number(min_s, line)?,
+            max: parse_number(max_s, line)?,
+        },
+    ))
+}
+
+fn find_signal_mut<'a>(
+    messages: &'a mut [DbcMessage],
+    raw_id: u32,
+    signal_name: &str,
+    line: usize,
+) -> Result<&'a mut DbcSignal, DbcError> {
+    let id = raw_id & 0x1fff_ffff;
+    let extended = raw_id & 0x8000_0000 != 0;
+    messages
+        .iter_mut()
+        .filter(|m| m.id == id && m.extended == extended)
+        .flat_map(|m| m.signals.iter_mut())
+        .find(|s| s.name == signal_name)
+        .ok_or_else(|| error(line, &format!("Unknown signal {:?}", signal_name)))
+}
+
+impl Dbc {
+    pub fn parse(source: &str) -> Result<Self, DbcError> {
+        let mut messages: Vec<DbcMessage> = Vec::new();
+        for (i, raw_line) in source.lines().enumerate() {
+            let line = i + 1;
+            let trimmed = raw_line.trim();
+            if let Some(rest) = trimmed.strip_prefix("BO_ ") {
+                messages.push(parse_message(rest, line)?);
+            } else if let Some(rest) = trimmed.strip_prefix("SG_ ") {
+                let signal = parse_signal(rest, line)?;
+                match messages.last_mut() {
+                    Some(message) => message.signals.push(signal),
+                    None => return Err(error(line, "SG_ outside of BO_")),
+                }
+            } else if let Some(rest) = trimmed.strip_prefix("VAL_ ") {
+                let (raw_id, signal_name, rest) = parse_signal_reference(rest, line)?;
+                let value_table = parse_value_table(rest, line)?;
+                find_signal_mut(&mut messages, raw_id, signal_name, line)?.value_table =
+                    value_table;
+            } else if let Some(rest) = trimmed.strip_prefix("SIG_VALTYPE_ ") {
+                // SIG_VALTYPE_ 256 Power : 1;
+                let (raw_id, signal_name, rest) = parse_signal_reference(rest, line)?;
+                let value_type = rest.trim_start_matches([' ', ':']).trim_end_matches(';');
+                let float = match value_type.trim() {
+                    "0" => false,
+                    "1" => true,
+                    _ => return Err(error(line, "Only float32 signals are supported")),
+                };
+                find_signal_mut(&mut messages, raw_id, signal_name, line)?.float = float;
+            } else if let Some(rest) = trimmed.strip_prefix("SG_MUL_VAL_ ") {
+                let (raw_id, extended_mux) = parse_extended_mux(rest, line)?;
+                let id = raw_id & 0x1fff_ffff;
+                let extended = raw_id & 0x8000_0000 != 0;
+                match messages.iter_mut().find(|m| m.id == id && m.extended == extended) {
+                    Some(message) => message.extended_mux.push(extended_mux),
+                    None => return Err(error(line, "SG_MUL_VAL_ for an unknown BO_")),
+                }
+            }
+            // Everything else (nodes, comments, attributes...) is ignored
+        }
+        Ok(Self { messages })
+    }
+
+    // Finds a signal either by "Message.Signal" or by an unique signal name
+    pub fn find_signal(&self, path: &str) -> Result<(&DbcMessage, &DbcSignal), DbcError> {
+        let (message_name, signal_name) = match path.split_once('.') {
+            Some((m, s)) => (Some(m), s),
+            None => (None, path),
+        };
+        let mut found: Option<(&DbcMessage, &DbcSignal)> = None;
+        for message in &self.messages {
+            if message_name.is_some() && message_name != Some(message.name.as_str()) {
+                continue;
+            }
+            for signal in &message.signals {
+                if signal.name == signal_name {
+                    if found.is_some() {
+                        return Err(error(0, &format!(
+                            "Signal {:?} is ambiguous; use Message.Signal", path)));
+                    }
+                    found = Some((message, signal));
+                }
+            }
+        }
+        found.ok_or_else(|| error(0, &format!("Signal {:?} not found", path)))
+    }
+}
+
+impl DbcMessage {
+    fn signal(&self, name: &str) -> Option<&DbcSignal> {
+        self.signals.iter().find(|s| s.name == name)
+    }
+
+    // Returns the multiplexer signal and value that select the signal, if any
+    fn multiplexer_of(&self, signal: &DbcSignal) -> Result<Option<(&DbcSignal, u32)>, DbcError> {
+        let line_name = format!("{}.{}", self.name, signal.name);
+        if let Some(extended_mux) = self.extended_mux.iter().find(|e| e.signal == signal.name) {
+            if extended_mux.min != extended_mux.max {
+                return Err(error(0, &format!(
+                    "{}: Multiplexer value ranges are not supported", line_name)));
+            }
+            let multiplexer = self.signal(&extended_mux.multiplexer).ok_or_else(|| {
+                error(0, &format!(
+                    "{}: Multiplexer {:?} not found", line_name, extended_mux.multiplexer))
+            })?;
+            return Ok(Some((multiplexer, extended_mux.min)));
+        }
+        let Some(value) = signal.mux_value else {
+            return Ok(None);
+        };
+        // Without SG_MUL_VAL_ there has to be exactly one top level multiplexer
+        let mut candidates = self
+            .signals
+            .iter()
+            .filter(|s| s.multiplexer && s.mux_value.is_none());
+        match (candidates.next(), candidates.next()) {
+            (Some(multiplexer), None) => Ok(Some((multiplexer, value))),
+            _ => Err(error(0, &format!(
+                "{}: Can't tell which multiplexer selects this signal; add SG_MUL_VAL_",
+                line_name))),
+        }
+    }
+
+    // Returns the multiplexers and values that select the signal, outermost
+    // first
+    pub fn mux_conditions(&self, signal: &DbcSignal) -> Result<Vec<(&DbcSignal, u32)>, DbcError> {
+        let mut conditions = Vec::new();
+        let mut current = signal;
+        while let Some((multiplexer, value)) = self.multiplexer_of(current)? {
+            if conditions.len() >= 8 {
+                return Err(error(0, &format!(
+                    "{}.{}: Multiplexers nested too deep", self.name, signal.name)));
+            }
+            conditions.insert(0, (multiplexer, value));
+            current = multiplexer;
+        }
+        Ok(conditions)
+    }
+}
+
+// Converts e.g. "BMS_MaxChargeCurrent" to "BmsMaxChargeCurrent"
+fn to_parameter_ident(name: &str) -> String {
+    let mut ident = String::new();
+    for part in name.split(|c: char| !c.is_ascii_alphanumeric()) {
+        let mut chars = part.chars();
+        if let Some(first) = chars.next() {
+            ident.push(first.to_ascii_uppercase());
+            let rest: String = chars.collect();
+            if rest.chars().all(|c| !c.is_ascii_lowercase()) {
+                ident.push_str(&rest.to_ascii_lowercase());
+            } else {
+                ident.push_str(&rest);
+            }
+        }
+    }
+    if ident.chars().next().is_none_or(|c| c.is_ascii_digit()) {
+        ident.insert(0, 'S');
+    }
+    ident
+}
+
+// Enough decimals to show one step of the scale
+fn decimals_for_scale(scale: f64) -> u8 {
+    let mut decimals = 0;
+    let mut step = scale.abs();
+    while step > 0.0 && step < 0.999 && decimals < 4 {
+        step *= 10.0;
+        decimals += 1;
+    }
+    decimals
+}
+
+pub struct SignalImport {
+    path: String,
+    ident: Option<String>,
+    display_name: Option<String>,
+    decimals: Option<u8>,
+    report_name: Option<String>,
+    report_decimals: Option<u8>,
+    report_scale: f32,
+}
+
+impl SignalImport {
+    // Name of the ParameterId variant. Defaults to the signal name in CamelCase
+    pub fn name(&mut self, ident: &str) -> &mut Self {
+        self.ident = Some(ident.to_string());
+        self
+    }
+
+    // Defaults to the signal name
+    pub fn display_name(&mut self, display_name: &str) -> &mut Self {
+        self.display_name = Some(display_name.to_string());
+        self
+    }
+
+    // Defaults to what is needed for showing one step of the DBC factor
+    pub fn decimals(&mut self, decimals: u8) -> &mut Self {
+        self.decimals = Some(decimals);
+        self
+    }
+
+    // Adds a ReportMap. No report is generated by default
+    pub fn report_name(&mut self, report_name: &str) -> &mut Self {
+        self.report_name = Some(report_name.to_string());
+        self
+    }
+
+    pub fn report_decimals(&mut self, decimals: u8) -> &mut Self {
+        self.report_decimals = Some(decimals);
+        self
+    }
+
+    pub fn report_scale(&mut self, scale: f32) -> &mut Self {
+        self.report_scale = scale;
+        self
+    }
+}
+
+pub struct DbcImport {
+    dbc: Dbc,
+    imports: Vec<SignalImport>,
+}
+
+impl DbcImport {
+    pub fn new(dbc_source: &str) -> Result<Self, DbcError> {
+        Ok(Self {
+            dbc: Dbc::parse(dbc_source)?,
+            imports: Vec::new(),
+        })
+    }
+
+    pub fn dbc(&self) -> &Dbc {
+        &self.dbc
+    }
+
+    // Selects a signal for import, by "Message.Signal" or by signal name
+    pub fn signal(&mut self, path: &str) -> &mut SignalImport {
+        self.imports.push(SignalImport {
+            path: path.to_string(),
+            ident: None,
+            display_name: None,
+            decimals: None,
+            report_name: None,
+            report_decimals: None,
+            report_scale: 1.0,
+        });
+        self.imports.last_mut().unwrap()
+    }
+
+    // Selects every signal of a message for import
+    pub fn message(&mut self, message_name: &str) -> Result<(), DbcError> {
+        let signal_names: Vec<String> = self
+            .dbc
+            .messages
+            .iter()
+            .find(|m| m.name == message_name)
+            .ok_or_else(|| error(0, &format!("Message {:?} not found", message_name)))?
+            .signals
+            .iter()
+            .map(|s| format!("{}.{}", message_name, s.name))
+            .collect();
+        for name in signal_names {
+            self.signal(&name);
+        }
+        Ok(())
+    }
+
+    // Generates the entries to be placed inside define_parameters! { ... }
+    pub fn generate_entries(&self) -> Result<String, DbcError> {
+        let mut out = String::new();
+        for import in &self.imports {
+            let (message, signal) = self.dbc.find_signal(&import.path)?;
+            write_entry(&mut out, message, signal, import)?;
+        }
+        Ok(out)
+    }
+
+    // Generates a complete define_parameters! invocation, with the given
+    // hand-written entries placed before the imported ones. Write this into
+    // OUT_DIR and include!() it from the application.
+    pub fn generate_parameters(&self, handwritten_entries: &str) -> Result<String, DbcError> {
+        let mut out = String::new();
+        out.push_str("// Generated by common::dbc_import. Do not edit.\n");
+        out.push_str("define_parameters! {\n");
+        out.push_str(handwritten_entries.trim_end());
+        if !handwritten_entries.trim().is_empty() {
+            out.push('\n');
+        }
+        out.push_str(&self.generate_entries()?);
+        out.push_str("}\n");
+        Ok(out)
+    }
+}
+
+fn bit_selection(signal: &DbcSignal, line_name: &str) -> Result<String, DbcError> {
+    let start = signal.start_bit;
+    let len = signal.length;
+    if len == 0 || len > 64 {
+        return Err(error(0, &format!("{}: Unsupported length {}", line_name, len)));
+    }
+    // DBC Motorola start bit is the MSB with Lsb0 numbering within the byte.
+    // Be* wants the Msb0 index of the MSB.
+    let msb0_start = (start / 8) * 8 + (7 - start % 8);
+    Ok(if signal.float {
+        if len != 32 {
+            return Err(error(0, &format!("{}: Float length has to be 32", line_name)));
+        }
+        if signal.little_endian {
+            format!("CanBitSelection::LeFloat32({})", start)
+        } else {
+            format!("CanBitSelection::BeFloat32({})", msb0_start)
+        }
+    } else if len == 1 && !signal.signed {
+        // Bit numbering of a single bit is the same in both byte orders
+        format!("CanBitSelection::Bit({})", start)
+    } else if signal.little_endian {
+        // DBC Intel start bit is the LSB with Lsb0 numbering, which matches
+        // Le* directly
+        if signal.signed {
+            format!("CanBitSelection::LeSigned({}, {})", start, len)
+        } else {
+            format!("CanBitSelection::LeUnsigned({}, {})", start, len)
+        }
+    } else if signal.signed {
+        format!("CanBitSelection::BeSigned({}, {})", msb0_start, len)
+    } else {
+        format!("CanBitSelection::BeUnsigned({}, {})", msb0_start, len)
+    })
+}
+
+fn write_entry(
+    out: &mut String,
+    message: &DbcMessage,
+    signal: &DbcSignal,
+    import: &SignalImport,
+) -> Result<(), DbcError> {
+    let line_name = format!("{}.{}", message.name, signal.name);
+
+    let ident = import
+        .ident
+        .clone()
+        .unwrap_or_else(|| to_parameter_ident(&signal.name));
+    let display_name = import.display_name.as_deref().unwrap_or(&signal.name);
+    let decimals = import
+        .decimals
+        .unwrap_or_else(|| decimals_for_scale(signal.scale));
+    let id = if message.extended {
+        format!("extended_id({:#x})", message.id)
+    } else {
+        format!("standard_id({:#x})", message.id)
+    };
+    let bits = bit_selection(signal, &line_name)?;
+    let mut mux = Vec::new();
+    for (multiplexer, value) in message.mux_conditions(signal)? {
+        mux.push(format!(
+            "CanMux {{ bits: {}, value: {} }}",
+            bit_selection(multiplexer, &line_name)?,
+            value
+        ));
+    }
+
+    // Writing into a String can't fail
+    writeln!(out, "    {} {{", ident).unwrap();
+    writeln!(out, "        display_name: {:?},", display_name).unwrap();
+    if decimals != 0 {
+        writeln!(out, "        decimals: {},", decimals).unwrap();
+    }
+    writeln!(out, "        unit: {:?},", signal.unit).unwrap();
+    writeln!(out, "        can_map: CanMap {{").unwrap();
+    writeln!(out, "            id: {},", id).unwrap();
+    writeln!(out, "            bits: {},", bits).unwrap();
+    writeln!(out, "            scale: {:?},", signal.scale as f32).unwrap();
+    if signal.offset != 0.0 {
+        writeln!(out, "            offset: {:?},", signal.offset as f32).unwrap();
+    }
+    if !mux.is_empty() {
+        writeln!(out, "            mux: &[{}],", mux.join(", ")).unwrap();
+    }
+    if !signal.value_table.is_empty() {
+        let labels: Vec<String> = signal
+            .value_table
+            .iter()
+            .map(|(raw, label)| format!("({}, {:?})", raw, label))
+            .collect();
+        writeln!(out, "            value_table: &[{}],", labels.join(", ")).unwrap();
+    }
+    writeln!(out, "        }},").unwrap();
+    if let Some(report_name) = &import.report_name {
+        writeln!(
+            out,
+            "        report_map: ReportMap {{ name: {:?}, decimals: {}, scale: {:?} }},",
+            report_name,
+            import.report_decimals.unwrap_or(decimals),
+            import.report_scale
+        )
+        .unwrap();
+    }
+    writeln!(out, "    }},").unwrap();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CanBitSelection;
+
+    const TEST_DBC: &str = r#"
+VERSION ""
+BU_: BMS
+BO_ 256 BMS_Status: 8 BMS
+ SG_ BMS_Soc : 0|8@1+ (0.5,0) [0|100] "%" Vector__XXX
+ SG_ Current : 3|12@0- (0.1,-10) [-200|200] "A" Vector__XXX
+ SG_ Temp : 16|8@1- (1,0) [-40|100] "degC" Vector__XXX
+ SG_ Fault : 31|1@1+ (1,0) [0|1] "" Vector__XXX
+BO_ 2147485696 BMS_Ext: 8 BMS
+ SG_ Power : 0|32@1- (1,0) [0|0] "W" Vector__XXX
+ SG_ Temp : 39|16@0+ (0.01,0) [0|0] "degC" Vector__XXX
+CM_ SG_ 256 BMS_Soc "State of charge";
+SIG_VALTYPE_ 2147485696 Power : 1;
+VAL_ 256 Fault 0 "Ok" 1 "Fault" ;
+"#;
+
+    #[test]
+    fn messages_parse() {
+        let dbc = Dbc::parse(TEST_DBC).unwrap();
+        assert_eq!(dbc.messages.len(), 2);
+        let status = &dbc.messages[0];
+        assert_eq!((status.id, status.extended, status.name.as_str(), status.dlc),
+                (0x100, false, "BMS_Status", 8));
+        assert!(status.signals.iter().map(|s| s.name.as_str())
+                .eq(["BMS_Soc", "Current", "Temp", "Fault"]));
+        let current = &status.signals[1];
+        assert_eq!((current.start_bit, current.length), (3, 12));
+        assert!(!current.little_endian && current.signed);
+        assert_eq!((current.scale, current.offset, current.min, current.max), (0.1, -10.0, -200.0, 200.0));
+        assert_eq!(current.unit, "A");
+        assert!(status.signals[0].little_endian && !status.signals[0].signed);
+        assert!(status.signals[2].signed);
+        assert_eq!(status.signals[3].value_table, [(0, "Ok".into()), (1, "Fault".into())]);
+
+        let ext = &dbc.messages[1];
+        assert_eq!((ext.id, ext.extended), (0x800, true));
+        assert!(ext.signals[0].float);
+        assert!(!ext.signals[1].float);
+
+        // "Temp" is in both messages
+        assert!(dbc.find_signal("Temp").is_err());
+        assert_eq!(dbc.find_signal("BMS_Ext.Temp").unwrap().1.start_bit, 39);
+        assert!(dbc.find_signal("Nope").is_err());
+
+        let error = Dbc::parse("SG_ Soc : 0|8@1+ (1,0) [0|100] \"%\" X").unwrap_err();
+        assert_eq!(error.line, 1);
+        assert!(Dbc::parse("BO_ 1 M: 8 X\n SG_ Soc : 0|8@2+ (1,0) [0|1] \"\" X").is_err());
+        assert!(Dbc::parse("BO_ 1 M: 8 X\n SG_ Soc : 0|8@1* (1,0) [0|1] \"\" X").is_err());
+    }
+
+    #[test]
+    fn motorola_start_bit_converts() {
+        // Current starts at bit 3 of byte 0 and continues through byte 1. BMS_Ext.Temp
+        // starts at bit 7 of byte 4.
+        let mut import = DbcImport::new(TEST_DBC).unwrap();
+        import.signal("Current");
+        import.signal("BMS_Ext.Temp");
+        let entries = import.generate_entries().unwrap();
+        assert!(entries.contains("bits: CanBitSelection::BeSigned(4, 12),"), "{}", entries);
+        assert!(entries.contains("bits: CanBitSelection::BeUnsigned(32, 16),"), "{}", entries);
+        // The low nibble of byte 0 is the most significant part
+        let data = [0xa5, 0x3c, 0, 0, 0x12, 0x34, 0, 0];
+        assert_eq!(CanBitSelection::BeSigned(4, 12).decode_int(&data), Some(0x53c));
+        assert_eq!(CanBitSelection::BeSigned(4, 12).decode_int(&[0x0f, 0xfe]), Some(-2));
+        assert_eq!(CanBitSelection::BeUnsigned(32, 16).decode_int(&data), Some(0x1234));
+    }
+
+    #[test]
+    fn parameters_are_generated() {
+        let mut import = DbcImport::new(TEST_DBC).unwrap();
+        import.signal("BMS_Soc").name("Soc").display_name("SoC").report_name("er")
+                .report_decimals(0);
+        import.signal("Temp").decimals(1); // Ambiguous
+        assert!(import.generate_entries().is_err());
+
+        let mut import = DbcImport::new(TEST_DBC).unwrap();
+        import.signal("BMS_Soc").name("Soc").display_name("SoC").report_name("er")
+                .report_decimals(0);
+        import.message("BMS_Ext").unwrap();
+        assert!(import.message("Nope").is_err());
+        let handwritten = "    Handwritten {\n        display_name: \"H\",\n        unit: \"\",\n    },\n";
+        assert_eq!(import.generate_parameters(handwritten).unwrap(), r#"// Generated by common::dbc_import. Do not edit.
+define_parameters! {
+    Handwritten {
+        display_name: "H",
+        unit: "",
+    },
+    Soc {
+        display_name: "SoC",
+        decimals: 1,
+        unit: "%",
+        can_map: CanMap {
+            id: standard_id(0x100),
+            bits: CanBitSelection::LeUnsigned(0, 8),
+            scale: 0.5,
+        },
+        report_map: ReportMap { name: "er", decimals: 0, scale: 1.0 },
+    },
+    Power {
+        display_name: "Power",
+        unit: "W",
+        can_map: CanMap {
+            id: extended_id(0x800),
+            bits: CanBitSelection::LeFloat32(0),
+            scale: 1.0,
+        },
+    },
+    Temp {
+        display_name: "Temp",
+        decimals: 2,
+        unit: "degC",
+        can_map: CanMap {
+            id: extended_id(0x800),
+            bits: CanBitSelection::BeUnsigned(32, 16),
+            scale: 0.01,
+        },
+    },
+}
+"#);
+
+        let mut import = DbcImport::new(TEST_DBC).unwrap();
+        import.signal("BMS_Status.Temp");
+        import.signal("Fault");
+        let entries = import.generate_entries().unwrap();
+        assert!(entries.contains("bits: CanBitSelection::LeSigned(16, 8),"), "{}", entries);
+        assert!(entries.contains("bits: CanBitSelection::Bit(31),"), "{}", entries);
+        assert!(entries.contains("value_table: &[(0, \"Ok\"), (1, \"Fault\")],"), "{}", entries);
+    }
+}
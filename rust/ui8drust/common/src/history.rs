@@ -0,0 +1,143 @@
+// Parameter value history
+//
+// Parameters with a History setting get a ring buffer of (timestamp, value)
+// samples, taken at most once per History::interval_ms. All rings share one
+// sample array sized by define_parameters!, so nothing is allocated. Stale and
+// missing values are recorded as NaN so that gaps show up, and are left out of
+// the statistics.
+
+use crate::Parameter;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HistorySample {
+    pub millis: u64,
+    pub value: f32,
+}
+
+impl HistorySample {
+    pub const EMPTY: HistorySample = HistorySample { millis: 0, value: f32::NAN };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HistoryStats {
+    pub min: f32,
+    pub max: f32,
+    pub mean: f32,
+    pub count: usize, // Samples that have a value
+}
+
+// One parameter's part of the sample array
+#[derive(Clone, Copy)]
+struct Ring {
+    offset: usize,
+    len: usize,
+    head: usize, // Where the next sample goes
+    count: usize,
+    last_sample_millis: Option<u64>,
+    interval_ms: u64,
+}
+
+impl Ring {
+    const NONE: Ring = Ring {
+        offset: 0,
+        len: 0,
+        head: 0,
+        count: 0,
+        last_sample_millis: None,
+        interval_ms: 0,
+    };
+}
+
+// Used by define_parameters! to size the sample array
+pub const fn count_history_samples(params: &[Parameter]) -> usize {
+    let mut count = 0;
+    let mut i = 0;
+    while i < params.len() {
+        if let Some(history) = &params[i].history {
+            count += history.samples as usize;
+        }
+        i += 1;
+    }
+    count
+}
+
+// N is the number of parameters and H the total number of samples
+pub struct ParameterHistory<const N: usize, const H: usize> {
+    samples: [HistorySample; H],
+    rings: [Ring; N],
+}
+
+impl<const N: usize, const H: usize> ParameterHistory<N, H> {
+    pub fn new(params: &[Parameter]) -> Self {
+        let mut rings = [Ring::NONE; N];
+        let mut offset = 0;
+        for (ring, param) in rings.iter_mut().zip(params) {
+            if let Some(history) = &param.history {
+                ring.offset = offset;
+                ring.len = history.samples as usize;
+                ring.interval_ms = history.interval_ms;
+                offset += ring.len;
+            }
+        }
+        assert!(offset <= H, "History sample array is too small");
+        Self {
+            samples: [HistorySample::EMPTY; H],
+            rings,
+        }
+    }
+
+    // Call this regularly, after the parameters have been updated
+    pub fn update(&mut self, params: &[Parameter], millis: u64) {
+        for (ring, param) in self.rings.iter_mut().zip(params) {
+            if ring.len == 0 {
+                continue;
+            }
+            if let Some(last) = ring.last_sample_millis {
+                if millis.wrapping_sub(last) < ring.interval_ms {
+                    continue;
+                }
+            }
+            ring.last_sample_millis = Some(millis);
+            self.samples[ring.offset + ring.head] = HistorySample {
+                millis,
+                value: param.current_value(),
+            };
+            ring.head = (ring.head + 1) % ring.len;
+            ring.count = (ring.count + 1).min(ring.len);
+        }
+    }
+
+    // Oldest first. Empty if the parameter has no history.
+    pub fn samples(&self, id: impl Into<usize>) -> impl Iterator<Item = &HistorySample> {
+        let ring = &self.rings[id.into()];
+        let ring_samples = &self.samples[ring.offset..ring.offset + ring.len];
+        let first = (ring.head + ring.len - ring.count) % ring.len.max(1);
+        (0..ring.count).map(move |i| &ring_samples[(first + i) % ring.len])
+    }
+
+    pub fn latest(&self, id: impl Into<usize>) -> Option<&HistorySample> {
+        self.samples(id).last()
+    }
+
+    // Over the buffered window. None if no sample has a value.
+    pub fn stats(&self, id: impl Into<usize>) -> Option<HistoryStats> {
+        let mut stats = HistoryStats {
+            min: f32::INFINITY,
+            max: f32::NEG_INFINITY,
+            mean: 0.0,
+            count: 0,
+        };
+        let mut sum = 0.0;
+        for sample in self.samples(id).filter(|sample| !sample.value.is_nan()) {
+            stats.min = stats.min.min(sample.value);
+            stats.max = stats.max.max(sample.value);
+            sum += sample.value;
+            stats.count += 1;
+        }
+        if stats.count == 0 {
+            return None;
+        }
+        stats.mean = sum / stats.count as f32;
+        Some(stats)
+    }
+}
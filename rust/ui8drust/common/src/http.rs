@@ -79,3 +79,18 @@ impl HttpProcess {
         }
     }
 }
+
+// Percent-encodes everything except unreserved characters, for putting text
+// into query strings
+pub fn push_url_encoded<const N: usize>(url: &mut ArrayString<N>, text: &str) {
+    for b in text.bytes() {
+        if b.is_ascii_alphanumeric() || b"-_.~".contains(&b) {
+            url.push(b as char);
+        } else {
+            const HEX: &[u8; 16] = b"0123456789ABCDEF";
+            url.push('%');
+            url.push(HEX[(b >> 4) as usize] as char);
+            url.push(HEX[(b & 0xf) as usize] as char);
+        }
+    }
+}
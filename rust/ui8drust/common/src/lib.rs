@@ -1,14 +1,32 @@
 #![no_std]
 
+// Tests always have std, so dbc_import is tested without the feature
+#[cfg(any(feature = "std", test))]
+extern crate std;
+
 pub mod sim7600;
 pub use sim7600::*;
 pub mod command_accumulator;
+pub mod console;
 
 pub mod log_display;
 pub use log_display::LogDisplay;
+pub mod log_filter;
 
 pub mod http;
 
+pub mod can_dispatch;
+pub mod can_tx;
+pub mod candump;
+pub mod slcan;
+pub mod parameter_store;
+pub use parameter_store::ParameterStore;
+pub mod history;
+
+pub mod dbc_export;
+#[cfg(any(feature = "std", test))]
+pub mod dbc_import;
+
 pub extern crate bxcan;
 pub extern crate embedded_graphics;
 pub extern crate log;
@@ -107,6 +125,21 @@ pub trait HardwareInterface {
     fn get_digital_input(&mut self, input: DigitalInput) -> bool;
 
     fn set_digital_output(&mut self, output: DigitalOutput, value: bool);
+
+    // The filter used by the platform's logger. set_log_filter() should also
+    // update log::set_max_level().
+    fn log_filter(&mut self) -> log_filter::LogFilter;
+    fn set_log_filter(&mut self, filter: log_filter::LogFilter);
+
+    // Which CAN frames the platform logs. The platform passes every received
+    // and transmitted frame to CanLog::log().
+    fn can_log(&mut self) -> candump::CanLog;
+    fn set_can_log(&mut self, can_log: candump::CanLog);
+
+    // Gateway mode where a serial port speaks slcan and bridges to the CAN bus.
+    // See slcan.rs.
+    fn slcan_enabled(&mut self) -> bool;
+    fn set_slcan_enabled(&mut self, enabled: bool);
 }
 
 // Parameter definitions
@@ -119,13 +152,311 @@ pub enum CanBitSelection {
     LeSigned(u8, u8),
     Uint8(u8),
     Int8(u8),
-    Function(fn(&[u8]) -> f32),
+    // IEEE 754 single precision. Bit numbering is the same as in LeUnsigned and
+    // BeUnsigned.
+    LeFloat32(u8),
+    BeFloat32(u8),
+    // Number of bytes the function reads, and the function
+    Function(u8, fn(&[u8]) -> f32),
+}
+
+// No f32::round() in no_std. Saturates like `as`
+const fn round_to_i64(v: f32) -> i64 {
+    (if v < 0.0 { v - 0.5 } else { v + 0.5 }) as i64
+}
+
+fn round_f64_to_i64(v: f64) -> i64 {
+    (if v < 0.0 { v - 0.5 } else { v + 0.5 }) as i64
+}
+
+// Checks that bits i0..i0+len fit in the frame and in a u64
+const fn bit_range_fits(frame_len: usize, i0: u8, len: u8) -> bool {
+    len >= 1 && len <= 64 && i0 as usize + len as usize <= frame_len * 8
+}
+
+impl CanBitSelection {
+    // Returns true if the frame is long enough for this selection
+    pub fn fits(&self, data: &[u8]) -> bool {
+        self.fits_len(data.len())
+    }
+
+    pub const fn fits_len(&self, frame_len: usize) -> bool {
+        match *self {
+            CanBitSelection::Bit(bit_i) => (bit_i as usize) / 8 < frame_len,
+            CanBitSelection::BeUnsigned(i0, len) |
+            CanBitSelection::LeUnsigned(i0, len) |
+            CanBitSelection::BeSigned(i0, len) |
+            CanBitSelection::LeSigned(i0, len) => bit_range_fits(frame_len, i0, len),
+            CanBitSelection::Uint8(byte_i) |
+            CanBitSelection::Int8(byte_i) => (byte_i as usize) < frame_len,
+            CanBitSelection::LeFloat32(i0) |
+            CanBitSelection::BeFloat32(i0) => bit_range_fits(frame_len, i0, 32),
+            CanBitSelection::Function(num_bytes, _) => (num_bytes as usize) <= frame_len,
+        }
+    }
+
+    // Returns the raw value before scaling, or None if the frame is too short
+    pub fn decode(&self, data: &[u8]) -> Option<f32> {
+        if !self.fits(data) {
+            return None;
+        }
+        Some(match *self {
+            CanBitSelection::Bit(bit_i) => {
+                let byte = data[(bit_i as usize) / 8];
+                let bit_in_byte = bit_i % 8;
+                let mask = 1 << bit_in_byte;
+                ((byte & mask) >> bit_in_byte) as f32
+            }
+            CanBitSelection::BeUnsigned(i0, len) => {
+                let bits = data.view_bits::<Msb0>();
+                bits[i0 as usize .. (i0+len) as usize].load_be::<u64>() as f32
+            }
+            CanBitSelection::LeUnsigned(i0, len) => {
+                let bits = data.view_bits::<Lsb0>();
+                bits[i0 as usize .. (i0+len) as usize].load_le::<u64>() as f32
+            }
+            CanBitSelection::BeSigned(i0, len) => {
+                let bits = data.view_bits::<Msb0>();
+                bits[i0 as usize .. (i0+len) as usize].load_be::<i64>() as f32
+            }
+            CanBitSelection::LeSigned(i0, len) => {
+                let bits = data.view_bits::<Lsb0>();
+                bits[i0 as usize .. (i0+len) as usize].load_le::<i64>() as f32
+            }
+            CanBitSelection::Uint8(byte_i) => {
+                (data[byte_i as usize] as u8) as f32
+            }
+            CanBitSelection::Int8(byte_i) => {
+                (data[byte_i as usize] as i8) as f32
+            }
+            CanBitSelection::LeFloat32(i0) => {
+                let bits = data.view_bits::<Lsb0>();
+                f32::from_bits(bits[i0 as usize .. i0 as usize + 32].load_le::<u32>())
+            }
+            CanBitSelection::BeFloat32(i0) => {
+                let bits = data.view_bits::<Msb0>();
+                f32::from_bits(bits[i0 as usize .. i0 as usize + 32].load_be::<u32>())
+            }
+            CanBitSelection::Function(_, function) => {
+                function(data)
+            }
+        })
+    }
+
+    // Like decode(), but integer selections are returned exactly instead of
+    // going through f32, which only has 24 bits of precision
+    pub fn decode_int(&self, data: &[u8]) -> Option<i64> {
+        if !self.fits(data) {
+            return None;
+        }
+        Some(match *self {
+            CanBitSelection::BeUnsigned(i0, len) => {
+                let bits = data.view_bits::<Msb0>();
+                bits[i0 as usize .. (i0+len) as usize].load_be::<u64>().min(i64::MAX as u64) as i64
+            }
+            CanBitSelection::LeUnsigned(i0, len) => {
+                let bits = data.view_bits::<Lsb0>();
+                bits[i0 as usize .. (i0+len) as usize].load_le::<u64>().min(i64::MAX as u64) as i64
+            }
+            CanBitSelection::BeSigned(i0, len) => {
+                let bits = data.view_bits::<Msb0>();
+                bits[i0 as usize .. (i0+len) as usize].load_be::<i64>()
+            }
+            CanBitSelection::LeSigned(i0, len) => {
+                let bits = data.view_bits::<Lsb0>();
+                bits[i0 as usize .. (i0+len) as usize].load_le::<i64>()
+            }
+            // The rest fit in f32 exactly or are floats to begin with
+            _ => round_to_i64(self.decode(data)?),
+        })
+    }
+
+    // Stores a raw value. Values outside the range of the selection are
+    // saturated. Returns false if the selection doesn't fit in data or is a
+    // Function.
+    pub fn encode(&self, data: &mut [u8], raw: f32) -> bool {
+        if !self.fits(data) {
+            return false;
+        }
+        match *self {
+            CanBitSelection::Bit(bit_i) => {
+                data.view_bits_mut::<Lsb0>().set(bit_i as usize, raw >= 0.5);
+            }
+            CanBitSelection::BeUnsigned(i0, len) => {
+                let bits = data.view_bits_mut::<Msb0>();
+                bits[i0 as usize .. (i0+len) as usize].store_be(round_to_u64(raw, len));
+            }
+            CanBitSelection::LeUnsigned(i0, len) => {
+                let bits = data.view_bits_mut::<Lsb0>();
+                bits[i0 as usize .. (i0+len) as usize].store_le(round_to_u64(raw, len));
+            }
+            CanBitSelection::BeSigned(i0, len) => {
+                let bits = data.view_bits_mut::<Msb0>();
+                bits[i0 as usize .. (i0+len) as usize].store_be(round_to_signed(raw, len));
+            }
+            CanBitSelection::LeSigned(i0, len) => {
+                let bits = data.view_bits_mut::<Lsb0>();
+                bits[i0 as usize .. (i0+len) as usize].store_le(round_to_signed(raw, len));
+            }
+            CanBitSelection::Uint8(byte_i) => {
+                data[byte_i as usize] = round_to_u64(raw, 8) as u8;
+            }
+            CanBitSelection::Int8(byte_i) => {
+                data[byte_i as usize] = round_to_signed(raw, 8) as i8 as u8;
+            }
+            CanBitSelection::LeFloat32(i0) => {
+                let bits = data.view_bits_mut::<Lsb0>();
+                bits[i0 as usize .. i0 as usize + 32].store_le(raw.to_bits());
+            }
+            CanBitSelection::BeFloat32(i0) => {
+                let bits = data.view_bits_mut::<Msb0>();
+                bits[i0 as usize .. i0 as usize + 32].store_be(raw.to_bits());
+            }
+            CanBitSelection::Function(..) => {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+// Rounds and saturates to an unsigned field of len bits
+fn round_to_u64(raw: f32, len: u8) -> u64 {
+    ((raw + 0.5) as u64).min(u64::MAX >> (64 - len))
+}
+
+// Rounds and saturates to a signed field of len bits
+fn round_to_signed(raw: f32, len: u8) -> i64 {
+    round_to_i64(raw).clamp(i64::MIN >> (64 - len), i64::MAX >> (64 - len))
+}
+
+// Selects one page of a multiplexed CAN ID: The CanMap is only decoded when
+// the multiplexer field has this value
+pub struct CanMux {
+    pub bits: CanBitSelection,
+    pub value: u32,
+}
+
+impl CanMux {
+    // Returns None if the frame is too short for the multiplexer
+    pub fn matches(&self, data: &[u8]) -> Option<bool> {
+        Some(self.bits.decode_int(data)? == self.value as i64)
+    }
 }
 
 pub struct CanMap {
     pub id: bxcan::Id,
     pub bits: CanBitSelection,
     pub scale: f32,
+    pub offset: f32, // value = raw * scale + offset
+    // All of these have to match. Nested multiplexers are listed from the
+    // outermost to the innermost.
+    pub mux: &'static [CanMux],
+    // Labels for raw values, e.g. &[(0, "Off"), (0x22, "Running")]
+    pub value_table: &'static [(i64, &'static str)],
+}
+
+impl CanMap {
+    // define_parameters! fills in the fields that aren't given from this
+    pub const DEFAULT: CanMap = CanMap {
+        id: bxcan::Id::Standard(StandardId::ZERO),
+        bits: CanBitSelection::Uint8(0),
+        scale: 1.0,
+        offset: 0.0,
+        mux: &[],
+        value_table: &[],
+    };
+
+    // Returns None if the frame is too short
+    pub fn decode(&self, data: &[u8]) -> Option<f32> {
+        Some(self.bits.decode(data)? * self.scale + self.offset)
+    }
+
+    // Decodes into a value of the parameter's type. Integer types are scaled
+    // in f64 so that 32 bit values stay exact.
+    pub fn decode_value(&self, data: &[u8], value_type: ParameterType) -> Option<ParameterValue> {
+        if let ParameterType::F32 = value_type {
+            return Some(ParameterValue::F32(self.decode(data)?));
+        }
+        let raw = self.bits.decode_int(data)?;
+        let value = if self.scale == 1.0 && self.offset == 0.0 {
+            raw
+        } else {
+            round_f64_to_i64(raw as f64 * self.scale as f64 + self.offset as f64)
+        };
+        Some(value_type.from_i64(value))
+    }
+
+    // Returns None if the frame is too short for the multiplexers
+    pub fn mux_matches(&self, data: &[u8]) -> Option<bool> {
+        for mux in self.mux {
+            if !mux.matches(data)? {
+                return Some(false);
+            }
+        }
+        Some(true)
+    }
+
+    // Returns the label of a scaled value, if the value table has one
+    pub fn value_label(&self, value: f32) -> Option<&'static str> {
+        if self.value_table.is_empty() || value.is_nan() {
+            return None;
+        }
+        let raw = round_to_i64((value - self.offset) / self.scale);
+        self.value_table
+            .iter()
+            .find(|(table_raw, _)| *table_raw == raw)
+            .map(|(_, label)| *label)
+    }
+}
+
+// Publishes the value of a parameter on CAN every period_ms. Parameters with
+// the same ID are sent in the same frame, at the period of the first one. See
+// can_tx::TxCanScheduler.
+pub struct TxCanMap {
+    pub id: bxcan::Id,
+    pub bits: CanBitSelection, // Function can't be used here
+    pub scale: f32,
+    pub offset: f32, // raw = (value - offset) / scale
+    pub period_ms: u64,
+}
+
+impl TxCanMap {
+    // define_parameters! fills in the fields that aren't given from this
+    pub const DEFAULT: TxCanMap = TxCanMap {
+        id: bxcan::Id::Standard(StandardId::ZERO),
+        bits: CanBitSelection::Uint8(0),
+        scale: 1.0,
+        offset: 0.0,
+        period_ms: 0,
+    };
+
+    // Stores value into data. NaN is stored as zero; see
+    // can_tx::encode_tx_frame() for how it is kept off the bus.
+    pub fn encode(&self, data: &mut [u8], value: f32) -> bool {
+        let raw = if value.is_nan() { 0.0 } else { (value - self.offset) / self.scale };
+        self.bits.encode(data, raw)
+    }
+}
+
+// Limit for DerivedParameter::inputs, so that the input values can be passed
+// in a stack array
+pub const MAX_DERIVED_INPUTS: usize = 8;
+
+// A parameter computed from other parameters. See
+// ParameterStore::update_derived().
+pub struct DerivedParameter {
+    pub inputs: &'static [usize],
+    // Gets the current values of the inputs in the order of inputs. Stale and
+    // missing inputs are NaN.
+    pub compute: fn(&[f32]) -> f32,
+}
+
+// Keeps the last `samples` values of the parameter, at most one per
+// interval_ms. See history::ParameterHistory.
+pub struct History {
+    pub samples: u16,
+    pub interval_ms: u64,
 }
 
 pub struct ReportMap<'a> {
@@ -134,60 +465,298 @@ pub struct ReportMap<'a> {
     pub scale: f32,
 }
 
+// The type of Parameter::value. define_parameters! defaults to F32.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParameterType {
+    F32,
+    Bool,
+    I32,
+    U32,
+    // Labels for the values, e.g. &[(0, "Off"), (0x22, "Running")]
+    Enum(&'static [(u32, &'static str)]),
+}
+
+impl ParameterType {
+    // Non-F32 values are rounded. Bool is true at 0.5 and above, like the
+    // flags used to be compared.
+    pub const fn from_f32(self, v: f32) -> ParameterValue {
+        match self {
+            ParameterType::F32 => ParameterValue::F32(v),
+            ParameterType::Bool => ParameterValue::Bool(v >= 0.5),
+            _ => self.from_i64(round_to_i64(v)),
+        }
+    }
+
+    // Saturates to the range of the type
+    pub const fn from_i64(self, v: i64) -> ParameterValue {
+        let to_u32 = if v < 0 { 0 } else if v > u32::MAX as i64 { u32::MAX } else { v as u32 };
+        match self {
+            ParameterType::F32 => ParameterValue::F32(v as f32),
+            ParameterType::Bool => ParameterValue::Bool(v != 0),
+            ParameterType::I32 => ParameterValue::I32(
+                if v < i32::MIN as i64 { i32::MIN } else if v > i32::MAX as i64 { i32::MAX }
+                else { v as i32 }),
+            ParameterType::U32 => ParameterValue::U32(to_u32),
+            ParameterType::Enum(_) => ParameterValue::Enum(to_u32),
+        }
+    }
+
+    pub fn convert(self, value: ParameterValue) -> ParameterValue {
+        match value {
+            ParameterValue::F32(v) => self.from_f32(v),
+            ParameterValue::Bool(v) => self.from_i64(v as i64),
+            ParameterValue::I32(v) => self.from_i64(v as i64),
+            ParameterValue::U32(v) | ParameterValue::Enum(v) => self.from_i64(v as i64),
+        }
+    }
+
+    // Parses text typed by a user. Bool also takes true/false and on/off, and
+    // Enum takes its labels in any case.
+    pub fn parse(self, s: &str) -> Option<ParameterValue> {
+        match self {
+            ParameterType::F32 => s.parse::<f32>().ok().map(ParameterValue::F32),
+            ParameterType::Bool => match s {
+                "1" | "true" | "on" => Some(ParameterValue::Bool(true)),
+                "0" | "false" | "off" => Some(ParameterValue::Bool(false)),
+                _ => None,
+            },
+            ParameterType::I32 => s.parse::<i32>().ok().map(ParameterValue::I32),
+            ParameterType::U32 => s.parse::<u32>().ok().map(ParameterValue::U32),
+            ParameterType::Enum(labels) => labels.iter()
+                .find(|(_, label)| label.eq_ignore_ascii_case(s))
+                .map(|(value, _)| *value)
+                .or_else(|| s.parse::<u32>().ok())
+                .map(ParameterValue::Enum),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParameterValue {
+    F32(f32),
+    Bool(bool),
+    I32(i32),
+    U32(u32),
+    Enum(u32), // See ParameterType::Enum for the labels
+}
+
+impl ParameterValue {
+    pub fn as_f32(&self) -> f32 {
+        match *self {
+            ParameterValue::F32(v) => v,
+            ParameterValue::Bool(v) => if v { 1.0 } else { 0.0 },
+            ParameterValue::I32(v) => v as f32,
+            ParameterValue::U32(v) | ParameterValue::Enum(v) => v as f32,
+        }
+    }
+    pub fn as_bool(&self) -> bool {
+        match *self {
+            ParameterValue::F32(v) => v >= 0.5,
+            ParameterValue::Bool(v) => v,
+            ParameterValue::I32(v) => v != 0,
+            ParameterValue::U32(v) | ParameterValue::Enum(v) => v != 0,
+        }
+    }
+    pub fn is_nan(&self) -> bool {
+        matches!(self, ParameterValue::F32(v) if v.is_nan())
+    }
+    // Like ==, but NaN is the same as NaN
+    pub fn same_as(&self, other: &ParameterValue) -> bool {
+        self == other || (self.is_nan() && other.is_nan())
+    }
+}
+
+impl From<f32> for ParameterValue {
+    fn from(v: f32) -> Self {
+        ParameterValue::F32(v)
+    }
+}
+
+impl From<bool> for ParameterValue {
+    fn from(v: bool) -> Self {
+        ParameterValue::Bool(v)
+    }
+}
+
+impl From<i32> for ParameterValue {
+    fn from(v: i32) -> Self {
+        ParameterValue::I32(v)
+    }
+}
+
+impl From<u32> for ParameterValue {
+    fn from(v: u32) -> Self {
+        ParameterValue::U32(v)
+    }
+}
+
+// Honors width and, for F32, precision. Bool is written as 0 or 1 so that
+// reports stay numeric.
+impl core::fmt::Display for ParameterValue {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match *self {
+            ParameterValue::F32(v) => core::fmt::Display::fmt(&v, f),
+            ParameterValue::Bool(v) => core::fmt::Display::fmt(&(v as u8), f),
+            ParameterValue::I32(v) => core::fmt::Display::fmt(&v, f),
+            ParameterValue::U32(v) | ParameterValue::Enum(v) => core::fmt::Display::fmt(&v, f),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParameterQuality {
+    NeverReceived,
+    Valid,
+    Stale,       // Not updated within timeout_ms. value is the last known value
+    DecodeError, // The last received frame was too short for can_map
+    OutOfRange,  // Fresh, but outside min..max
+}
+
+// Used for CAN mapped parameters that don't specify timeout_ms
+pub const DEFAULT_CAN_TIMEOUT_MS: u64 = 5000;
+
 pub struct Parameter<'a> {
     pub id: usize,
+    pub name: &'a str, // The ParameterId variant name
     pub display_name: &'a str,
-    pub value: f32,
+    pub value_type: ParameterType,
+    pub value: ParameterValue,
     pub decimals: u8,
     pub unit: &'a str,
+    // Expected range. Values outside it are kept but marked OutOfRange.
+    pub min: Option<f32>,
+    pub max: Option<f32>,
     pub can_map: Option<CanMap>,
+    pub tx_can_map: Option<TxCanMap>,
     pub report_map: Option<ReportMap<'a>>,
+    pub derived: Option<DerivedParameter>,
+    pub history: Option<History>,
+    pub writable: bool, // Can be set from the console
     pub update_timestamp: u64,
+    pub decode_error_count: u32, // Frames too short for can_map
+    pub out_of_range_count: u32, // Values outside min..max
+    pub timeout_ms: u64, // 0 = never goes stale
+    pub quality: ParameterQuality,
+    // Set when value or quality changes, until ParameterStore::commit_changes()
+    // gives the change a sequence number
+    pub change_pending: bool,
+    pub change_sequence: u32,
 }
 
 impl<'a> Parameter<'a> {
+    // Creates an F32 parameter. Set value_type and value for other types, and
+    // the rest of the optional fields the same way.
     pub const fn new(
         id: usize,
+        name: &'a str,
         display_name: &'a str,
         value: f32,
         decimals: u8,
         unit: &'a str,
         can_map: Option<CanMap>,
-        report_map: Option<ReportMap<'a>>,
     ) -> Self {
+        let timeout_ms = if can_map.is_some() { DEFAULT_CAN_TIMEOUT_MS } else { 0 };
         Self {
             id: id,
+            name: name,
             display_name: display_name,
-            value: value,
+            value_type: ParameterType::F32,
+            value: ParameterValue::F32(value),
             decimals: decimals,
             unit: unit,
+            min: None,
+            max: None,
             can_map: can_map,
-            report_map: report_map,
+            tx_can_map: None,
+            report_map: None,
+            derived: None,
+            history: None,
+            writable: false,
             update_timestamp: 0,
+            decode_error_count: 0,
+            out_of_range_count: 0,
+            timeout_ms: timeout_ms,
+            quality: if value.is_nan() {
+                ParameterQuality::NeverReceived
+            } else {
+                ParameterQuality::Valid
+            },
+            change_pending: false,
+            change_sequence: 0,
         }
     }
-    pub fn set_value(&mut self, value: f32, millis: u64) {
+    // The value is converted to value_type
+    pub fn set_value(&mut self, value: impl Into<ParameterValue>, millis: u64) {
+        let value = self.value_type.convert(value.into());
+        if !value.same_as(&self.value) {
+            self.change_pending = true;
+        }
         self.value = value;
         self.update_timestamp = millis;
+        if self.in_range() {
+            self.set_quality(ParameterQuality::Valid);
+        } else {
+            self.set_quality(ParameterQuality::OutOfRange);
+            self.out_of_range_count = self.out_of_range_count.saturating_add(1);
+        }
     }
-}
-
-pub static mut PARAMETERS: Option<&'static mut [Parameter<'static>]> = None;
-
-pub fn set_parameters(params: &'static mut [Parameter<'static>]) {
-    unsafe {
-        PARAMETERS = Some(params);
+    pub fn set_quality(&mut self, quality: ParameterQuality) {
+        if quality != self.quality {
+            self.quality = quality;
+            self.change_pending = true;
+        }
+    }
+    // True if the change sequence is newer than sequence. See
+    // ParameterStore::changed_since().
+    pub fn changed_since(&self, sequence: u32) -> bool {
+        // Works across wrapping as long as sequence isn't 2^31 commits old
+        (self.change_sequence.wrapping_sub(sequence) as i32) > 0
+    }
+    // NaN counts as in range; it means there is no value
+    pub fn in_range(&self) -> bool {
+        let value = self.value.as_f32();
+        !(self.min.is_some_and(|min| value < min) || self.max.is_some_and(|max| value > max))
+    }
+    // The value to base decisions on: None unless the value is fresh. value
+    // holds the last known value, which is fine for displaying.
+    pub fn current(&self) -> Option<ParameterValue> {
+        match self.quality {
+            ParameterQuality::Valid | ParameterQuality::OutOfRange => Some(self.value),
+            _ => None,
+        }
+    }
+    // current() as f32, NaN if not fresh
+    pub fn current_value(&self) -> f32 {
+        self.current().map_or(f32::NAN, |value| value.as_f32())
+    }
+    pub fn current_bool(&self) -> Option<bool> {
+        self.current().map(|value| value.as_bool())
+    }
+    pub fn value_label(&self) -> Option<&'static str> {
+        if let (ParameterType::Enum(labels), ParameterValue::Enum(value)) =
+                (self.value_type, self.value) {
+            return labels.iter().find(|(v, _)| *v == value).map(|(_, label)| *label);
+        }
+        self.can_map.as_ref()?.value_label(self.value.as_f32())
     }
 }
 
 #[macro_export] macro_rules! define_parameters {
     ($($name:ident {
         display_name: $display_name:expr,
+        $(value_type: $value_type:expr,)?
         $(value: $value:expr,)?
         $(decimals: $decimals:expr,)?
         unit: $unit:expr,
-        $(can_map: $can_map:expr,)?
+        $(min: $min:expr,)?
+        $(max: $max:expr,)?
+        $(compute: $compute:expr, inputs: [$($input:ident),* $(,)?],)?
+        $(can_map: CanMap { $($can_field:ident: $can_value:expr),* $(,)? },)?
+        $(timeout_ms: $timeout_ms:expr,)?
+        $(tx_can_map: TxCanMap { $($tx_field:ident: $tx_value:expr),* $(,)? },)?
         $(report_map: $report_map:expr,)?
+        $(history: $history:expr,)?
+        $(writable: $writable:expr,)?
     }),* $(,)?) => {
         pub const NUM_PARAMETERS: usize = {
             let mut count = 0;
@@ -215,16 +784,27 @@ pub fn set_parameters(params: &'static mut [Parameter<'static>]) {
             }
         }
 
-        pub static mut PARAMETERS: [Parameter; NUM_PARAMETERS] = [
+        const PARAMETER_DEFINITIONS: [Parameter<'static>; NUM_PARAMETERS] = [
             $(
                 Parameter {
                     id: ParameterId::$name as usize,
+                    name: stringify!($name),
                     display_name: $display_name,
+                    value_type: {
+                        #[allow(unused_variables)]
+                        let value_type = $crate::ParameterType::F32;
+                        $(let value_type = $value_type;)?
+                        value_type
+                    },
+                    // Given as f32 and converted to value_type
                     value: {
+                        #[allow(unused_variables)]
+                        let value_type = $crate::ParameterType::F32;
+                        $(let value_type = $value_type;)?
                         #[allow(unused_variables)]
                         let value: f32 = f32::NAN;
                         $(let value = $value;)?
-                        value
+                        value_type.from_f32(value)
                     },
                     decimals: {
                         #[allow(unused_variables)]
@@ -233,100 +813,889 @@ pub fn set_parameters(params: &'static mut [Parameter<'static>]) {
                         decimals
                     },
                     unit: $unit,
+                    min: {
+                        #[allow(unused_variables)]
+                        let min: Option<f32> = None;
+                        $(let min = Some($min);)?
+                        min
+                    },
+                    max: {
+                        #[allow(unused_variables)]
+                        let max: Option<f32> = None;
+                        $(let max = Some($max);)?
+                        max
+                    },
                     can_map: {
                         #[allow(unused_variables)]
                         let can_map: Option<CanMap> = None;
-                        $(let can_map = Some($can_map);)?
+                        $(let can_map = Some(CanMap {
+                            $($can_field: $can_value,)*
+                            ..CanMap::DEFAULT
+                        });)?
                         can_map
                     },
+                    tx_can_map: {
+                        #[allow(unused_variables)]
+                        let tx_can_map: Option<TxCanMap> = None;
+                        $(let tx_can_map = Some(TxCanMap {
+                            $($tx_field: $tx_value,)*
+                            ..TxCanMap::DEFAULT
+                        });)?
+                        tx_can_map
+                    },
                     report_map: {
                         #[allow(unused_variables)]
                         let report_map: Option<ReportMap> = None;
                         $(let report_map = Some($report_map);)?
                         report_map
                     },
+                    derived: {
+                        #[allow(unused_variables)]
+                        let derived: Option<$crate::DerivedParameter> = None;
+                        $(let derived = Some($crate::DerivedParameter {
+                            inputs: &[$(ParameterId::$input as usize),*],
+                            compute: $compute,
+                        });)?
+                        derived
+                    },
+                    history: {
+                        #[allow(unused_variables)]
+                        let history: Option<$crate::History> = None;
+                        $(let history = Some($history);)?
+                        history
+                    },
+                    writable: {
+                        #[allow(unused_variables)]
+                        let writable = false;
+                        $(let writable = $writable;)?
+                        writable
+                    },
                     update_timestamp: 0,
+                    decode_error_count: 0,
+                    out_of_range_count: 0,
+                    timeout_ms: {
+                        #[allow(unused_variables)]
+                        let timeout_ms: u64 = 0;
+                        $(
+                            $(let _ = stringify!($can_field);)*
+                            let timeout_ms = $crate::DEFAULT_CAN_TIMEOUT_MS;
+                        )?
+                        $(let timeout_ms = $timeout_ms;)?
+                        timeout_ms
+                    },
+                    quality: {
+                        #[allow(unused_variables)]
+                        let quality = $crate::ParameterQuality::NeverReceived;
+                        $(
+                            let _ = stringify!($value);
+                            let quality = $crate::ParameterQuality::Valid;
+                        )?
+                        quality
+                    },
+                    change_pending: false,
+                    change_sequence: 0,
                 }
             ),*
         ];
 
-        // Accessor using ParameterId enum
-		pub fn get_parameter(id: ParameterId) -> &'static mut Parameter<'static> {
-		    get_parameter_id(id as usize)
-		}
+        // Fails the build with a readable error if a definition is invalid
+        const _: () = $crate::validate_parameters(&PARAMETER_DEFINITIONS);
 
-        // Initialization function: Call this at start of main() or whatever
-        pub fn init_parameters() {
-            unsafe {
-                $crate::set_parameters(&mut PARAMETERS);
+        impl From<ParameterId> for usize {
+            fn from(id: ParameterId) -> usize {
+                id as usize
             }
         }
+
+        pub const NUM_CAN_MAPPED_PARAMETERS: usize =
+                $crate::can_dispatch::count_can_mapped(&PARAMETER_DEFINITIONS);
+
+        pub static CAN_DISPATCH_TABLE:
+                $crate::can_dispatch::CanDispatchTable<NUM_CAN_MAPPED_PARAMETERS> =
+                $crate::can_dispatch::CanDispatchTable::build(&PARAMETER_DEFINITIONS);
+
+        pub type Parameters = $crate::ParameterStore<NUM_PARAMETERS, NUM_CAN_MAPPED_PARAMETERS>;
+
+        // Call this at start of main() or whatever, and keep the store around
+        pub fn new_parameter_store() -> Parameters {
+            $crate::ParameterStore::new(PARAMETER_DEFINITIONS, &CAN_DISPATCH_TABLE)
+        }
+
+        pub const NUM_HISTORY_SAMPLES: usize =
+                $crate::history::count_history_samples(&PARAMETER_DEFINITIONS);
+
+        pub type ParameterHistories =
+                $crate::history::ParameterHistory<NUM_PARAMETERS, NUM_HISTORY_SAMPLES>;
+
+        pub fn new_parameter_history() -> ParameterHistories {
+            $crate::history::ParameterHistory::new(&PARAMETER_DEFINITIONS)
+        }
     };
 }
 
-pub fn get_parameters() -> &'static mut [Parameter<'static>] {
-    unsafe {
-        PARAMETERS.as_mut().expect("Parameters not initialized")
+// CAN ID helpers for define_parameters!. Unlike StandardId::new(...).unwrap(),
+// these say what is wrong when the ID is out of range.
+pub const fn standard_id(raw: u16) -> bxcan::Id {
+    match StandardId::new(raw) {
+        Some(id) => bxcan::Id::Standard(id),
+        None => panic!("Standard CAN ID has to be 0x7ff or less"),
+    }
+}
+
+pub const fn extended_id(raw: u32) -> bxcan::Id {
+    match bxcan::ExtendedId::new(raw) {
+        Some(id) => bxcan::Id::Extended(id),
+        None => panic!("Extended CAN ID has to be 0x1fffffff or less"),
+    }
+}
+
+// Compile time validation of define_parameters!
+
+const fn str_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+// Panics with the parts joined together. const panic!() can only print a
+// single &str, so the message is assembled into a buffer first.
+const fn definition_error(parts: &[&str]) -> ! {
+    let mut buf = [0u8; 256];
+    let mut len = 0;
+    let mut part_i = 0;
+    while part_i < parts.len() {
+        let bytes = parts[part_i].as_bytes();
+        let mut i = 0;
+        while i < bytes.len() && len < buf.len() {
+            buf[len] = bytes[i];
+            len += 1;
+            i += 1;
+        }
+        part_i += 1;
+    }
+    match core::str::from_utf8(buf.split_at(len).0) {
+        Ok(message) => panic!("{}", message),
+        Err(_) => panic!("Invalid parameter definition"),
     }
 }
 
-pub fn get_parameter_id(id: usize) -> &'static mut Parameter<'static> {
-    unsafe {
-        return &mut PARAMETERS.as_mut().expect("Parameters not initialized")[id];
+const fn bit_selection_problem(bits: &CanBitSelection) -> Option<&'static str> {
+    match *bits {
+        CanBitSelection::BeUnsigned(_, 0) |
+        CanBitSelection::LeUnsigned(_, 0) |
+        CanBitSelection::BeSigned(_, 0) |
+        CanBitSelection::LeSigned(_, 0) => Some("bit range length is 0"),
+        CanBitSelection::BeUnsigned(_, len) |
+        CanBitSelection::LeUnsigned(_, len) |
+        CanBitSelection::BeSigned(_, len) |
+        CanBitSelection::LeSigned(_, len) if len > 64 =>
+            Some("bit range is longer than 64 bits"),
+        _ if !bits.fits_len(8) => Some("bits are past the end of an 8 byte CAN frame"),
+        _ => None,
     }
 }
 
-pub fn update_parameters_on_can(frame: bxcan::Frame, millis: u64) {
-    for (i, param) in get_parameters().iter_mut().enumerate() {
+const fn report_name_is_valid(name: &str) -> bool {
+    let bytes = name.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        if !(c.is_ascii_alphanumeric() || c == b'_' || c == b'-' || c == b'.') {
+            return false;
+        }
+        i += 1;
+    }
+    !bytes.is_empty()
+}
+
+pub const fn validate_parameters(params: &[Parameter]) {
+    let mut i = 0;
+    while i < params.len() {
+        let param = &params[i];
         if let Some(can_map) = &param.can_map {
-            if let Some(data) = frame.data() {
-                if can_map.id == frame.id() {
-                    match can_map.bits {
-                        CanBitSelection::Bit(bit_i) => {
-                            let byte = data[(bit_i as usize) / 8];
-                            let bit_in_byte = bit_i % 8;
-                            let mask = 1 << bit_in_byte;
-                            param.set_value(
-                                    ((byte & mask) >> bit_in_byte) as f32 * can_map.scale,
-                                    millis);
-                        }
-                        CanBitSelection::BeUnsigned(i0, len) => {
-                            let bits = data.view_bits::<Msb0>();
-                            let raw = bits[i0 as usize .. (i0+len) as usize].load_be::<u64>();
-                            param.set_value(raw as f32 * can_map.scale, millis);
-                        }
-                        CanBitSelection::LeUnsigned(i0, len) => {
-                            let bits = data.view_bits::<Lsb0>();
-                            let raw = bits[i0 as usize .. (i0+len) as usize].load_le::<u64>();
-                            param.set_value(raw as f32 * can_map.scale, millis);
-                        }
-                        CanBitSelection::BeSigned(i0, len) => {
-                            let bits = data.view_bits::<Msb0>();
-                            let raw = bits[i0 as usize .. (i0+len) as usize].load_be::<i64>();
-                            param.set_value(raw as f32 * can_map.scale, millis);
-                        }
-                        CanBitSelection::LeSigned(i0, len) => {
-                            let bits = data.view_bits::<Lsb0>();
-                            let raw = bits[i0 as usize .. (i0+len) as usize].load_le::<i64>();
-                            param.set_value(raw as f32 * can_map.scale, millis);
-                        }
-                        CanBitSelection::Uint8(byte_i) => {
-                            param.set_value((data[byte_i as usize] as u8) as
-                                    f32 * can_map.scale,
-                                millis);
-                        }
-                        CanBitSelection::Int8(byte_i) => {
-                            param.set_value((data[byte_i as usize] as i8) as
-                                    f32 * can_map.scale,
-                                millis);
-                        }
-                        CanBitSelection::Function(function) => {
-                            param.set_value(function(data) * can_map.scale,
-                                millis);
-                        }
+            if let Some(problem) = bit_selection_problem(&can_map.bits) {
+                definition_error(&[param.name, ": can_map: ", problem]);
+            }
+            let mut mux_i = 0;
+            while mux_i < can_map.mux.len() {
+                if let Some(problem) = bit_selection_problem(&can_map.mux[mux_i].bits) {
+                    definition_error(&[param.name, ": can_map mux: ", problem]);
+                }
+                mux_i += 1;
+            }
+        }
+        if let Some(tx_can_map) = &param.tx_can_map {
+            if let CanBitSelection::Function(..) = tx_can_map.bits {
+                definition_error(&[param.name,
+                        ": tx_can_map: CanBitSelection::Function can't be encoded"]);
+            }
+            if let Some(problem) = bit_selection_problem(&tx_can_map.bits) {
+                definition_error(&[param.name, ": tx_can_map: ", problem]);
+            }
+            if tx_can_map.period_ms == 0 {
+                definition_error(&[param.name, ": tx_can_map: period_ms has to be set"]);
+            }
+        }
+        if let (Some(min), Some(max)) = (param.min, param.max) {
+            if min > max {
+                definition_error(&[param.name, ": min has to be less than or equal to max"]);
+            }
+        }
+        if let Some(derived) = &param.derived {
+            if param.can_map.is_some() {
+                definition_error(&[param.name, ": compute and can_map can't be used together"]);
+            }
+            if derived.inputs.len() > MAX_DERIVED_INPUTS {
+                definition_error(&[param.name, ": compute: too many inputs"]);
+            }
+            let mut input_i = 0;
+            while input_i < derived.inputs.len() {
+                let input = derived.inputs[input_i];
+                if input == i {
+                    definition_error(&[param.name, ": compute: a parameter can't be its own input"]);
+                }
+                // Derived parameters are evaluated once per update in definition
+                // order, which also rules out cycles
+                if input > i && params[input].derived.is_some() {
+                    definition_error(&[param.name, ": compute: input ", params[input].name,
+                            " is derived and has to be defined before ", param.name]);
+                }
+                input_i += 1;
+            }
+            if param.writable {
+                definition_error(&[param.name, ": compute and writable can't be used together"]);
+            }
+        }
+        if let Some(history) = &param.history {
+            if history.samples == 0 || history.interval_ms == 0 {
+                definition_error(&[param.name, ": history: samples and interval_ms have to be set"]);
+            }
+        }
+        if let Some(report_map) = &param.report_map {
+            if !report_name_is_valid(report_map.name) {
+                definition_error(&[param.name, ": ReportMap name \"", report_map.name,
+                        "\" has to be non-empty and contain only A-Z, a-z, 0-9, _, - and ."]);
+            }
+            let mut j = 0;
+            while j < i {
+                if let Some(other) = &params[j].report_map {
+                    if str_eq(other.name, report_map.name) {
+                        definition_error(&[param.name, ": ReportMap name \"", report_map.name,
+                                "\" is already used by ", params[j].name]);
                     }
                 }
+                j += 1;
             }
         }
+        i += 1;
+    }
+}
+
+// Marks parameters stale when they haven't been updated within their
+// timeout_ms. The last value is kept.
+pub fn update_parameter_timeouts(params: &mut [Parameter], millis: u64) {
+    for param in params.iter_mut() {
+        if param.timeout_ms == 0 {
+            continue;
+        }
+        if let ParameterQuality::Valid | ParameterQuality::OutOfRange = param.quality {
+            if millis.saturating_sub(param.update_timestamp) >= param.timeout_ms {
+                param.set_quality(ParameterQuality::Stale);
+            }
+        }
+    }
+}
+
+// Checks every parameter. ParameterStore::update_on_can() does the same using
+// its dispatch table.
+pub fn update_parameters_on_can(params: &mut [Parameter], frame: bxcan::Frame,
+        millis: u64) {
+    for param in params.iter_mut() {
+        update_parameter_on_can(param, &frame, millis);
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum NameLookupError {
+    NotFound,
+    Ambiguous, // Several display names match; use the identifier instead
+}
+
+// Matches either the identifier ("AuxVoltage") or the display name ("Aux
+// battery"), ignoring case and spaces. An identifier match wins, as display
+// names need not be unique.
+pub fn get_parameter_by_name<'p, 'a>(params: &'p [Parameter<'a>], name: &str)
+        -> Result<&'p Parameter<'a>, NameLookupError> {
+    if let Some(param) = params.iter().find(|param| names_match(param.name, name)) {
+        return Ok(param);
+    }
+    let mut matches = params.iter().filter(|param| names_match(param.display_name, name));
+    match (matches.next(), matches.next()) {
+        (Some(param), None) => Ok(param),
+        (Some(_), Some(_)) => Err(NameLookupError::Ambiguous),
+        (None, _) => Err(NameLookupError::NotFound),
+    }
+}
+
+// Matches ReportMap::name exactly
+pub fn get_parameter_by_report_key<'p, 'a>(params: &'p [Parameter<'a>], key: &str)
+        -> Option<&'p Parameter<'a>> {
+    params.iter().find(|param| {
+        param.report_map.as_ref().is_some_and(|report_map| report_map.name == key)
+    })
+}
+
+fn names_match(a: &str, b: &str) -> bool {
+    let a = a.chars().filter(|c| *c != ' ').map(|c| c.to_ascii_lowercase());
+    let b = b.chars().filter(|c| *c != ' ').map(|c| c.to_ascii_lowercase());
+    a.eq(b)
+}
+
+pub(crate) fn update_parameter_on_can(param: &mut Parameter, frame: &bxcan::Frame, millis: u64) {
+    let (Some(can_map), Some(data)) = (&param.can_map, frame.data()) else {
+        return;
+    };
+    if can_map.id != frame.id() {
+        return;
+    }
+    let value = match can_map.mux_matches(data) {
+        Some(true) => can_map.decode_value(data, param.value_type),
+        Some(false) => return,
+        None => None,
+    };
+    match value {
+        Some(value) => param.set_value(value, millis),
+        None => {
+            param.decode_error_count = param.decode_error_count.saturating_add(1);
+            param.set_quality(ParameterQuality::DecodeError);
+            // Don't flood the log if a node keeps sending short frames
+            if param.decode_error_count.is_power_of_two() {
+                warn!("{}: CAN frame {:?} too short ({} bytes), {} errors",
+                        param.name, frame.id(), data.len(), param.decode_error_count);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATA: [u8; 8] = [0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde, 0xf0];
+
+    // Returns the shortest frame length the selection decodes from
+    fn min_len(bits: &CanBitSelection) -> usize {
+        (0..=8).find(|&len| bits.fits(&DATA[..len])).expect("Doesn't fit in 8 bytes")
+    }
+
+    fn assert_short_frames_fail(bits: CanBitSelection, expected_min_len: usize) {
+        assert_eq!(min_len(&bits), expected_min_len);
+        for len in 0..expected_min_len {
+            assert_eq!(bits.decode(&DATA[..len]), None, "len {}", len);
+        }
+        assert!(bits.decode(&DATA[..expected_min_len]).is_some());
+        assert!(bits.decode(&DATA).is_some());
+    }
+
+    #[test]
+    fn short_frame_bit() {
+        assert_short_frames_fail(CanBitSelection::Bit(0), 1);
+        assert_short_frames_fail(CanBitSelection::Bit(5 * 8 + 7), 6);
+    }
+
+    #[test]
+    fn short_frame_be_unsigned() {
+        assert_short_frames_fail(CanBitSelection::BeUnsigned(8, 16), 3);
+        assert_short_frames_fail(CanBitSelection::BeUnsigned(0, 64), 8);
+    }
+
+    #[test]
+    fn short_frame_le_unsigned() {
+        assert_short_frames_fail(CanBitSelection::LeUnsigned(4, 12), 2);
+        assert_short_frames_fail(CanBitSelection::LeUnsigned(60, 4), 8);
+    }
+
+    #[test]
+    fn short_frame_be_signed() {
+        assert_short_frames_fail(CanBitSelection::BeSigned(16, 8), 3);
+    }
+
+    #[test]
+    fn short_frame_le_signed() {
+        assert_short_frames_fail(CanBitSelection::LeSigned(17, 15), 4);
+    }
+
+    #[test]
+    fn short_frame_uint8() {
+        assert_short_frames_fail(CanBitSelection::Uint8(7), 8);
+    }
+
+    #[test]
+    fn short_frame_int8() {
+        assert_short_frames_fail(CanBitSelection::Int8(2), 3);
+    }
+
+    #[test]
+    fn short_frame_le_float32() {
+        assert_short_frames_fail(CanBitSelection::LeFloat32(8), 5);
+    }
+
+    #[test]
+    fn short_frame_be_float32() {
+        assert_short_frames_fail(CanBitSelection::BeFloat32(32), 8);
+    }
+
+    #[test]
+    fn short_frame_function() {
+        assert_short_frames_fail(CanBitSelection::Function(4, |data| data[3] as f32), 4);
+    }
+
+    #[test]
+    fn invalid_length_fails() {
+        assert_eq!(CanBitSelection::LeUnsigned(0, 0).decode(&DATA), None);
+        assert_eq!(CanBitSelection::BeSigned(0, 65).decode(&DATA), None);
+    }
+
+    #[test]
+    fn scale_and_offset_apply() {
+        let can_map = CanMap {
+            bits: CanBitSelection::LeUnsigned(0, 16),
+            scale: 0.1,
+            offset: -40.0,
+            ..CanMap::DEFAULT
+        };
+        // 0x3412 = 13330
+        assert_eq!(can_map.decode(&DATA), Some(13330.0 * 0.1 - 40.0));
+        let can_map = CanMap {
+            bits: CanBitSelection::Int8(7),
+            scale: 2.0,
+            offset: 1.0,
+            ..CanMap::DEFAULT
+        };
+        assert_eq!(can_map.decode(&DATA), Some(-16.0 * 2.0 + 1.0));
+        // Integer types are scaled without going through f32
+        let can_map = CanMap {
+            bits: CanBitSelection::LeUnsigned(0, 32),
+            scale: 1.0,
+            offset: 1.0,
+            ..CanMap::DEFAULT
+        };
+        assert_eq!(can_map.decode_value(&DATA, ParameterType::U32),
+                Some(ParameterValue::U32(0x7856_3413)));
+    }
+
+    #[test]
+    fn float32_byte_orders_decode() {
+        let mut le = [0u8; 8];
+        le[1..5].copy_from_slice(&(-12.5f32).to_le_bytes());
+        let mut be = [0u8; 8];
+        be[1..5].copy_from_slice(&(-12.5f32).to_be_bytes());
+        assert_eq!(CanBitSelection::LeFloat32(8).decode(&le), Some(-12.5));
+        assert_eq!(CanBitSelection::BeFloat32(8).decode(&be), Some(-12.5));
+        assert_ne!(CanBitSelection::BeFloat32(8).decode(&le), Some(-12.5));
+        let can_map = CanMap {
+            bits: CanBitSelection::BeFloat32(8),
+            scale: 2.0,
+            offset: 5.0,
+            ..CanMap::DEFAULT
+        };
+        assert_eq!(can_map.decode(&be), Some(-20.0));
+        // Encoding gives back the same bytes
+        let mut data = [0u8; 8];
+        assert!(CanBitSelection::LeFloat32(8).encode(&mut data, -12.5));
+        assert_eq!(data, le);
+        data = [0u8; 8];
+        assert!(CanBitSelection::BeFloat32(8).encode(&mut data, -12.5));
+        assert_eq!(data, be);
+    }
+
+    #[test]
+    fn value_labels_are_looked_up() {
+        let can_map = CanMap {
+            bits: CanBitSelection::Uint8(0),
+            scale: 0.5,
+            offset: 10.0,
+            value_table: &[(0, "Off"), (0x22, "Running")],
+            ..CanMap::DEFAULT
+        };
+        assert_eq!(can_map.value_label(10.0), Some("Off"));
+        assert_eq!(can_map.value_label(0x22 as f32 * 0.5 + 10.0), Some("Running"));
+        assert_eq!(can_map.value_label(can_map.decode(&[0x22]).unwrap()), Some("Running"));
+        assert_eq!(can_map.value_label(11.0), None);
+        assert_eq!(can_map.value_label(f32::NAN), None);
+
+        let mut param = Parameter::new(0, "Test", "Test", f32::NAN, 0, "",
+                Some(can_map));
+        assert_eq!(param.value_label(), None);
+        param.set_value(27.0, 10);
+        assert_eq!(param.value_label(), Some("Running"));
+    }
+
+    #[test]
+    fn short_multiplexer_fails() {
+        static MUX: [CanMux; 1] = [CanMux { bits: CanBitSelection::Uint8(4), value: 0x9a }];
+        let can_map = CanMap {
+            bits: CanBitSelection::Uint8(0),
+            mux: &MUX,
+            ..CanMap::DEFAULT
+        };
+        assert_eq!(can_map.mux_matches(&DATA[..4]), None);
+        assert_eq!(can_map.mux_matches(&DATA[..5]), Some(true));
+    }
+
+    #[test]
+    fn multiplexed_parameters_decode_on_their_page() {
+        static PAGE1: [CanMux; 1] = [CanMux { bits: CanBitSelection::Uint8(0), value: 1 }];
+        static PAGE2: [CanMux; 1] = [CanMux { bits: CanBitSelection::Uint8(0), value: 2 }];
+        // Page 2 has sub-pages selected by the low nibble of byte 1
+        static PAGE2_SUB5: [CanMux; 2] = [
+            CanMux { bits: CanBitSelection::Uint8(0), value: 2 },
+            CanMux { bits: CanBitSelection::LeUnsigned(8, 4), value: 5 },
+        ];
+        let id = standard_id(0x123);
+        let param = |i, bits, mux| Parameter::new(i, "Test", "Test", f32::NAN, 0, "",
+                Some(CanMap { id, bits, mux, ..CanMap::DEFAULT }));
+        let mut params = [
+            param(0, CanBitSelection::Uint8(2), &PAGE1),
+            param(1, CanBitSelection::Uint8(2), &PAGE2),
+            param(2, CanBitSelection::Uint8(3), &PAGE2_SUB5),
+        ];
+        let frame = |data: &[u8]| bxcan::Frame::new_data(StandardId::new(0x123).unwrap(),
+                bxcan::Data::new(data).unwrap());
+        let values = |params: &[Parameter]| [0, 1, 2].map(|i| params[i].value.as_f32());
+
+        update_parameters_on_can(&mut params, frame(&[1, 0x00, 10, 11]), 10);
+        assert_eq!(params[0].value, ParameterValue::F32(10.0));
+        assert!(params[1].value.is_nan() && params[2].value.is_nan());
+
+        // Sub-page 4 is the wrong inner mux value
+        update_parameters_on_can(&mut params, frame(&[2, 0x34, 20, 21]), 20);
+        assert_eq!(params[1].value, ParameterValue::F32(20.0));
+        assert!(params[2].value.is_nan());
+        assert_eq!(params[0].value, ParameterValue::F32(10.0));
+
+        update_parameters_on_can(&mut params, frame(&[2, 0xf5, 30, 31]), 30);
+        assert_eq!(values(&params), [10.0, 30.0, 31.0]);
+
+        // Sub-page 5 of a page nobody listens to
+        update_parameters_on_can(&mut params, frame(&[3, 0x05, 40, 41]), 40);
+        assert_eq!(values(&params), [10.0, 30.0, 31.0]);
+        assert_eq!(params.iter().map(|p| p.update_timestamp).max(), Some(30));
+        assert!(params.iter().all(|p| p.decode_error_count == 0));
+    }
+
+    #[test]
+    fn short_frame_counts_decode_errors() {
+        let id = bxcan::Id::Standard(StandardId::new(0x123).unwrap());
+        let mut params = [Parameter::new(0, "Test", "Test", f32::NAN, 0, "",
+                Some(CanMap { id, bits: CanBitSelection::Uint8(3), ..CanMap::DEFAULT }))];
+        let short = bxcan::Frame::new_data(StandardId::new(0x123).unwrap(),
+                bxcan::Data::new(&DATA[..3]).unwrap());
+        update_parameters_on_can(&mut params, short.clone(), 10);
+        update_parameters_on_can(&mut params, short, 20);
+        assert_eq!(params[0].decode_error_count, 2);
+        assert!(params[0].value.is_nan());
+        assert_eq!(params[0].update_timestamp, 0);
+
+        let full = bxcan::Frame::new_data(StandardId::new(0x123).unwrap(),
+                bxcan::Data::new(&DATA).unwrap());
+        update_parameters_on_can(&mut params, full, 30);
+        assert_eq!(params[0].value, ParameterValue::F32(0x78 as f32));
+        assert_eq!(params[0].decode_error_count, 2);
+    }
+
+    #[test]
+    fn stale_parameters_keep_last_value() {
+        use ParameterQuality::*;
+        let rx = |bits| Some(CanMap { id: standard_id(0x123), bits, ..CanMap::DEFAULT });
+        let mut params = [
+            Parameter::new(0, "Fast", "Fast", f32::NAN, 0, "", rx(CanBitSelection::Uint8(0))),
+            Parameter::new(1, "Slow", "Slow", f32::NAN, 0, "", rx(CanBitSelection::Uint8(1))),
+            Parameter::new(2, "Local", "Local", 1.0, 0, "", None),
+        ];
+        params[0].timeout_ms = 100;
+        params[0].max = Some(50.0);
+        assert_eq!(params[1].timeout_ms, DEFAULT_CAN_TIMEOUT_MS);
+        assert_eq!(params[2].timeout_ms, 0);
+        let quality = |params: &[Parameter; 3]| params.each_ref().map(|p| p.quality);
+        let frame = |data: &[u8]| bxcan::Frame::new_data(StandardId::new(0x123).unwrap(),
+                bxcan::Data::new(data).unwrap());
+        assert_eq!(quality(&params), [NeverReceived, NeverReceived, Valid]);
+        update_parameter_timeouts(&mut params, 10_000);
+        assert_eq!(quality(&params), [NeverReceived, NeverReceived, Valid]);
+
+        update_parameters_on_can(&mut params, frame(&[10, 20]), 10_000);
+        assert_eq!(quality(&params), [Valid, Valid, Valid]);
+        update_parameter_timeouts(&mut params, 10_099);
+        assert_eq!(params[0].quality, Valid);
+        update_parameter_timeouts(&mut params, 10_100);
+        assert_eq!(quality(&params), [Stale, Valid, Valid]);
+        // The value is kept for displaying, but isn't current
+        assert_eq!(params[0].value, ParameterValue::F32(10.0));
+        assert!(params[0].current().is_none());
+        assert!(params[0].current_value().is_nan());
+
+        update_parameters_on_can(&mut params, frame(&[11, 20]), 10_200);
+        assert_eq!(params[0].quality, Valid);
+        assert_eq!(params[0].current_value(), 11.0);
+        update_parameter_timeouts(&mut params, 15_200);
+        assert_eq!(quality(&params), [Stale, Stale, Valid]);
+        assert_eq!(params[1].value, ParameterValue::F32(20.0));
+
+        // A fresh value outside min..max is still current, and times out too
+        update_parameters_on_can(&mut params, frame(&[60, 21]), 15_300);
+        assert_eq!(quality(&params), [OutOfRange, Valid, Valid]);
+        assert_eq!(params[0].current_value(), 60.0);
+        update_parameter_timeouts(&mut params, 15_400);
+        assert_eq!(params[0].quality, Stale);
+
+        // A short frame marks the parameter it doesn't fit, which then stays
+        // that way until a good frame arrives
+        update_parameters_on_can(&mut params, frame(&[12]), 15_500);
+        assert_eq!(quality(&params), [Valid, DecodeError, Valid]);
+        assert_eq!(params[1].value, ParameterValue::F32(21.0));
+        update_parameter_timeouts(&mut params, 30_000);
+        assert_eq!(quality(&params), [Stale, DecodeError, Valid]);
+        update_parameters_on_can(&mut params, frame(&[13, 22]), 30_100);
+        assert_eq!(quality(&params), [Valid, Valid, Valid]);
+    }
+
+    // Builds the dispatch table at runtime; define_parameters! does it at
+    // compile time
+    fn new_store<const N: usize, const C: usize>(params: [Parameter<'static>; N])
+            -> ParameterStore<N, C> {
+        let can_dispatch = can_dispatch::CanDispatchTable::build(&params);
+        ParameterStore::new(params, std::boxed::Box::leak(std::boxed::Box::new(can_dispatch)))
+    }
+
+    #[test]
+    fn parameter_stores_are_independent() {
+        let id = bxcan::Id::Standard(StandardId::new(0x123).unwrap());
+        let definitions = || [
+            Parameter::new(0, "Plain", "Plain", 1.0, 0, "", None),
+            Parameter::new(1, "Test", "Test", f32::NAN, 0, "",
+                    Some(CanMap { id, bits: CanBitSelection::Uint8(3), ..CanMap::DEFAULT })),
+        ];
+        let mut store1: ParameterStore<2, 1> = new_store(definitions());
+        let store2: ParameterStore<2, 1> = new_store(definitions());
+        let frame = bxcan::Frame::new_data(StandardId::new(0x123).unwrap(),
+                bxcan::Data::new(&DATA).unwrap());
+        store1.update_on_can(frame, 10);
+        assert_eq!(store1[1usize].value, ParameterValue::F32(0x78 as f32));
+        assert!(store2[1usize].value.is_nan());
+        store1.get_parameter_mut(0usize).set_value(2.0, 20);
+        assert_eq!(store2.get_parameter(0usize).value, ParameterValue::F32(1.0));
+    }
+
+    fn typed_parameter(value_type: ParameterType, bits: CanBitSelection) -> Parameter<'static> {
+        let id = bxcan::Id::Standard(StandardId::new(0x123).unwrap());
+        let mut param = Parameter::new(0, "Test", "Test", f32::NAN, 0, "",
+                Some(CanMap { id, bits, ..CanMap::DEFAULT }));
+        param.value_type = value_type;
+        param.value = value_type.from_f32(f32::NAN);
+        param
+    }
+
+    fn receive(param: Parameter<'static>, data: &[u8]) -> Parameter<'static> {
+        let mut params = [param];
+        let frame = bxcan::Frame::new_data(StandardId::new(0x123).unwrap(),
+                bxcan::Data::new(data).unwrap());
+        update_parameters_on_can(&mut params, frame, 10);
+        let [param] = params;
+        param
+    }
+
+    #[test]
+    fn typed_values_decode() {
+        let param = typed_parameter(ParameterType::Bool, CanBitSelection::Bit(4));
+        assert_eq!(param.current_bool(), None);
+        assert_eq!(receive(param, &[0x10]).current_bool(), Some(true));
+
+        // Doesn't fit in f32 exactly
+        let param = typed_parameter(ParameterType::U32, CanBitSelection::LeUnsigned(0, 32));
+        assert_eq!(receive(param, &[0x01, 0x00, 0x00, 0xff]).value,
+                ParameterValue::U32(0xff00_0001));
+
+        let param = typed_parameter(ParameterType::I32, CanBitSelection::LeSigned(0, 16));
+        assert_eq!(receive(param, &[0xfe, 0xff]).value, ParameterValue::I32(-2));
+
+        let param = typed_parameter(ParameterType::Enum(&[(0x22, "Running")]),
+                CanBitSelection::Uint8(0));
+        let param = receive(param, &[0x22]);
+        assert_eq!(param.value, ParameterValue::Enum(0x22));
+        assert_eq!(param.value_label(), Some("Running"));
+    }
+
+    #[test]
+    fn set_value_converts_to_type() {
+        let mut param = typed_parameter(ParameterType::Bool, CanBitSelection::Bit(0));
+        param.set_value(0.7, 10);
+        assert_eq!(param.value, ParameterValue::Bool(true));
+        let mut param = typed_parameter(ParameterType::U32, CanBitSelection::Uint8(0));
+        param.set_value(-5.0, 10);
+        assert_eq!(param.value, ParameterValue::U32(0));
+        let mut param = typed_parameter(ParameterType::F32, CanBitSelection::Uint8(0));
+        param.set_value(true, 10);
+        assert_eq!(param.value, ParameterValue::F32(1.0));
+    }
+
+    #[test]
+    fn typed_values_parse() {
+        assert_eq!(ParameterType::F32.parse("-1.5"), Some(ParameterValue::F32(-1.5)));
+        assert_eq!(ParameterType::Bool.parse("on"), Some(ParameterValue::Bool(true)));
+        assert_eq!(ParameterType::Bool.parse("2"), None);
+        assert_eq!(ParameterType::U32.parse("-1"), None);
+        let status = ParameterType::Enum(&[(0x22, "Running")]);
+        assert_eq!(status.parse("running"), Some(ParameterValue::Enum(0x22)));
+        assert_eq!(status.parse("5"), Some(ParameterValue::Enum(5)));
+    }
+
+    #[test]
+    fn derived_parameter_follows_inputs() {
+        let id = bxcan::Id::Standard(StandardId::new(0x123).unwrap());
+        let mut input = Parameter::new(0, "Input", "Input", f32::NAN, 0, "",
+                Some(CanMap { id, bits: CanBitSelection::Uint8(0), ..CanMap::DEFAULT }));
+        input.timeout_ms = 100;
+        let mut output = Parameter::new(1, "Output", "Output", f32::NAN, 0, "", None);
+        output.derived = Some(DerivedParameter { inputs: &[0], compute: |v| v[0] * 2.0 });
+        let mut store: ParameterStore<2, 1> = new_store([input, output]);
+        let frame = |value: u8| bxcan::Frame::new_data(StandardId::new(0x123).unwrap(),
+                bxcan::Data::new(&[value]).unwrap());
+
+        store.update_derived(0);
+        assert_eq!(store[1usize].quality, ParameterQuality::NeverReceived);
+
+        store.update_on_can(frame(5), 10);
+        store.update_derived(10);
+        assert_eq!(store[1usize].current_value(), 10.0);
+        store.update_on_can(frame(6), 20);
+        store.update_derived(20);
+        assert_eq!(store[1usize].current_value(), 12.0);
+
+        // The input times out. The output keeps its last value, but as stale.
+        store.update_timeouts(200);
+        store.update_derived(200);
+        assert_eq!(store[1usize].quality, ParameterQuality::Stale);
+        assert_eq!(store[1usize].value, ParameterValue::F32(12.0));
+
+        store.update_on_can(frame(7), 210);
+        store.update_derived(210);
+        assert_eq!(store[1usize].current_value(), 14.0);
+    }
+
+    #[test]
+    fn out_of_range_values_are_flagged() {
+        let mut param = Parameter::new(0, "Test", "Test", f32::NAN, 0, "", None);
+        param.min = Some(0.0);
+        param.max = Some(10.0);
+        param.set_value(10.0, 10);
+        assert_eq!(param.quality, ParameterQuality::Valid);
+        param.set_value(10.5, 20);
+        assert_eq!(param.quality, ParameterQuality::OutOfRange);
+        assert_eq!(param.value, ParameterValue::F32(10.5));
+        param.set_value(-1.0, 30);
+        assert_eq!(param.out_of_range_count, 2);
+        param.set_value(5.0, 40);
+        assert_eq!(param.quality, ParameterQuality::Valid);
+        assert_eq!(param.out_of_range_count, 2);
+    }
+
+    #[test]
+    fn changes_get_sequence_numbers() {
+        let mut store: ParameterStore<2, 0> = new_store([
+            Parameter::new(0, "A", "A", 1.0, 0, "", None),
+            Parameter::new(1, "B", "B", 2.0, 0, "", None),
+        ]);
+        let seq0 = store.commit_changes();
+        assert_eq!(store.changed_since(seq0).count(), 0);
+
+        store[1usize].set_value(3.0, 10);
+        let seq1 = store.commit_changes();
+        assert!(store[1usize].changed_since(seq0));
+        assert!(!store[0usize].changed_since(seq0));
+        assert!(store.changed_since(seq0).map(|p| p.id).eq([1]));
+
+        // Setting the same value again is not a change
+        store[1usize].set_value(3.0, 20);
+        assert_eq!(store.commit_changes(), seq1);
+        assert_eq!(store.changed_since(seq1).count(), 0);
+
+        // Neither is a new NaN, but a change of quality is
+        store[0usize].set_value(f32::NAN, 30);
+        store.commit_changes();
+        store[0usize].set_value(f32::NAN, 40);
+        assert_eq!(store.commit_changes(), seq1.wrapping_add(1));
+        store[0usize].timeout_ms = 5;
+        store.update_timeouts(100);
+        assert!(store.commit_changes() != seq1.wrapping_add(1));
+        assert_eq!(store[0usize].quality, ParameterQuality::Stale);
+    }
+
+    #[test]
+    fn history_keeps_latest_samples() {
+        let mut params = [
+            Parameter::new(0, "A", "A", f32::NAN, 0, "", None),
+            Parameter::new(1, "B", "B", 5.0, 0, "", None),
+        ];
+        params[0].history = Some(History { samples: 3, interval_ms: 100 });
+        let mut history: history::ParameterHistory<2, 3> =
+            history::ParameterHistory::new(&params);
+        assert_eq!(history.stats(0usize), None);
+
+        for (millis, value) in [(0, 1.0), (50, 9.0), (100, 2.0), (200, 3.0), (300, 6.0)] {
+            params[0].set_value(value, millis);
+            history.update(&params, millis);
+        }
+        // The sample at 50ms was decimated away and the one at 0ms overwritten
+        assert!(history.samples(0usize).map(|s| s.millis).eq([100, 200, 300]));
+        let stats = history.stats(0usize).unwrap();
+        assert_eq!((stats.min, stats.max, stats.mean, stats.count), (2.0, 6.0, 11.0 / 3.0, 3));
+        assert_eq!(history.samples(1usize).count(), 0);
+    }
+
+    #[test]
+    fn parameters_are_found_by_name() {
+        let mut params = [
+            Parameter::new(0, "AuxVoltage", "Aux battery", 0.0, 0, "", None),
+            Parameter::new(1, "Soc", "SoC", 0.0, 0, "", None),
+        ];
+        params[0].report_map = Some(ReportMap { name: "vaux", decimals: 1, scale: 1.0 });
+        let find = |name| get_parameter_by_name(&params, name).map(|p| p.id);
+        assert_eq!(find("AuxVoltage"), Ok(0));
+        assert_eq!(find("aux battery"), Ok(0));
+        assert_eq!(find("AUXBATTERY"), Ok(0));
+        assert_eq!(find("soc"), Ok(1));
+        assert_eq!(find("So"), Err(NameLookupError::NotFound));
+        assert_eq!(get_parameter_by_report_key(&params, "vaux").map(|p| p.id), Some(0));
+        assert!(get_parameter_by_report_key(&params, "er").is_none());
+    }
+
+    #[test]
+    fn duplicate_display_names_are_ambiguous() {
+        let params = [
+            Parameter::new(0, "TripKm", "Trip", 0.0, 0, "km", None),
+            Parameter::new(1, "TripEnergy", "Trip", 0.0, 0, "Wh", None),
+            Parameter::new(2, "Trip", "Trip counter", 0.0, 0, "", None),
+            Parameter::new(3, "RangeKm", "Range", 0.0, 0, "km", None),
+        ];
+        let find = |name| get_parameter_by_name(&params, name).map(|p| p.id);
+        assert_eq!(find("tripkm"), Ok(0));
+        assert_eq!(find("TripEnergy"), Ok(1));
+        // The identifier wins over the display names of the others
+        assert_eq!(find("Trip"), Ok(2));
+        assert_eq!(find("range"), Ok(3));
+
+        let params = &params[..2];
+        assert_eq!(get_parameter_by_name(params, "Trip").map(|p| p.id),
+                Err(NameLookupError::Ambiguous));
     }
 }
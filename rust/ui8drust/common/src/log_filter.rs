@@ -0,0 +1,176 @@
+// Log level filtering by target and sink
+//
+// Each platform's logger owns a LogFilter and checks every record against it.
+// A rule applies to a log target (normally the module path, e.g.
+// "common::sim7600") and everything below it, with the longest matching rule
+// winning. Targets without a rule use the default levels. The filter can be
+// changed at runtime through HardwareInterface::set_log_filter(), e.g. by the
+// "loglevel" console command.
+
+use arrayvec::ArrayString;
+use log::{LevelFilter, Metadata};
+
+pub const MAX_LOG_RULES: usize = 8;
+pub const MAX_LOG_TARGET_LEN: usize = 32;
+
+// Where log output goes on hardware. The desktop version writes to stderr
+// using the Usb levels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogSink {
+    Uart,
+    Usb,
+    Display,
+}
+
+impl LogSink {
+    pub const ALL: [LogSink; 3] = [LogSink::Uart, LogSink::Usb, LogSink::Display];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LogSink::Uart => "uart",
+            LogSink::Usb => "usb",
+            LogSink::Display => "display",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<LogSink> {
+        Self::ALL.into_iter().find(|sink| sink.name().eq_ignore_ascii_case(name))
+    }
+}
+
+// Indexed by LogSink
+pub type LogLevels = [LevelFilter; LogSink::ALL.len()];
+
+#[derive(Debug, Clone, Copy)]
+struct LogRule {
+    target: ArrayString<MAX_LOG_TARGET_LEN>,
+    levels: LogLevels,
+}
+
+impl LogRule {
+    const EMPTY: LogRule = LogRule {
+        target: ArrayString::new_const(),
+        levels: [LevelFilter::Off; LogSink::ALL.len()],
+    };
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LogFilterError {
+    TooManyRules,
+    TargetTooLong,
+}
+
+// Copy, so that loggers can hand out their filter for modification
+#[derive(Debug, Clone, Copy)]
+pub struct LogFilter {
+    default_levels: LogLevels,
+    rules: [LogRule; MAX_LOG_RULES],
+    num_rules: usize,
+}
+
+impl LogFilter {
+    pub const fn new(level: LevelFilter) -> Self {
+        Self {
+            default_levels: [level; LogSink::ALL.len()],
+            rules: [LogRule::EMPTY; MAX_LOG_RULES],
+            num_rules: 0,
+        }
+    }
+
+    pub fn enabled(&self, sink: LogSink, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level(sink, metadata.target())
+    }
+
+    pub fn level(&self, sink: LogSink, target: &str) -> LevelFilter {
+        self.levels(target)[sink as usize]
+    }
+
+    pub fn levels(&self, target: &str) -> LogLevels {
+        self.rules[..self.num_rules].iter()
+            .filter(|rule| target_matches(&rule.target, target))
+            .max_by_key(|rule| rule.target.len())
+            .map_or(self.default_levels, |rule| rule.levels)
+    }
+
+    // target None sets the default levels and sink None sets all sinks. Other
+    // sinks of a new rule start from the levels the target had so far.
+    pub fn set(&mut self, target: Option<&str>, sink: Option<LogSink>, level: LevelFilter)
+            -> Result<(), LogFilterError> {
+        let levels = match target {
+            None => &mut self.default_levels,
+            Some(target) => {
+                let existing = self.rules[..self.num_rules].iter()
+                        .position(|rule| rule.target.as_str() == target);
+                let i = match existing {
+                    Some(i) => i,
+                    None => {
+                        if self.num_rules == MAX_LOG_RULES {
+                            return Err(LogFilterError::TooManyRules);
+                        }
+                        self.rules[self.num_rules] = LogRule {
+                            target: ArrayString::from(target)
+                                    .map_err(|_| LogFilterError::TargetTooLong)?,
+                            levels: self.levels(target),
+                        };
+                        self.num_rules += 1;
+                        self.num_rules - 1
+                    }
+                };
+                &mut self.rules[i].levels
+            }
+        };
+        match sink {
+            Some(sink) => levels[sink as usize] = level,
+            None => *levels = [level; LogSink::ALL.len()],
+        }
+        Ok(())
+    }
+
+    // Pass this to log::set_max_level() after changing the filter
+    pub fn max_level(&self) -> LevelFilter {
+        self.rules[..self.num_rules].iter()
+            .flat_map(|rule| rule.levels)
+            .chain(self.default_levels)
+            .max()
+            .unwrap_or(LevelFilter::Off)
+    }
+
+    pub fn default_levels(&self) -> &LogLevels {
+        &self.default_levels
+    }
+
+    pub fn rules(&self) -> impl Iterator<Item = (&str, &LogLevels)> {
+        self.rules[..self.num_rules].iter().map(|rule| (rule.target.as_str(), &rule.levels))
+    }
+}
+
+// A rule for "common" applies to "common::sim7600" but not to "commonx"
+fn target_matches(rule_target: &str, target: &str) -> bool {
+    match target.strip_prefix(rule_target) {
+        Some(rest) => rest.is_empty() || rest.starts_with("::"),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+
+    #[test]
+    fn rules_apply_by_target_and_sink() {
+        let mut filter = LogFilter::new(LevelFilter::Info);
+        filter.set(Some("common"), Some(LogSink::Uart), LevelFilter::Warn).unwrap();
+        filter.set(Some("common::sim7600"), None, LevelFilter::Trace).unwrap();
+        assert_eq!(filter.level(LogSink::Uart, "common::can"), LevelFilter::Warn);
+        assert_eq!(filter.level(LogSink::Usb, "common::can"), LevelFilter::Info);
+        assert_eq!(filter.level(LogSink::Uart, "common::sim7600::x"), LevelFilter::Trace);
+        assert_eq!(filter.level(LogSink::Uart, "commonx"), LevelFilter::Info);
+        assert_eq!(filter.max_level(), LevelFilter::Trace);
+        let metadata = log::Metadata::builder().level(Level::Info).target("common").build();
+        assert!(!filter.enabled(LogSink::Uart, &metadata));
+        assert!(filter.enabled(LogSink::Display, &metadata));
+        assert_eq!(filter.set(Some("a_very_long_target_name::that_does_not_fit"), None,
+                LevelFilter::Off), Err(LogFilterError::TargetTooLong));
+    }
+}
@@ -0,0 +1,168 @@
+// Owned parameter storage
+//
+// Holds the parameter array generated by define_parameters! together with a
+// reference to its CAN dispatch table, which the macro builds at compile time. Whoever owns the store (normally MainState) passes it
+// around explicitly, so there is no global state and any number of stores can
+// exist at once, e.g. in tests.
+
+use crate::can_dispatch::CanDispatchTable;
+use crate::{
+    get_parameter_by_name, get_parameter_by_report_key, update_parameter_on_can,
+    update_parameter_timeouts, NameLookupError, Parameter, ParameterQuality, MAX_DERIVED_INPUTS,
+};
+use core::ops::{Index, IndexMut};
+
+// N is the number of parameters and C the number of CAN mapped parameters. See
+// can_dispatch::count_can_mapped().
+pub struct ParameterStore<const N: usize, const C: usize> {
+    params: [Parameter<'static>; N],
+    can_dispatch: &'static CanDispatchTable<C>,
+    sequence: u32, // Of the latest commit_changes()
+}
+
+impl<const N: usize, const C: usize> ParameterStore<N, C> {
+    // define_parameters! generates new_parameter_store() which calls this.
+    // can_dispatch has to be built from the same parameters.
+    pub fn new(params: [Parameter<'static>; N], can_dispatch: &'static CanDispatchTable<C>)
+            -> Self {
+        Self {
+            params,
+            can_dispatch,
+            sequence: 0,
+        }
+    }
+
+    // id can be a usize or the ParameterId generated by define_parameters!
+    pub fn get_parameter(&self, id: impl Into<usize>) -> &Parameter<'static> {
+        &self.params[id.into()]
+    }
+
+    pub fn get_parameter_mut(&mut self, id: impl Into<usize>) -> &mut Parameter<'static> {
+        &mut self.params[id.into()]
+    }
+
+    // See get_parameter_by_name(). Use the returned parameter's id to modify it.
+    pub fn get_parameter_by_name(&self, name: &str)
+            -> Result<&Parameter<'static>, NameLookupError> {
+        get_parameter_by_name(&self.params, name)
+    }
+
+    pub fn get_parameter_by_report_key(&self, key: &str) -> Option<&Parameter<'static>> {
+        get_parameter_by_report_key(&self.params, key)
+    }
+
+    pub fn as_slice(&self) -> &[Parameter<'static>] {
+        &self.params
+    }
+
+    // Don't change the CAN mappings through this; the dispatch table is built
+    // at compile time
+    pub fn as_mut_slice(&mut self) -> &mut [Parameter<'static>] {
+        &mut self.params
+    }
+
+    pub fn iter(&self) -> core::slice::Iter<'_, Parameter<'static>> {
+        self.params.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        N
+    }
+
+    pub fn is_empty(&self) -> bool {
+        N == 0
+    }
+
+    pub fn update_on_can(&mut self, frame: bxcan::Frame, millis: u64) {
+        for param_i in self.can_dispatch.parameters_for(frame.id()) {
+            update_parameter_on_can(&mut self.params[param_i], &frame, millis);
+        }
+    }
+
+    pub fn update_timeouts(&mut self, millis: u64) {
+        update_parameter_timeouts(&mut self.params, millis);
+    }
+
+    // Re-evaluates the derived parameters whose inputs have been updated since
+    // the last evaluation. Call this after updating the inputs. If the result
+    // is NaN and an input is stale, the output goes stale as well and keeps its
+    // last value.
+    pub fn update_derived(&mut self, millis: u64) {
+        for i in 0..N {
+            let Some(derived) = &self.params[i].derived else {
+                continue;
+            };
+            let (inputs, compute) = (derived.inputs, derived.compute);
+            let output_fresh = self.params[i].current().is_some();
+            let output_timestamp = self.params[i].update_timestamp;
+            let mut values = [f32::NAN; MAX_DERIVED_INPUTS];
+            let mut changed = false;
+            let mut stale = false;
+            for (value, &input_i) in values.iter_mut().zip(inputs) {
+                let input = &self.params[input_i];
+                *value = input.current_value();
+                changed |= input.update_timestamp >= output_timestamp;
+                stale |= matches!(input.quality,
+                        ParameterQuality::Stale | ParameterQuality::DecodeError);
+            }
+            // A stale input may turn a fresh output stale even without updates
+            let evaluate = changed || (stale && output_fresh);
+            if !evaluate {
+                continue;
+            }
+            let value = compute(&values[..inputs.len()]);
+            let output = &mut self.params[i];
+            if !value.is_nan() {
+                output.set_value(value, millis);
+            } else if stale {
+                if output_fresh {
+                    output.set_quality(ParameterQuality::Stale);
+                }
+            } else {
+                // Not computable yet. The timestamp marks the evaluation.
+                let value = output.value_type.from_f32(value);
+                output.change_pending |= !value.same_as(&output.value);
+                output.value = value;
+                output.set_quality(ParameterQuality::NeverReceived);
+                output.update_timestamp = millis;
+            }
+        }
+    }
+
+    // Gives the parameters changed since the last call a new sequence number,
+    // and returns it. Call this once per update cycle, after the parameters
+    // have been updated, so that changed_since() sees the whole cycle.
+    pub fn commit_changes(&mut self) -> u32 {
+        if self.params.iter().any(|param| param.change_pending) {
+            self.sequence = self.sequence.wrapping_add(1);
+            for param in self.params.iter_mut().filter(|param| param.change_pending) {
+                param.change_pending = false;
+                param.change_sequence = self.sequence;
+            }
+        }
+        self.sequence
+    }
+
+    // Store this and pass it to changed_since() later to find out what has
+    // changed in between
+    pub fn sequence(&self) -> u32 {
+        self.sequence
+    }
+
+    pub fn changed_since(&self, sequence: u32) -> impl Iterator<Item = &Parameter<'static>> {
+        self.params.iter().filter(move |param| param.changed_since(sequence))
+    }
+}
+
+impl<const N: usize, const C: usize, I: Into<usize>> Index<I> for ParameterStore<N, C> {
+    type Output = Parameter<'static>;
+    fn index(&self, id: I) -> &Self::Output {
+        &self.params[id.into()]
+    }
+}
+
+impl<const N: usize, const C: usize, I: Into<usize>> IndexMut<I> for ParameterStore<N, C> {
+    fn index_mut(&mut self, id: I) -> &mut Self::Output {
+        &mut self.params[id.into()]
+    }
+}
@@ -0,0 +1,318 @@
+// slcan (Lawicel) protocol codec, for using the device as a USB-CAN adapter
+//
+// The host sends commands terminated by CR: "S6" sets the bitrate, "O" opens
+// the channel ("L" in listen-only mode), "C" closes it, and "t12381122334455"
+// and "T1234567881122" send standard and extended frames ("r"/"R" remote
+// frames). Replies are CR for OK and BEL for errors, such as a bitrate that the
+// platform can't do. Frames from the bus are
+// written to the host in the same format while the channel is open, with a
+// millisecond timestamp (0...59999) at the end if enabled with "Z1". "F"
+// reads the status flags, which tell if frames for the host were lost.
+//
+// The codec only parses and formats; the platform applies the SlcanActions to
+// its CAN peripheral and moves the bytes.
+
+use arrayvec::ArrayString;
+use bxcan::{ExtendedId, Frame, Id, StandardId};
+use core::fmt::{self, Write};
+
+// Longest command is an extended frame: T, 8 ID digits, DLC and 16 data digits
+pub const SLCAN_LINE_LEN: usize = 32;
+
+// Indexed by the number of the S command
+pub const SLCAN_BITRATES: [u32; 9] =
+        [10_000, 20_000, 50_000, 100_000, 125_000, 250_000, 500_000, 800_000, 1_000_000];
+
+const OK: &str = "\r";
+const ERROR: &str = "\x07";
+
+// Status flag bits of the F command
+const STATUS_DATA_OVERRUN: u8 = 0x08;
+
+#[derive(Debug, PartialEq)]
+pub enum SlcanAction {
+    Open { bitrate: u32, listen_only: bool },
+    Close,
+    Transmit(Frame),
+}
+
+pub struct Slcan {
+    line: ArrayString<SLCAN_LINE_LEN>,
+    overflow: bool, // The current line didn't fit and is discarded
+    bitrate: u32,
+    open: bool,
+    listen_only: bool,
+    timestamps: bool,
+    // A frame for the host was dropped since the status was last read
+    overrun: bool,
+    supports_bitrate: fn(u32) -> bool,
+}
+
+impl Slcan {
+    // supports_bitrate() tells whether the CAN peripheral can be set to a
+    // bitrate. The others are refused on S and O.
+    pub const fn new(supports_bitrate: fn(u32) -> bool) -> Self {
+        Self {
+            line: ArrayString::new_const(),
+            overflow: false,
+            bitrate: 500_000,
+            open: false,
+            listen_only: false,
+            timestamps: false,
+            overrun: false,
+            supports_bitrate,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    // Call this when output of write_frame() couldn't be passed to the host.
+    // It is reported by the next F command.
+    pub fn frame_dropped(&mut self) {
+        self.overrun = true;
+    }
+
+    // Call this for every byte from the host. The reply, if any, is written to
+    // out.
+    pub fn put(&mut self, b: u8, out: &mut dyn Write) -> Option<SlcanAction> {
+        match b {
+            b'\r' => {
+                let result = if self.overflow { Err(()) } else { self.handle_line(out) };
+                self.line.clear();
+                self.overflow = false;
+                match result {
+                    Ok(action) => action,
+                    Err(()) => {
+                        let _ = out.write_str(ERROR);
+                        None
+                    }
+                }
+            }
+            // Some hosts end lines with CR LF
+            b'\n' => None,
+            _ => {
+                if !b.is_ascii() || self.line.try_push(b as char).is_err() {
+                    self.overflow = true;
+                }
+                None
+            }
+        }
+    }
+
+    fn handle_line(&mut self, out: &mut dyn Write) -> Result<Option<SlcanAction>, ()> {
+        let line = self.line;
+        let Some(command) = line.chars().next() else {
+            let _ = out.write_str(OK);
+            return Ok(None);
+        };
+        let args = &line[1..];
+        let mut action = None;
+        match command {
+            'S' if !self.open => {
+                let i: usize = args.parse().map_err(|_| ())?;
+                let bitrate = *SLCAN_BITRATES.get(i).ok_or(())?;
+                if !(self.supports_bitrate)(bitrate) {
+                    return Err(());
+                }
+                self.bitrate = bitrate;
+            }
+            'O' | 'L' if !self.open && (self.supports_bitrate)(self.bitrate) => {
+                self.open = true;
+                self.listen_only = command == 'L';
+                self.overrun = false;
+                action = Some(SlcanAction::Open {
+                    bitrate: self.bitrate,
+                    listen_only: self.listen_only,
+                });
+            }
+            'C' => {
+                // Hosts close the channel first to get to a known state, so
+                // this is fine when closed as well
+                if self.open {
+                    self.open = false;
+                    action = Some(SlcanAction::Close);
+                }
+            }
+            't' | 'T' | 'r' | 'R' if self.open && !self.listen_only => {
+                let frame = parse_frame(command, args).ok_or(())?;
+                let _ = out.write_str(if command.is_ascii_lowercase() { "z\r" } else { "Z\r" });
+                return Ok(Some(SlcanAction::Transmit(frame)));
+            }
+            'Z' => {
+                self.timestamps = match args {
+                    "0" => false,
+                    "1" => true,
+                    _ => return Err(()),
+                };
+            }
+            'V' => {
+                let _ = out.write_str("V1013\r");
+                return Ok(None);
+            }
+            'N' => {
+                let _ = out.write_str("NUI8D\r");
+                return Ok(None);
+            }
+            'F' => {
+                let status = if self.overrun { STATUS_DATA_OVERRUN } else { 0 };
+                self.overrun = false;
+                let _ = write!(out, "F{:02X}\r", status);
+                return Ok(None);
+            }
+            // Acceptance code and mask; everything is passed to the host
+            'M' | 'm' => {}
+            _ => return Err(()),
+        }
+        let _ = out.write_str(OK);
+        Ok(action)
+    }
+
+    // Writes a frame from the bus for the host. Nothing is written unless the
+    // channel is open.
+    pub fn write_frame(&self, frame: &Frame, millis: u64, out: &mut dyn Write) -> fmt::Result {
+        if !self.open {
+            return Ok(());
+        }
+        let remote = frame.is_remote_frame();
+        match frame.id() {
+            Id::Standard(id) => write!(out, "{}{:03X}", if remote { 'r' } else { 't' },
+                    id.as_raw())?,
+            Id::Extended(id) => write!(out, "{}{:08X}", if remote { 'R' } else { 'T' },
+                    id.as_raw())?,
+        }
+        write!(out, "{}", frame.dlc())?;
+        if let Some(data) = frame.data() {
+            for b in data.iter() {
+                write!(out, "{:02X}", b)?;
+            }
+        }
+        if self.timestamps {
+            write!(out, "{:04X}", millis % 60000)?;
+        }
+        out.write_str("\r")
+    }
+}
+
+fn parse_frame(command: char, args: &str) -> Option<Frame> {
+    let id_len = if command.is_ascii_lowercase() { 3 } else { 8 };
+    let id = parse_hex(args.get(..id_len)?)?;
+    let id = if id_len == 3 {
+        Id::Standard(StandardId::new(u16::try_from(id).ok()?)?)
+    } else {
+        Id::Extended(ExtendedId::new(id)?)
+    };
+    let dlc = parse_hex(args.get(id_len..id_len + 1)?)? as u8;
+    if dlc > 8 {
+        return None;
+    }
+    let data_text = &args[id_len + 1..];
+    if command == 'r' || command == 'R' {
+        return data_text.is_empty().then(|| Frame::new_remote(id, dlc));
+    }
+    if data_text.len() != dlc as usize * 2 {
+        return None;
+    }
+    let mut data = [0u8; 8];
+    for (i, byte) in data[..dlc as usize].iter_mut().enumerate() {
+        *byte = parse_hex(&data_text[i * 2..i * 2 + 2])? as u8;
+    }
+    Some(Frame::new_data(id, bxcan::Data::new(&data[..dlc as usize]).unwrap()))
+}
+
+fn parse_hex(text: &str) -> Option<u32> {
+    if !text.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    u32::from_str_radix(text, 16).ok()
+}
+
+// The bxcan BTR register value for the bitrate, or None if the bitrate can't
+// be made from the peripheral clock. The sample point is at 87.5...93%.
+pub fn bxcan_bit_timing(pclk_hz: u32, bitrate: u32) -> Option<u32> {
+    for time_quanta in [12, 14, 16, 10, 8] {
+        if bitrate == 0 || !pclk_hz.is_multiple_of(time_quanta * bitrate) {
+            continue;
+        }
+        let prescaler = pclk_hz / (time_quanta * bitrate);
+        if !(1..=1024).contains(&prescaler) {
+            continue;
+        }
+        let ts2 = time_quanta / 8;
+        let ts1 = time_quanta - 1 - ts2;
+        return Some(((ts2 - 1) << 20) | ((ts1 - 1) << 16) | (prescaler - 1));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::candump::parse_cansend;
+
+    #[test]
+    fn commands_are_handled() {
+        fn send(slcan: &mut Slcan, text: &str) -> (Option<SlcanAction>, ArrayString<32>) {
+            let mut out = ArrayString::new();
+            let mut action = None;
+            for b in text.bytes() {
+                action = action.or(slcan.put(b, &mut out));
+            }
+            (action, out)
+        }
+        let mut slcan = Slcan::new(|_| true);
+        assert_eq!(send(&mut slcan, "t1230\r"), (None, ArrayString::from("\x07").unwrap()));
+        assert_eq!(send(&mut slcan, "S4\r").1.as_str(), "\r");
+        assert_eq!(send(&mut slcan, "S9\r").1.as_str(), "\x07");
+        assert_eq!(send(&mut slcan, "O\r").0,
+                Some(SlcanAction::Open { bitrate: 125_000, listen_only: false }));
+        let (action, out) = send(&mut slcan, "t1232AABB\r");
+        assert_eq!(action, Some(SlcanAction::Transmit(
+                parse_cansend("123#AABB").unwrap())));
+        assert_eq!(out.as_str(), "z\r");
+        let (action, out) = send(&mut slcan, "R1F3344554\r\n");
+        assert_eq!(action, Some(SlcanAction::Transmit(
+                parse_cansend("1F334455#R4").unwrap())));
+        assert_eq!(out.as_str(), "Z\r");
+        assert_eq!(send(&mut slcan, "t1232AA\r").1.as_str(), "\x07");
+        assert_eq!(send(&mut slcan, "t12312AABBCCDDEEFF0011223344556677\r").1.as_str(), "\x07");
+
+        let mut out: ArrayString<32> = ArrayString::new();
+        let frame = parse_cansend("1F334455#0102").unwrap();
+        slcan.write_frame(&frame, 1000, &mut out).unwrap();
+        assert_eq!(out.as_str(), "T1F33445520102\r");
+        send(&mut slcan, "Z1\r");
+        out.clear();
+        slcan.write_frame(&parse_cansend("123#R2").unwrap(), 61000, &mut out).unwrap();
+        assert_eq!(out.as_str(), "r123203E8\r");
+
+        // Dropped frames are reported once
+        assert_eq!(send(&mut slcan, "F\r").1.as_str(), "F00\r");
+        slcan.frame_dropped();
+        assert_eq!(send(&mut slcan, "F\r").1.as_str(), "F08\r");
+        assert_eq!(send(&mut slcan, "F\r").1.as_str(), "F00\r");
+
+        assert_eq!(send(&mut slcan, "C\r").0, Some(SlcanAction::Close));
+        out.clear();
+        slcan.write_frame(&frame, 0, &mut out).unwrap();
+        assert!(out.is_empty());
+
+        assert_eq!(bxcan_bit_timing(42_000_000, 500_000), Some(0x00090006));
+        assert_eq!(bxcan_bit_timing(42_000_000, 1_000_000), Some(0x000b0002));
+        assert_eq!(bxcan_bit_timing(42_000_000, 800_000), None);
+
+        // Bitrates that the platform can't do are refused
+        let mut slcan = Slcan::new(|bitrate| bxcan_bit_timing(42_000_000, bitrate).is_some());
+        assert_eq!(send(&mut slcan, "S7\r").1.as_str(), "\x07");
+        assert_eq!(send(&mut slcan, "S8\r").1.as_str(), "\r");
+        assert_eq!(send(&mut slcan, "O\r").0,
+                Some(SlcanAction::Open { bitrate: 1_000_000, listen_only: false }));
+        let mut slcan = Slcan::new(|bitrate| bitrate == 125_000);
+        assert_eq!(send(&mut slcan, "O\r"), (None, ArrayString::from("\x07").unwrap()));
+        assert!(!slcan.is_open());
+        assert_eq!(send(&mut slcan, "S4\r").1.as_str(), "\r");
+        assert_eq!(send(&mut slcan, "L\r").0,
+                Some(SlcanAction::Open { bitrate: 125_000, listen_only: true }));
+    }
+}
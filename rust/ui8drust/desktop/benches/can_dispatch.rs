@@ -0,0 +1,65 @@
+// Compares ParameterStore::update_on_can(), which uses the CAN ID dispatch
+// table, against scanning every parameter for every frame.
+// $ BASE_URL=... cargo bench --bench can_dispatch
+
+use app::can_simulator::CanSimulator;
+use app::parameters::new_parameter_store;
+use common::bxcan;
+use common::update_parameters_on_can;
+use ringbuffer::RingBuffer;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const ROUNDS: usize = 20000;
+
+// One cycle of the simulated bus, plus the same amount of traffic from nodes
+// that ui8d doesn't decode, as on the real bus
+fn frame_mix() -> Vec<bxcan::Frame> {
+    let mut frames = Vec::new();
+    let mut can_simulator = CanSimulator::new();
+    for millis in 0..26 {
+        can_simulator.update(millis);
+        while let Some(frame) = can_simulator.txbuf.dequeue() {
+            frames.push(frame);
+        }
+    }
+    let num_simulated = frames.len();
+    for i in 0..num_simulated {
+        frames.push(bxcan::Frame::new_data(
+            bxcan::StandardId::new(0x600 + i as u16).unwrap(),
+            bxcan::Data::new(&[i as u8; 8]).unwrap(),
+        ));
+    }
+    frames
+}
+
+fn measure(name: &str, frames: &[bxcan::Frame], mut f: impl FnMut(&bxcan::Frame, u64)) -> Duration {
+    let t0 = Instant::now();
+    for round in 0..ROUNDS {
+        for frame in frames {
+            f(black_box(frame), round as u64);
+        }
+    }
+    let elapsed = t0.elapsed();
+    println!(
+        "{:<16} {:>8.1} ns/frame",
+        name,
+        elapsed.as_nanos() as f64 / (ROUNDS * frames.len()) as f64
+    );
+    elapsed
+}
+
+fn main() {
+    let mut params = new_parameter_store();
+    let frames = frame_mix();
+    println!("{} parameters, {} frames per round, {} rounds",
+            params.len(), frames.len(), ROUNDS);
+
+    let linear = measure("Linear scan", &frames, |frame, millis| {
+        update_parameters_on_can(params.as_mut_slice(), frame.clone(), millis)
+    });
+    let dispatch = measure("Dispatch table", &frames, |frame, millis| {
+        params.update_on_can(frame.clone(), millis)
+    });
+    println!("Speedup: {:.1}x", linear.as_secs_f64() / dispatch.as_secs_f64());
+}
@@ -0,0 +1,137 @@
+// Feeds the frames of a candump -L log file to the app with their original
+// timing, in place of CanSimulator. Frames marked as transmitted (T) were sent
+// by ui8d itself and are skipped.
+
+use common::bxcan;
+use common::candump::{parse_candump_line, CanDirection};
+#[allow(unused_imports)]
+use log::{info, warn};
+use std::path::Path;
+
+pub struct CanReplay {
+    // Microseconds from the first frame of the log
+    frames: Vec<(u64, bxcan::Frame)>,
+    next: usize,
+    looping: bool,
+    speed: f64,
+    offset_us: u64,
+    start_millis: Option<u64>,
+}
+
+impl CanReplay {
+    pub fn load(path: &Path, looping: bool, speed: f64, offset_s: f64) -> std::io::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        let replay = Self::parse(&text, looping, speed, offset_s)?;
+        info!("Loaded {} frames from {:?}", replay.frames.len(), path);
+        Ok(replay)
+    }
+
+    // Fails if the offset skips the whole log
+    fn parse(text: &str, looping: bool, speed: f64, offset_s: f64) -> std::io::Result<Self> {
+        let mut frames = Vec::new();
+        let mut first_micros = None;
+        for line in text.lines() {
+            let Some(line) = parse_candump_line(line) else {
+                continue;
+            };
+            if line.direction == Some(CanDirection::Tx) {
+                continue;
+            }
+            let first_micros = *first_micros.get_or_insert(line.micros);
+            frames.push((line.micros.saturating_sub(first_micros), line.frame));
+        }
+        let offset_us = (offset_s.max(0.0) * 1_000_000.0) as u64;
+        if let Some((last_us, _)) = frames.last() {
+            if offset_us > 0 && offset_us >= *last_us {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!(
+                        "Offset {}s is not before the last frame at {}s",
+                        offset_s, *last_us as f64 / 1_000_000.0)));
+            }
+        }
+        Ok(Self {
+            frames,
+            next: 0,
+            looping,
+            speed: if speed > 0.0 { speed } else { 1.0 },
+            offset_us,
+            start_millis: None,
+        })
+    }
+
+    // Calls on_frame() with every frame that is due at millis
+    pub fn update(&mut self, millis: u64, mut on_frame: impl FnMut(bxcan::Frame)) {
+        let start_millis = *self.start_millis.get_or_insert_with(|| {
+            self.next = self.frames.partition_point(|(t, _)| *t < self.offset_us);
+            millis
+        });
+        let replay_us = self.offset_us +
+                (millis.saturating_sub(start_millis) as f64 * 1000.0 * self.speed) as u64;
+        while let Some((t, frame)) = self.frames.get(self.next) {
+            if *t > replay_us {
+                return;
+            }
+            on_frame(frame.clone());
+            self.next += 1;
+        }
+        if self.looping && !self.frames.is_empty() {
+            info!("CAN replay: Starting over");
+            self.start_millis = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOG: &str = "\
+(100.000000) can0 100#01
+(100.500000) can0 200#02 T
+(101.000000) can0 101#03
+(103.000000) can0 102#04
+";
+
+    fn ids(replay: &mut CanReplay, millis: u64) -> Vec<u16> {
+        let mut ids = Vec::new();
+        replay.update(millis, |frame| match frame.id() {
+            bxcan::Id::Standard(id) => ids.push(id.as_raw()),
+            bxcan::Id::Extended(_) => panic!(),
+        });
+        ids
+    }
+
+    #[test]
+    fn frames_follow_log_timing() {
+        let mut replay = CanReplay::parse(LOG, false, 1.0, 0.0).unwrap();
+        // The transmitted frame is skipped
+        assert_eq!(ids(&mut replay, 5000), [0x100]);
+        assert!(ids(&mut replay, 5999).is_empty());
+        assert_eq!(ids(&mut replay, 6000), [0x101]);
+        assert_eq!(ids(&mut replay, 8000), [0x102]);
+        assert!(ids(&mut replay, 20000).is_empty());
+    }
+
+    #[test]
+    fn speed_scales_timing() {
+        let mut replay = CanReplay::parse(LOG, false, 2.0, 0.0).unwrap();
+        assert_eq!(ids(&mut replay, 0), [0x100]);
+        assert!(ids(&mut replay, 499).is_empty());
+        assert_eq!(ids(&mut replay, 500), [0x101]);
+        assert_eq!(ids(&mut replay, 1500), [0x102]);
+    }
+
+    #[test]
+    fn offset_skips_frames_and_loops_back_to_it() {
+        let mut replay = CanReplay::parse(LOG, true, 1.0, 0.5).unwrap();
+        assert!(ids(&mut replay, 0).is_empty());
+        assert_eq!(ids(&mut replay, 500), [0x101]);
+        assert_eq!(ids(&mut replay, 2500), [0x102]);
+        // Started over at the offset
+        assert!(ids(&mut replay, 2520).is_empty());
+        assert_eq!(ids(&mut replay, 3020), [0x101]);
+
+        assert!(CanReplay::parse(LOG, true, 1.0, 3.0).is_err());
+        assert!(CanReplay::parse(LOG, false, 1.0, 10.0).is_err());
+        assert!(CanReplay::parse("", true, 1.0, 10.0).is_ok());
+    }
+}
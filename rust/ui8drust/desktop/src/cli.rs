@@ -3,4 +3,35 @@ use std::path::PathBuf;
 
 #[derive(Parser)]
 #[command(version)]
-pub struct Cli {}
+pub struct Cli {
+    /// Write the CAN mapped parameters into a DBC file and exit
+    #[arg(long, value_name = "FILE")]
+    pub export_dbc: Option<PathBuf>,
+
+    /// Send and receive CAN frames on a SocketCAN interface, e.g. vcan0,
+    /// instead of using the built-in simulated frames
+    #[arg(long, value_name = "INTERFACE")]
+    pub can_iface: Option<String>,
+
+    /// Start in slcan gateway mode: open a pty that speaks the slcan
+    /// protocol and bridges to the simulated CAN bus
+    #[arg(long)]
+    pub slcan_pty: bool,
+
+    /// Replay CAN frames from a candump -L log file instead of the built-in
+    /// simulated frames
+    #[arg(long, value_name = "FILE")]
+    pub can_replay: Option<PathBuf>,
+
+    /// Start the replay over from the offset when it ends
+    #[arg(long, requires = "can_replay")]
+    pub can_replay_loop: bool,
+
+    /// Replay speed, e.g. 2 for twice as fast
+    #[arg(long, value_name = "FACTOR", default_value_t = 1.0, requires = "can_replay")]
+    pub can_replay_speed: f64,
+
+    /// Start the replay this many seconds into the log
+    #[arg(long, value_name = "SECONDS", default_value_t = 0.0, requires = "can_replay")]
+    pub can_replay_offset: f64,
+}
@@ -0,0 +1,57 @@
+// Puts the same LogFilter as on hardware in front of stderrlog, so that the
+// "loglevel" console command works on desktop too. stderr uses the Usb levels.
+// candump lines are written to stdout as is, so that they can be piped to CAN
+// tools.
+
+use common::candump;
+use common::log_filter::{LogFilter, LogSink};
+use log::{LevelFilter, Log, Metadata, Record};
+use std::sync::Mutex;
+
+static FILTER: Mutex<LogFilter> = Mutex::new(LogFilter::new(LevelFilter::Off));
+
+struct FilteredLogger {
+    inner: stderrlog::StdErrLog,
+}
+
+impl Log for FilteredLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        FILTER.lock().unwrap().enabled(LogSink::Usb, metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        if record.target() == candump::LOG_TARGET {
+            println!("{}", record.args());
+        } else {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+pub fn init() {
+    let mut filter = FILTER.lock().unwrap();
+    for target in ["desktop", "common", "app", candump::LOG_TARGET] {
+        filter.set(Some(target), None, LevelFilter::Info).unwrap();
+    }
+    log::set_max_level(filter.max_level());
+
+    let mut inner = stderrlog::new();
+    inner.verbosity(LevelFilter::Trace).show_module_names(true);
+    log::set_boxed_logger(Box::new(FilteredLogger { inner })).unwrap();
+}
+
+pub fn filter() -> LogFilter {
+    *FILTER.lock().unwrap()
+}
+
+pub fn set_filter(filter: LogFilter) {
+    log::set_max_level(filter.max_level());
+    *FILTER.lock().unwrap() = filter;
+}
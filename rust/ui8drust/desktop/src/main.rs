@@ -1,11 +1,22 @@
 // Local modules
+mod can_replay;
+use can_replay::CanReplay;
 mod cli;
+mod logger;
 use cli::Cli;
 mod sim7600simulator;
+mod slcan_pty;
+use slcan_pty::SlcanPty;
+mod socketcan;
+use socketcan::SocketCan;
 use sim7600simulator::Sim7600Simulator;
+mod terminal;
 
 // Internal crates
 use common::*;
+use common::candump::{CanDirection, CanLog, CansendFrame};
+use common::slcan::SlcanAction;
+use common::command_accumulator::CommandAccumulator;
 use app::can_simulator::CanSimulator;
 
 // Platform-specific dependencies
@@ -34,6 +45,8 @@ use std::f64::consts::PI;
 //use nalgebra::{Vector2, Point2, UnitComplex, Rotation2};
 use arrayvec::ArrayString;
 use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::sync::mpsc;
 
 const FPS: u64 = 50;
 const UPS: u64 = 50;
@@ -50,6 +63,9 @@ struct HardwareImplementation {
     sim7600driver: Sim7600Driver,
     can_sim: CanSimulator,
     digital_output_states: HashMap<DigitalOutput, bool>,
+    can_log: CanLog,
+    can_socket: Option<SocketCan>,
+    slcan_pty: Option<SlcanPty>,
 }
 
 impl HardwareImplementation {
@@ -61,11 +77,44 @@ impl HardwareImplementation {
             sim7600driver: Sim7600Driver::new(),
             can_sim: CanSimulator::new(),
             digital_output_states: HashMap::new(),
+            can_log: CanLog::new(),
+            can_socket: None,
+            slcan_pty: None,
         }
     }
 }
 
 impl HardwareImplementation {
+    // Passes a frame on the bus to the slcan host, if any
+    fn forward_to_slcan(&mut self, frame: &bxcan::Frame) {
+        if let Some(slcan_pty) = &mut self.slcan_pty {
+            slcan_pty.forward(frame, self.ms_counter);
+        }
+    }
+
+    fn update_slcan(&mut self) {
+        let Some(slcan_pty) = &mut self.slcan_pty else {
+            return;
+        };
+        for action in slcan_pty.poll() {
+            match action {
+                SlcanAction::Open { bitrate, listen_only } => {
+                    info!("slcan: Open at {} bps{}", bitrate,
+                            if listen_only { ", listen only" } else { "" });
+                }
+                SlcanAction::Close => info!("slcan: Close"),
+                SlcanAction::Transmit(frame) => match &self.can_socket {
+                    Some(can_socket) => {
+                        if let Err(e) = can_socket.send(&frame) {
+                            warn!("slcan: {}", e);
+                        }
+                    }
+                    None => info!("slcan: Transmit {}", CansendFrame(&frame)),
+                },
+            }
+        }
+    }
+
     fn update_sim7600(&mut self) {
         self.sim7600driver.update_time(self.ms_counter);
 
@@ -110,6 +159,40 @@ impl HardwareInterface for HardwareImplementation {
         warn!("activate_dfu() does nothing in desktop mode");
     }
 
+    fn log_filter(&mut self) -> common::log_filter::LogFilter {
+        logger::filter()
+    }
+
+    fn set_log_filter(&mut self, filter: common::log_filter::LogFilter) {
+        logger::set_filter(filter);
+    }
+
+    fn can_log(&mut self) -> CanLog {
+        self.can_log
+    }
+
+    fn set_can_log(&mut self, can_log: CanLog) {
+        self.can_log = can_log;
+    }
+
+    fn slcan_enabled(&mut self) -> bool {
+        self.slcan_pty.is_some()
+    }
+
+    fn set_slcan_enabled(&mut self, enabled: bool) {
+        if !enabled {
+            self.slcan_pty = None;
+        } else if self.slcan_pty.is_none() {
+            match SlcanPty::open() {
+                Ok(slcan_pty) => {
+                    info!("slcan: Listening on {}", slcan_pty.path());
+                    self.slcan_pty = Some(slcan_pty);
+                }
+                Err(e) => warn!("slcan: Can't open pty: {}", e),
+            }
+        }
+    }
+
     fn http_get_start(&mut self, url: &str) {
         info!("http_get_start(): url: {:?}", url);
 
@@ -125,7 +208,17 @@ impl HardwareInterface for HardwareImplementation {
     }
 
     fn send_can(&mut self, frame: bxcan::Frame) {
-        info!("send_can(): {:?}", frame);
+        if self.can_log.is_enabled() {
+            self.can_log.log(self.ms_counter, &frame, CanDirection::Tx);
+        } else if self.can_socket.is_none() {
+            info!("send_can(): {:?}", frame);
+        }
+        if let Some(can_socket) = &self.can_socket {
+            if let Err(e) = can_socket.send(&frame) {
+                warn!("send_can(): {}", e);
+            }
+        }
+        self.forward_to_slcan(&frame);
     }
 
     fn get_analog_input(&mut self, input: AnalogInput) -> f32 {
@@ -149,17 +242,26 @@ impl HardwareInterface for HardwareImplementation {
     }
 }
 
+// Every frame from the bus goes through here
+fn receive_can(hw: &mut HardwareImplementation, state: &mut app::MainState, frame: bxcan::Frame) {
+    hw.can_log.log(hw.ms_counter, &frame, CanDirection::Rx);
+    hw.forward_to_slcan(&frame);
+    state.on_can(frame);
+}
+
 fn main() {
     let cli = Cli::parse();
 
-    stderrlog::new()
-        .verbosity(log::LevelFilter::Info)
-        .show_module_names(true)
-        .module(module_path!())
-        .module("common")
-        .init()
-        .unwrap();
-    log::set_max_level(log::LevelFilter::Info);
+    logger::init();
+
+    if let Some(path) = &cli.export_dbc {
+        let params = app::parameters::new_parameter_store();
+        let mut dbc = String::new();
+        common::dbc_export::write_dbc(&mut dbc, params.as_slice()).unwrap();
+        std::fs::write(path, dbc).unwrap();
+        info!("Wrote {:?}", path);
+        return;
+    }
 
     let mut window: PistonWindow = WindowSettings::new(
         "ui8drust",
@@ -181,6 +283,36 @@ fn main() {
 
     let mut hw = HardwareImplementation::new();
 
+    if let Some(interface) = &cli.can_iface {
+        let can_socket = SocketCan::open(interface)
+                .unwrap_or_else(|e| panic!("Can't open CAN interface {:?}: {}", interface, e));
+        info!("Using CAN interface {:?}", interface);
+        hw.can_socket = Some(can_socket);
+    }
+
+    if cli.slcan_pty {
+        hw.set_slcan_enabled(true);
+    }
+
+    let mut can_replay = cli.can_replay.as_ref().map(|path| {
+        CanReplay::load(path, cli.can_replay_loop, cli.can_replay_speed, cli.can_replay_offset)
+                .unwrap_or_else(|e| panic!("Can't replay {:?}: {}", path, e))
+    });
+
+    // Console commands come from stdin, like from USB serial on hardware
+    let _raw_input = terminal::RawInput::enable();
+    let (console_tx, console_rx) = mpsc::channel::<u8>();
+    std::thread::spawn(move || {
+        for b in std::io::stdin().bytes() {
+            let Ok(b) = b else { break };
+            if console_tx.send(b).is_err() {
+                break;
+            }
+        }
+    });
+    let mut command_accumulator: CommandAccumulator<50> = CommandAccumulator::new();
+    let console_commands = app::console::new_console_commands();
+
     let mut counter: u64 = 0;
 
     while let Some(e) = window.next() {
@@ -228,13 +360,48 @@ fn main() {
                 state.on_mainboard_rx(&text);
             }
 
-            hw.can_sim.update(hw.ms_counter);
-            while let Some(frame) = hw.can_sim.txbuf.dequeue() {
-                state.on_can(frame);
+            if let Some(can_replay) = &mut can_replay {
+                can_replay.update(hw.ms_counter, |frame| receive_can(&mut hw, &mut state, frame));
+            } else if hw.can_socket.is_none() {
+                hw.can_sim.update(hw.ms_counter);
+                while let Some(frame) = hw.can_sim.txbuf.dequeue() {
+                    receive_can(&mut hw, &mut state, frame);
+                }
+            }
+
+            while let Some(can_socket) = &hw.can_socket {
+                let frame = match can_socket.receive() {
+                    Ok(Some(frame)) => frame,
+                    Ok(None) => break,
+                    Err(e) => {
+                        warn!("CAN receive: {}", e);
+                        break;
+                    }
+                };
+                receive_can(&mut hw, &mut state, frame);
             }
 
+            hw.update_slcan();
+
             state.update(&mut hw);
 
+            while let Ok(b) = console_rx.try_recv() {
+                if state.on_console_key() {
+                    continue;
+                }
+                let completer = app::console::ConsoleCompleter {
+                    commands: &console_commands,
+                    params: &state.params,
+                };
+                let command = command_accumulator.put_with_completion(b as char, &completer);
+                print!("{}", command_accumulator.take_output());
+                let _ = std::io::stdout().flush();
+                if let Some(command) = command {
+                    info!("Command: {:?}", command);
+                    console_commands.dispatch(&mut state, &command, &mut hw);
+                }
+            }
+
             counter += 1;
             hw.ms_counter += 1000 / UPS;
         }
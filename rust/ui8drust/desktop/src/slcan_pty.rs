@@ -0,0 +1,86 @@
+// slcan gateway on a pseudo terminal, the desktop version of slcan mode over
+// USB serial. Point slcand or SavvyCAN at the printed /dev/pts path.
+
+use common::bxcan;
+use common::slcan::{Slcan, SlcanAction};
+use std::ffi::CStr;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+pub struct SlcanPty {
+    master: OwnedFd,
+    path: String,
+    slcan: Slcan,
+}
+
+impl SlcanPty {
+    pub fn open() -> io::Result<Self> {
+        unsafe {
+            let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY | libc::O_NONBLOCK);
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let master = OwnedFd::from_raw_fd(fd);
+            if libc::grantpt(fd) != 0 || libc::unlockpt(fd) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let name = libc::ptsname(fd);
+            if name.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            let path = CStr::from_ptr(name).to_string_lossy().into_owned();
+            // No echo or line ending translation, whatever the host does
+            let mut termios: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(fd, &mut termios) == 0 {
+                libc::cfmakeraw(&mut termios);
+                libc::tcsetattr(fd, libc::TCSANOW, &termios);
+            }
+            // The bitrate isn't applied to anything, so any will do
+            Ok(Self { master, path, slcan: Slcan::new(|_| true) })
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    // Handles what the host has sent and returns the resulting actions
+    pub fn poll(&mut self) -> Vec<SlcanAction> {
+        let mut actions = Vec::new();
+        let mut buf = [0u8; 256];
+        loop {
+            let count = unsafe {
+                libc::read(self.master.as_raw_fd(), buf.as_mut_ptr() as *mut libc::c_void,
+                        buf.len())
+            };
+            // Nothing to read, or nobody has the pty open (EIO)
+            if count <= 0 {
+                break;
+            }
+            let mut reply = String::new();
+            for &b in &buf[..count as usize] {
+                actions.extend(self.slcan.put(b, &mut reply));
+            }
+            self.write(&reply);
+        }
+        actions
+    }
+
+    // Passes a frame on the bus to the host
+    pub fn forward(&mut self, frame: &bxcan::Frame, millis: u64) {
+        let mut text = String::new();
+        if self.slcan.write_frame(frame, millis, &mut text).is_ok() {
+            self.write(&text);
+        }
+    }
+
+    // Output is dropped if the host isn't reading
+    fn write(&self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        unsafe {
+            libc::write(self.master.as_raw_fd(), text.as_ptr() as *const libc::c_void, text.len());
+        }
+    }
+}
@@ -0,0 +1,96 @@
+// A raw SocketCAN socket, e.g. on vcan0 or on a USB-CAN adapter. The socket is
+// non-blocking so that receive() can be polled from the update loop.
+
+use common::bxcan;
+use std::ffi::CString;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+pub struct SocketCan {
+    fd: OwnedFd,
+}
+
+impl SocketCan {
+    pub fn open(interface: &str) -> io::Result<Self> {
+        let name = CString::new(interface)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Invalid interface name"))?;
+        unsafe {
+            let ifindex = libc::if_nametoindex(name.as_ptr());
+            if ifindex == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let fd = libc::socket(libc::PF_CAN, libc::SOCK_RAW | libc::SOCK_NONBLOCK, libc::CAN_RAW);
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let fd = OwnedFd::from_raw_fd(fd);
+            let mut addr: libc::sockaddr_can = std::mem::zeroed();
+            addr.can_family = libc::AF_CAN as libc::sa_family_t;
+            addr.can_ifindex = ifindex as libc::c_int;
+            let result = libc::bind(fd.as_raw_fd(),
+                    &addr as *const libc::sockaddr_can as *const libc::sockaddr,
+                    std::mem::size_of::<libc::sockaddr_can>() as libc::socklen_t);
+            if result != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Self { fd })
+        }
+    }
+
+    pub fn send(&self, frame: &bxcan::Frame) -> io::Result<()> {
+        let mut raw: libc::can_frame = unsafe { std::mem::zeroed() };
+        raw.can_id = match frame.id() {
+            bxcan::Id::Standard(id) => id.as_raw() as u32,
+            bxcan::Id::Extended(id) => id.as_raw() | libc::CAN_EFF_FLAG,
+        };
+        raw.can_dlc = frame.dlc();
+        match frame.data() {
+            Some(data) => raw.data[..data.len()].copy_from_slice(data),
+            None => raw.can_id |= libc::CAN_RTR_FLAG,
+        }
+        let size = std::mem::size_of::<libc::can_frame>();
+        let written = unsafe {
+            libc::write(self.fd.as_raw_fd(), &raw as *const libc::can_frame as *const libc::c_void,
+                    size)
+        };
+        if written < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    // Returns None when there is nothing to receive. Error frames are skipped.
+    pub fn receive(&self) -> io::Result<Option<bxcan::Frame>> {
+        loop {
+            let mut raw: libc::can_frame = unsafe { std::mem::zeroed() };
+            let size = std::mem::size_of::<libc::can_frame>();
+            let read = unsafe {
+                libc::read(self.fd.as_raw_fd(), &mut raw as *mut libc::can_frame as *mut libc::c_void,
+                        size)
+            };
+            if read < 0 {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::WouldBlock {
+                    return Ok(None);
+                }
+                return Err(e);
+            }
+            if read as usize != size || raw.can_id & libc::CAN_ERR_FLAG != 0 {
+                continue;
+            }
+            let id = if raw.can_id & libc::CAN_EFF_FLAG != 0 {
+                bxcan::Id::Extended(bxcan::ExtendedId::new(raw.can_id & libc::CAN_EFF_MASK).unwrap())
+            } else {
+                bxcan::Id::Standard(
+                        bxcan::StandardId::new((raw.can_id & libc::CAN_SFF_MASK) as u16).unwrap())
+            };
+            let dlc = raw.can_dlc.min(8);
+            let frame = if raw.can_id & libc::CAN_RTR_FLAG != 0 {
+                bxcan::Frame::new_remote(id, dlc)
+            } else {
+                bxcan::Frame::new_data(id, bxcan::Data::new(&raw.data[..dlc as usize]).unwrap())
+            };
+            return Ok(Some(frame));
+        }
+    }
+}
@@ -0,0 +1,38 @@
+// Puts the terminal into non-canonical mode without echo, so that console input
+// arrives one key at a time and CommandAccumulator can do the line editing and
+// echoing, like over the serial console on hardware. Output processing and
+// signals are left alone, so Ctrl-C still quits. Restored on drop.
+
+pub struct RawInput {
+    original: Option<libc::termios>,
+}
+
+impl RawInput {
+    pub fn enable() -> Self {
+        unsafe {
+            if libc::isatty(libc::STDIN_FILENO) == 0 {
+                return Self { original: None };
+            }
+            let mut termios: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+                return Self { original: None };
+            }
+            let original = termios;
+            termios.c_lflag &= !(libc::ICANON | libc::ECHO);
+            termios.c_cc[libc::VMIN] = 1;
+            termios.c_cc[libc::VTIME] = 0;
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios);
+            Self { original: Some(original) }
+        }
+    }
+}
+
+impl Drop for RawInput {
+    fn drop(&mut self) {
+        if let Some(original) = &self.original {
+            unsafe {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, original);
+            }
+        }
+    }
+}
@@ -7,6 +7,9 @@
 // Internal crates
 use command_accumulator::CommandAccumulator;
 use common::*;
+use common::candump::{CanDirection, CanLog};
+use common::log_filter::{LogFilter, LogSink};
+use common::slcan::{self, Slcan, SlcanAction};
 
 // Platform-specific dependencies
 use adc::{config::AdcConfig, Adc};
@@ -29,7 +32,7 @@ use embedded_hal_bus;
 use ili9341::Ili9341;
 
 // Standard library utilities
-use core::{cell::RefCell, fmt::Write, ops::DerefMut};
+use core::{cell::RefCell, fmt::Write, ops::DerefMut, sync::atomic::{AtomicBool, Ordering}};
 
 // General purpose libraries
 use arrayvec::{ArrayString, ArrayVec};
@@ -52,6 +55,14 @@ const MAINBOARD_TX_BUF_SIZE: usize = 200;
 const SIM7600_RX_BUF_SIZE: usize = 500;
 const SIM7600_TX_BUF_SIZE: usize = 500;
 const CAN_ENABLE_LOOPBACK_MODE: bool = false;
+const CAN_BITRATE: u32 = 500_000;
+const CAN_PCLK1_HZ: u32 = 42_000_000;
+const SLCAN_RX_BUF_SIZE: usize = 100;
+// Start with USB serial in slcan gateway mode instead of as a console. The
+// UART console still works, e.g. for "slcan off".
+const SLCAN_AT_BOOT: bool = false;
+
+static SLCAN_MODE: AtomicBool = AtomicBool::new(SLCAN_AT_BOOT);
 
 // Log buffering system
 
@@ -59,6 +70,7 @@ struct MultiLogger {
     uart_buffer: Mutex<RefCell<Option<ArrayString<LOG_BUFFER_SIZE>>>>,
     usb_buffer: Mutex<RefCell<Option<ArrayString<LOG_BUFFER_SIZE>>>>,
     display_buffer: Mutex<RefCell<Option<ArrayString<LOG_BUFFER_SIZE>>>>,
+    filter: Mutex<RefCell<LogFilter>>,
 }
 
 impl MultiLogger {
@@ -80,6 +92,40 @@ impl MultiLogger {
         });
         buf2
     }
+    // Writes text to the console as-is, e.g. the echo of typed characters
+    fn write_console(&self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        critical_section::with(|cs| {
+            if let Some(ref mut buffer) = self.uart_buffer.borrow(cs).borrow_mut().deref_mut() {
+                let _ = buffer.try_push_str(text);
+            }
+            if SLCAN_MODE.load(Ordering::Relaxed) {
+                return;
+            }
+            if let Some(ref mut buffer) = self.usb_buffer.borrow(cs).borrow_mut().deref_mut() {
+                let _ = buffer.try_push_str(text);
+            }
+        });
+        pac::NVIC::pend(pac::Interrupt::USART1);
+        pac::NVIC::pend(pac::Interrupt::OTG_FS);
+    }
+    // Writes slcan protocol output to USB. Dropped as a whole if it doesn't
+    // fit, in which case false is returned.
+    fn write_slcan(&self, text: &str) -> bool {
+        if text.is_empty() {
+            return true;
+        }
+        let written = critical_section::with(|cs| {
+            match self.usb_buffer.borrow(cs).borrow_mut().deref_mut() {
+                Some(ref mut buffer) => buffer.try_push_str(text).is_ok(),
+                None => false,
+            }
+        });
+        pac::NVIC::pend(pac::Interrupt::OTG_FS);
+        written
+    }
     fn get_display_buffer(&self) -> Option<ArrayString<LOG_BUFFER_SIZE>> {
         let mut buf2: Option<ArrayString<LOG_BUFFER_SIZE>> = Some(ArrayString::new());
         critical_section::with(|cs| {
@@ -93,39 +139,48 @@ impl MultiLogger {
 
 impl Log for MultiLogger {
     fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= log::Level::Info // TODO: Adjust as needed
+        critical_section::with(|cs| {
+            let filter = self.filter.borrow(cs).borrow();
+            LogSink::ALL.into_iter().any(|sink| filter.enabled(sink, metadata))
+        })
     }
 
     fn log(&self, record: &Record) {
         if self.enabled(record.metadata()) {
             critical_section::with(|cs| {
-                if let Some(ref mut buffer) = self.uart_buffer.borrow(cs).borrow_mut().deref_mut() {
-                    let _ = buffer.write_fmt(format_args!(
-                        "[{}] {}\r\n",
-                        record.level(),
-                        record.args()
-                    ));
+                let filter = self.filter.borrow(cs).borrow();
+                let enabled = |sink| filter.enabled(sink, record.metadata());
+                // candump lines go out as is, so that captures work with CAN tools
+                let bare = record.target() == common::candump::LOG_TARGET;
+                let mut uart_buffer = self.uart_buffer.borrow(cs).borrow_mut();
+                if let Some(buffer) = uart_buffer.as_mut().filter(|_| enabled(LogSink::Uart)) {
+                    let _ = if bare {
+                        buffer.write_fmt(format_args!("{}\r\n", record.args()))
+                    } else {
+                        buffer.write_fmt(format_args!("[{}] {}\r\n", record.level(), record.args()))
+                    };
                     if buffer.is_full() {
                         let warning = " | LOG BUFFER FULL\r\n";
                         buffer.truncate(buffer.capacity() - warning.len());
                         let _ = buffer.try_push_str(warning);
                     }
                 }
-                if let Some(ref mut buffer) = self.usb_buffer.borrow(cs).borrow_mut().deref_mut() {
-                    let _ = buffer.write_fmt(format_args!(
-                        "[{}] {}\r\n",
-                        record.level(),
-                        record.args()
-                    ));
+                let mut usb_buffer = self.usb_buffer.borrow(cs).borrow_mut();
+                let usb_enabled = enabled(LogSink::Usb) && !SLCAN_MODE.load(Ordering::Relaxed);
+                if let Some(buffer) = usb_buffer.as_mut().filter(|_| usb_enabled) {
+                    let _ = if bare {
+                        buffer.write_fmt(format_args!("{}\r\n", record.args()))
+                    } else {
+                        buffer.write_fmt(format_args!("[{}] {}\r\n", record.level(), record.args()))
+                    };
                     if buffer.is_full() {
                         let warning = " | LOG BUFFER FULL\r\n";
                         buffer.truncate(buffer.capacity() - warning.len());
                         let _ = buffer.try_push_str(warning);
                     }
                 }
-                if let Some(ref mut buffer) =
-                    self.display_buffer.borrow(cs).borrow_mut().deref_mut()
-                {
+                let mut display_buffer = self.display_buffer.borrow(cs).borrow_mut();
+                if let Some(buffer) = display_buffer.as_mut().filter(|_| enabled(LogSink::Display)) {
                     let _ = buffer.write_fmt(format_args!("{}\r\n", record.args()));
                     if buffer.is_full() {
                         let warning = " | LOG BUFFER FULL\r\n";
@@ -150,6 +205,8 @@ static MULTI_LOGGER: MultiLogger = MultiLogger {
     uart_buffer: Mutex::new(RefCell::new(None)),
     usb_buffer: Mutex::new(RefCell::new(None)),
     display_buffer: Mutex::new(RefCell::new(None)),
+    // Change at runtime with the "loglevel" console command
+    filter: Mutex::new(RefCell::new(LogFilter::new(log::LevelFilter::Info))),
 };
 
 // Function to initialize the logger
@@ -169,7 +226,7 @@ fn init_logger() {
             .replace(Some(ArrayString::new()));
     });
     log::set_logger(&MULTI_LOGGER).unwrap();
-    log::set_max_level(log::LevelFilter::Info); // TODO: Adjust as needed
+    log::set_max_level(critical_section::with(|cs| MULTI_LOGGER.filter.borrow(cs).borrow().max_level()));
 }
 
 // CAN driver
@@ -251,6 +308,25 @@ struct HardwareImplementation {
     adc_result_vbat: f32,
     adc_result_tpcb: f32,
     usb1_vbus_pin: Usb1VbusInputPin,
+    can_log: CanLog,
+    slcan: Slcan,
+    // Set when slcan mode is turned off with the channel open
+    restore_can_config: bool,
+}
+
+impl HardwareImplementation {
+    // Passes a frame on the bus to the slcan host, if any
+    fn forward_to_slcan(&mut self, frame: &bxcan::Frame) {
+        if !SLCAN_MODE.load(Ordering::Relaxed) {
+            return;
+        }
+        let millis = self.millis();
+        let mut text: ArrayString<32> = ArrayString::new();
+        if self.slcan.write_frame(frame, millis, &mut text).is_ok() &&
+                !MULTI_LOGGER.write_slcan(&text) {
+            self.slcan.frame_dropped();
+        }
+    }
 }
 
 impl HardwareInterface for HardwareImplementation {
@@ -301,6 +377,9 @@ impl HardwareInterface for HardwareImplementation {
 
     fn send_can(&mut self, frame: bxcan::Frame) {
         //info!("send_can(): {:?}", frame);
+        let millis = self.millis();
+        self.can_log.log(millis, &frame, CanDirection::Tx);
+        self.forward_to_slcan(&frame);
         self.can_tx_buf.push(frame);
     }
 
@@ -325,6 +404,35 @@ impl HardwareInterface for HardwareImplementation {
             DigitalOutput::Sim7600PowerInhibit => { self.sim7600_power_inhibit_pin.set_state(value.into()) }
         }
     }
+
+    fn log_filter(&mut self) -> LogFilter {
+        critical_section::with(|cs| *MULTI_LOGGER.filter.borrow(cs).borrow())
+    }
+
+    fn set_log_filter(&mut self, filter: LogFilter) {
+        critical_section::with(|cs| *MULTI_LOGGER.filter.borrow(cs).borrow_mut() = filter);
+        log::set_max_level(filter.max_level());
+    }
+
+    fn can_log(&mut self) -> CanLog {
+        self.can_log
+    }
+
+    fn set_can_log(&mut self, can_log: CanLog) {
+        self.can_log = can_log;
+    }
+
+    fn slcan_enabled(&mut self) -> bool {
+        SLCAN_MODE.load(Ordering::Relaxed)
+    }
+
+    fn set_slcan_enabled(&mut self, enabled: bool) {
+        if self.slcan.is_open() {
+            self.restore_can_config = true;
+        }
+        self.slcan = Slcan::new(can1_supports_bitrate);
+        SLCAN_MODE.store(enabled, Ordering::Relaxed);
+    }
 }
 
 // Panic output and input methods
@@ -346,6 +454,7 @@ mod rtic_app {
         usb_dev: UsbDevice<'static, otg_fs::UsbBusType>,
         usb_serial: usbd_serial::SerialPort<'static, otg_fs::UsbBusType>,
         console_rxbuf: ConstGenericRingBuffer<u8, CONSOLE_RX_BUF_SIZE>,
+        slcan_rxbuf: ConstGenericRingBuffer<u8, SLCAN_RX_BUF_SIZE>,
         sim7600_rxbuf: ConstGenericRingBuffer<u8, SIM7600_RX_BUF_SIZE>,
         sim7600_txbuf: ConstGenericRingBuffer<u8, SIM7600_TX_BUF_SIZE>,
         mainboard_rxbuf: ConstGenericRingBuffer<u8, MAINBOARD_RX_BUF_SIZE>,
@@ -389,6 +498,7 @@ mod rtic_app {
         last_backlight_pwm: f32,
         // Other
         hw: HardwareImplementation,
+        state: &'static mut app::MainState,
     }
 
     #[init()]
@@ -396,6 +506,7 @@ mod rtic_app {
         static mut EP_MEMORY: [u32; 1024] = [0; 1024];
         static mut USB_BUS: Option<usb_device::bus::UsbBusAllocator<otg_fs::UsbBusType>> = None;
         static mut SPI3_SHARED: Option<Mutex<RefCell<hal::spi::Spi<hal::pac::SPI3>>>> = None;
+        static mut MAIN_STATE: Option<app::MainState> = None;
 
         // System clock
 
@@ -703,7 +814,7 @@ mod rtic_app {
 
         let mut can1 = bxcan::Can::builder(CAN1 { _private: () })
             .set_loopback(CAN_ENABLE_LOOPBACK_MODE)
-            .set_bit_timing(0x00090006) // 500kbps at 42MHz pclk1
+            .set_bit_timing(slcan::bxcan_bit_timing(CAN_PCLK1_HZ, CAN_BITRATE).unwrap())
             .enable();
 
         can1.modify_filters()
@@ -734,6 +845,21 @@ mod rtic_app {
             adc_result_vbat: f32::NAN,
             adc_result_tpcb: f32::NAN,
             usb1_vbus_pin,
+            can_log: CanLog::new(),
+            slcan: Slcan::new(can1_supports_bitrate),
+            restore_can_config: false,
+        };
+
+        // Application state
+
+        let state = unsafe {
+            // MainState owns the parameter store, its CAN dispatch table and
+            // the parameter history, which add up to tens of kilobytes. That
+            // would take a large part of the RAM as ui_task stack, so it lives
+            // in a static instead. A temporary copy may be made on the init
+            // stack, which is free again once the tasks start.
+            MAIN_STATE = Some(app::MainState::new());
+            MAIN_STATE.as_mut().unwrap()
         };
 
         // Set the ARM SLEEPONEXIT bit to go to sleep after handling interrupts
@@ -750,6 +876,7 @@ mod rtic_app {
         (
             Shared {
                 console_rxbuf: ConstGenericRingBuffer::new(),
+                slcan_rxbuf: ConstGenericRingBuffer::new(),
                 sim7600_rxbuf: ConstGenericRingBuffer::new(),
                 sim7600_txbuf: ConstGenericRingBuffer::new(),
                 mainboard_rxbuf: ConstGenericRingBuffer::new(),
@@ -787,6 +914,7 @@ mod rtic_app {
                 tim4_pwm,
                 last_backlight_pwm: 0.2,
                 hw,
+                state,
             },
         )
     }
@@ -807,6 +935,7 @@ mod rtic_app {
     #[task(priority = 1,
         shared = [
             console_rxbuf,
+            slcan_rxbuf,
             sim7600_rxbuf,
             sim7600_txbuf,
             mainboard_rxbuf,
@@ -823,13 +952,15 @@ mod rtic_app {
         local = [
             command_accumulator,
             hw,
+            state,
             tim4_pwm,
             last_backlight_pwm,
             button1_last_pressed: bool = false,
         ]
     )]
     async fn ui_task(mut cx: ui_task::Context) {
-        let mut state = app::MainState::new();
+        let state: &mut app::MainState = cx.local.state;
+        let console_commands = app::console::new_console_commands();
 
         loop {
             // Update values
@@ -865,10 +996,37 @@ mod rtic_app {
 
             state.update(cx.local.hw);
 
+            // Handle slcan gateway
+            while let Some(b) = cx.shared.slcan_rxbuf.lock(|rxbuf| rxbuf.dequeue()) {
+                let mut reply: ArrayString<16> = ArrayString::new();
+                let action = cx.local.hw.slcan.put(b, &mut reply);
+                MULTI_LOGGER.write_slcan(&reply);
+                match action {
+                    Some(SlcanAction::Open { bitrate, listen_only }) => {
+                        info!("slcan: Open at {} bps", bitrate);
+                        cx.shared.can1.lock(|can1| configure_can1(can1, bitrate, listen_only));
+                    }
+                    Some(SlcanAction::Close) => {
+                        info!("slcan: Close");
+                        cx.shared.can1.lock(|can1| configure_can1(can1, CAN_BITRATE, false));
+                    }
+                    Some(SlcanAction::Transmit(frame)) => {
+                        cx.local.hw.can_tx_buf.push(frame);
+                    }
+                    None => {}
+                }
+            }
+            if core::mem::take(&mut cx.local.hw.restore_can_config) {
+                cx.shared.can1.lock(|can1| configure_can1(can1, CAN_BITRATE, false));
+            }
+
             // Handle CAN receive buffer
             while let Some(received_frame) =
                 cx.shared.can_rx_buf.lock(|can_rx_buf| can_rx_buf.dequeue())
             {
+                let millis = cx.local.hw.millis();
+                cx.local.hw.can_log.log(millis, &received_frame, CanDirection::Rx);
+                cx.local.hw.forward_to_slcan(&received_frame);
                 state.on_can(received_frame);
             }
             // Handle CAN transmit buffer
@@ -917,17 +1075,18 @@ mod rtic_app {
 
             // Handle console commands
             while let Some(b) = cx.shared.console_rxbuf.lock(|rxbuf| rxbuf.dequeue()) {
-                if let Some(command) = cx.local.command_accumulator.put(b as char) {
+                if state.on_console_key() {
+                    continue;
+                }
+                let completer = app::console::ConsoleCompleter {
+                    commands: &console_commands,
+                    params: &state.params,
+                };
+                let command = cx.local.command_accumulator.put_with_completion(b as char, &completer);
+                MULTI_LOGGER.write_console(&cx.local.command_accumulator.take_output());
+                if let Some(command) = command {
                     info!("Command: {:?}", command);
-                    if state.on_console_command(&command, cx.local.hw) {
-                        // Higher level logic handled the command
-                    } else {
-                        info!(
-                            "-> {:?} is an unknown command. Available commands:",
-                            command
-                        );
-                        state.list_console_commands();
-                    }
+                    console_commands.dispatch(state, &command, cx.local.hw);
                 }
             }
 
@@ -1249,7 +1408,8 @@ mod rtic_app {
         shared = [
             usb_dev,
             usb_serial,
-            console_rxbuf
+            console_rxbuf,
+            slcan_rxbuf,
         ],
         local = [
             usb_serial_txbuf: ConstGenericRingBuffer<u8, LOG_BUFFER_SIZE> =
@@ -1262,6 +1422,7 @@ mod rtic_app {
             mut usb_dev,
             mut usb_serial,
             mut console_rxbuf,
+            mut slcan_rxbuf,
         } = cx.shared;
 
         // Fill up usb_serial_txbuf
@@ -1292,15 +1453,20 @@ mod rtic_app {
         });
 
         // Read
-        (&mut usb_dev, &mut usb_serial, &mut console_rxbuf).lock(
-            |usb_dev, usb_serial, console_rxbuf| {
+        (&mut usb_dev, &mut usb_serial, &mut console_rxbuf, &mut slcan_rxbuf).lock(
+            |usb_dev, usb_serial, console_rxbuf, slcan_rxbuf| {
                 if usb_dev.poll(&mut [usb_serial]) {
                     let mut buf = [0u8; 64];
                     match usb_serial.read(&mut buf) {
                         Ok(count) if count > 0 => {
+                            let slcan_mode = SLCAN_MODE.load(Ordering::Relaxed);
                             for i in 0..count {
                                 //cx.local.usb_serial_txbuf.push(buf[i]); // Echo
-                                console_rxbuf.push(buf[i]);
+                                if slcan_mode {
+                                    slcan_rxbuf.push(buf[i]);
+                                } else {
+                                    console_rxbuf.push(buf[i]);
+                                }
                             }
                         }
                         _ => {}
@@ -1365,6 +1531,23 @@ mod rtic_app {
     }
 }
 
+fn can1_supports_bitrate(bitrate: u32) -> bool {
+    slcan::bxcan_bit_timing(CAN_PCLK1_HZ, bitrate).is_some()
+}
+
+// Silent is the listen-only mode of slcan
+fn configure_can1(can1: &mut bxcan::Can<CAN1>, bitrate: u32, silent: bool) {
+    let Some(bit_timing) = slcan::bxcan_bit_timing(CAN_PCLK1_HZ, bitrate) else {
+        warn!("CAN1: {} bps is not supported", bitrate);
+        return;
+    };
+    can1.modify_config()
+        .set_loopback(CAN_ENABLE_LOOPBACK_MODE)
+        .set_silent(silent)
+        .set_bit_timing(bit_timing)
+        .enable();
+}
+
 const PANIC_TEXT_STYLE: mono_font::MonoTextStyle<Rgb565> = mono_font::MonoTextStyleBuilder::new()
     .font(&mono_font::iso_8859_10::FONT_10X20)
     .text_color(Rgb565::WHITE)
@@ -1,32 +1,55 @@
 import sys
 
+# Returns (id, data bytes) or None. Understands candump -L log lines, such as
+# "(12.345000) can0 123#DEADBEEF R" as logged by "log can", and candump's
+# default output with a timestamp, such as "(12.345000) can0 123 [4] DE AD BE EF".
+def parse_line(line):
+    parts = line.split()
+    if len(parts) < 3:
+        return None
+    if '#' in parts[2]:
+        id_text, data_text = parts[2].split('#', 1)
+        if data_text.startswith('R') or data_text.startswith('#'):
+            # Remote and CAN FD frames can't be simulated
+            return None
+        return int(id_text, 16), bytes.fromhex(data_text.replace('.', ''))
+    if len(parts) >= 4 and parts[3].startswith('['):
+        data_length = int(parts[3].strip('[]'))
+        data_bytes = parts[4:4 + data_length]
+        return int(parts[2], 16), bytes.fromhex(''.join(data_bytes))
+    return None
+
 def process_can_frames(file_path, target_ids):
     with open(file_path, 'r') as file:
         lines = file.readlines()
-    
+
     # Keep the original order from command line
     target_ids_list = target_ids.split(',')
     target_ids_int = [int(id, 16) for id in target_ids_list]
-    
+
     found_ids = {}
     for line in lines:
-        parts = line.split()
-        if len(parts) >= 3:
-            current_id = int(parts[2], 16)
-            if current_id in target_ids_int and current_id not in found_ids:
-                data_length = int(parts[3].strip('[]'))
-                data_bytes = parts[-data_length:]
-                hex_bytes = [bytes.fromhex(byte) for byte in data_bytes]
-                combined_bytes = b''.join(hex_bytes)
-                hex_format = ''.join([f'\\x{b:02x}' for b in combined_bytes])
-                
-                found_ids[current_id] = hex_format
+        try:
+            parsed = parse_line(line)
+        except ValueError:
+            continue
+        if parsed is None:
+            continue
+        current_id, combined_bytes = parsed
+        if current_id in target_ids_int and current_id not in found_ids:
+            hex_format = ''.join([f'\\x{b:02x}' for b in combined_bytes])
+
+            found_ids[current_id] = hex_format
 
     for i, can_id in enumerate(target_ids_int):
         if can_id in found_ids:  # Check if the ID was found in the file
+            if can_id > 0x7ff:
+                id_code = f"bxcan::ExtendedId::new(0x{can_id:X}).unwrap()"
+            else:
+                id_code = f"bxcan::StandardId::new(0x{can_id:X}).unwrap()"
             formatted_line = f"""        if self.i % {len(target_ids_int)} == {i} {{
             self.txbuf.push(bxcan::Frame::new_data(
-                bxcan::StandardId::new(0x{can_id:X}).unwrap(),
+                {id_code},
                 bxcan::Data::new(b"{found_ids[can_id]}").unwrap(),
             ));
         }}"""
@@ -36,11 +59,12 @@ def process_can_frames(file_path, target_ids):
 
 if __name__ == "__main__":
     if len(sys.argv) != 3:
-        print("Usage: python script.py  ")
+        print("Usage: python script.py <candump file> <comma separated IDs>")
         print("Example: python script.py can_data.txt 0x1da,0x285")
+        print("The file can be a candump -L log, e.g. a capture of \"log can\"")
         sys.exit(1)
-    
+
     file_path = sys.argv[1]
     target_ids = sys.argv[2]
-    
+
     process_can_frames(file_path, target_ids)